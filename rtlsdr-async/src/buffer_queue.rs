@@ -15,6 +15,7 @@ use std::{
         Poll,
         Waker,
     },
+    time::Instant,
 };
 
 use futures_util::Stream;
@@ -22,8 +23,39 @@ use parking_lot::{
     Condvar,
     Mutex,
 };
+use tokio::sync::Notify;
 
-use crate::SampleType;
+use crate::{
+    Gain,
+    SampleType,
+};
+
+/// What happens when a receiver lags behind and the buffer queue is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Backpressure {
+    /// The oldest buffer is dropped. The receiver will skip ahead and can tell
+    /// from [`Chunk::dropped`][crate::Chunk::dropped] that chunks were lost.
+    #[default]
+    DropOldest,
+
+    /// The producer waits until the receiver has caught up.
+    ///
+    /// For an [`RtlSdr`][crate::RtlSdr] this means that samples will be lost
+    /// in librtlsdr or the device instead, but at least the chunks that are
+    /// received are contiguous. Recordings from files are slowed down
+    /// instead.
+    Block,
+
+    /// The stream yields an [`Error::Lagged`][crate::Error::Lagged] and then
+    /// ends.
+    Error,
+}
+
+/// a receiver with [`Backpressure::Error`] lagged behind by this many buffers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lagged {
+    pub chunks: u64,
+}
 
 #[derive(Clone, derive_more::Debug)]
 pub struct Buffer {
@@ -33,6 +65,28 @@ pub struct Buffer {
     pub end: usize,
     pub sample_rate: u32,
     pub sample_type: SampleType,
+    pub center_frequency: Option<u32>,
+    pub frequency_correction: Option<i32>,
+    pub tuner_gain: Option<Gain>,
+
+    /// position of the buffer in the queue. this is set when the buffer is
+    /// pushed, and increases by one for every buffer.
+    pub sequence: u64,
+
+    /// time at which the samples were captured, i.e. when the read that filled
+    /// the buffer returned.
+    pub timestamp: Instant,
+
+    /// number of samples that were read before this buffer since the stream
+    /// started.
+    pub sample_index: u64,
+
+    /// samples are missing before this buffer, e.g. because the device was
+    /// disconnected.
+    pub discontinuity: bool,
+
+    /// a setting was changed before this buffer.
+    pub config_changed: bool,
 }
 
 impl Buffer {
@@ -44,6 +98,14 @@ impl Buffer {
             end: 0,
             sample_rate: 0,
             sample_type: SampleType::Iq,
+            center_frequency: None,
+            frequency_correction: None,
+            tuner_gain: None,
+            sequence: 0,
+            timestamp: Instant::now(),
+            sample_index: 0,
+            discontinuity: false,
+            config_changed: false,
         }
     }
 
@@ -78,6 +140,13 @@ impl Buffer {
         assert!(start <= end, "slice start > end");
         assert!(end <= self.end, "slice end out of bounds");
 
+        // keep the sample index pointing at the first sample
+        let sample_size = match self.sample_type {
+            SampleType::Iq => 2,
+            SampleType::I | SampleType::Q => 1,
+        };
+        self.sample_index += ((start - self.start) / sample_size) as u64;
+
         self.start = start;
         self.end = end;
     }
@@ -91,6 +160,14 @@ impl Buffer {
     pub fn is_empty(&self) -> bool {
         self.start == self.end
     }
+
+    /// number of samples in the buffer. this depends on the sample type.
+    pub fn num_samples(&self) -> usize {
+        match self.sample_type {
+            SampleType::Iq => self.len() / 2,
+            SampleType::I | SampleType::Q => self.len(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -103,6 +180,27 @@ struct Shared {
     /// receiver count drops to 0. the latter is so the reader
     /// thread can resume, find out nobody is left, and terminate.
     receiver_count_changed: Condvar,
+
+    /// if a receiver with [`Backpressure::Block`] hasn't read the oldest
+    /// buffer, the producer waits for this condition. it's notified when such
+    /// a receiver reads a buffer, or goes away.
+    receiver_progressed: Condvar,
+
+    /// notified whenever one of the conditions above is notified. this is for
+    /// producers that run on an async runtime.
+    changed: Notify,
+}
+
+impl Shared {
+    fn notify_receiver_count_changed(&self) {
+        self.receiver_count_changed.notify_all();
+        self.changed.notify_waiters();
+    }
+
+    fn notify_receiver_progressed(&self) {
+        self.receiver_progressed.notify_all();
+        self.changed.notify_waiters();
+    }
 }
 
 /// This is the central queue that passes buffers from the reader thread
@@ -144,9 +242,39 @@ struct SharedState {
 
     /// receiver IDs to identify wakers with receivers.
     next_receiver_id: usize,
+
+    /// read positions of receivers with [`Backpressure::Block`]
+    blocking_receivers: HashMap<usize, usize>,
+
+    /// backpressure policy for new receivers
+    default_backpressure: Backpressure,
 }
 
 impl SharedState {
+    /// whether a receiver with [`Backpressure::Block`] still needs to read the
+    /// oldest buffer, which would be popped next.
+    fn is_blocked(&self) -> bool {
+        self.slots.len() == self.capacity && self.head_is_unread()
+    }
+
+    /// whether a receiver with [`Backpressure::Block`] hasn't read the oldest
+    /// buffer yet.
+    fn head_is_unread(&self) -> bool {
+        self.blocking_receivers
+            .values()
+            .any(|read_pos| *read_pos <= self.head_pos)
+    }
+
+    fn register_receiver(&mut self, read_pos: usize, backpressure: Backpressure) -> usize {
+        self.num_receivers += 1;
+        let receiver_id = self.next_receiver_id;
+        self.next_receiver_id += 1;
+        if backpressure == Backpressure::Block {
+            self.blocking_receivers.insert(receiver_id, read_pos);
+        }
+        receiver_id
+    }
+
     fn pop_buffer(&mut self) -> Option<Buffer> {
         if self.slots.len() == self.capacity {
             let buffer = self
@@ -161,11 +289,12 @@ impl SharedState {
         }
     }
 
-    fn push_buffer(&mut self, buffer: Buffer) {
+    fn push_buffer(&mut self, mut buffer: Buffer) {
         assert!(
             self.slots.len() < self.capacity,
             "expecting buffer queue to be below capacity when pushing"
         );
+        buffer.sequence = self.tail_pos as u64;
         self.slots.push_back(buffer);
         self.tail_pos += 1;
         for (_, waker) in self.wakers.drain() {
@@ -196,6 +325,9 @@ impl Drop for Subscriber {
         let mut state = self.shared.state.lock();
 
         state.num_subscribers -= 1;
+        if state.num_subscribers == 0 && state.num_receivers == 0 {
+            self.shared.notify_receiver_count_changed();
+        }
     }
 }
 
@@ -203,19 +335,27 @@ impl Subscriber {
     pub fn receiver(&self) -> Receiver {
         let mut state = self.shared.state.lock();
 
-        state.num_receivers += 1;
+        let read_pos = state.tail_pos;
+        let backpressure = state.default_backpressure;
+        let receiver_id = state.register_receiver(read_pos, backpressure);
         if state.num_receivers == 1 {
-            self.shared.receiver_count_changed.notify_one();
+            self.shared.notify_receiver_count_changed();
         }
-        let receiver_id = state.next_receiver_id;
-        state.next_receiver_id += 1;
 
         Receiver {
             shared: self.shared.clone(),
-            read_pos: state.tail_pos,
+            read_pos,
             receiver_id,
+            backpressure,
+            lagged: false,
         }
     }
+
+    /// Sets the backpressure policy for receivers that are created after this.
+    pub fn set_default_backpressure(&self, backpressure: Backpressure) {
+        let mut state = self.shared.state.lock();
+        state.default_backpressure = backpressure;
+    }
 }
 
 #[derive(Debug)]
@@ -223,39 +363,83 @@ pub struct Receiver {
     shared: Arc<Shared>,
     read_pos: usize,
     receiver_id: usize,
+    backpressure: Backpressure,
+    /// the receiver lagged behind with [`Backpressure::Error`] and is done.
+    /// it's then [detached][Self::detach], so the producer doesn't wait or
+    /// read for it anymore.
+    lagged: bool,
+}
+
+impl Receiver {
+    pub fn set_backpressure(&mut self, backpressure: Backpressure) {
+        let mut state = self.shared.state.lock();
+
+        self.backpressure = backpressure;
+        if self.lagged {
+            return;
+        }
+        if backpressure == Backpressure::Block {
+            state
+                .blocking_receivers
+                .insert(self.receiver_id, self.read_pos);
+        }
+        else if state.blocking_receivers.remove(&self.receiver_id).is_some() {
+            self.shared.notify_receiver_progressed();
+        }
+    }
+}
+
+impl Receiver {
+    /// unregisters the receiver, because it was dropped or lagged behind.
+    fn detach(&self, state: &mut SharedState) {
+        state.num_receivers -= 1;
+        state.wakers.remove(&self.receiver_id);
+        if state.blocking_receivers.remove(&self.receiver_id).is_some() {
+            self.shared.notify_receiver_progressed();
+        }
+        if state.num_subscribers == 0 && state.num_receivers == 0 {
+            self.shared.notify_receiver_count_changed();
+        }
+    }
 }
 
 impl Clone for Receiver {
     fn clone(&self) -> Self {
         let mut state = self.shared.state.lock();
 
-        state.num_receivers += 1;
-        if state.num_receivers == 1 {
-            self.shared.receiver_count_changed.notify_all();
+        // a detached receiver stays detached
+        let receiver_id = if self.lagged {
+            self.receiver_id
         }
-        let receiver_id = state.next_receiver_id;
-        state.next_receiver_id += 1;
+        else {
+            let receiver_id = state.register_receiver(self.read_pos, self.backpressure);
+            if state.num_receivers == 1 {
+                self.shared.notify_receiver_count_changed();
+            }
+            receiver_id
+        };
 
         Self {
             shared: self.shared.clone(),
             read_pos: self.read_pos,
             receiver_id,
+            backpressure: self.backpressure,
+            lagged: self.lagged,
         }
     }
 }
 
 impl Drop for Receiver {
     fn drop(&mut self) {
-        let mut state = self.shared.state.lock();
-        state.num_receivers -= 1;
-        if state.num_subscribers == 0 && state.num_receivers == 0 {
-            self.shared.receiver_count_changed.notify_all();
+        if !self.lagged {
+            let mut state = self.shared.state.lock();
+            self.detach(&mut state);
         }
     }
 }
 
 impl Stream for Receiver {
-    type Item = Buffer;
+    type Item = Result<Buffer, Lagged>;
 
     fn poll_next(
         mut self: std::pin::Pin<&mut Self>,
@@ -263,12 +447,25 @@ impl Stream for Receiver {
     ) -> Poll<Option<Self::Item>> {
         let this = self.deref_mut();
 
+        if this.lagged {
+            return Poll::Ready(None);
+        }
+
         let mut state = this.shared.state.lock();
 
         // determine index into the VecDeque
         let queue_index = if this.read_pos < state.head_pos {
-            // we're behind, update our read_pos to the current head
-            tracing::debug!(?this.read_pos, ?state.head_pos, ?state.tail_pos, "lagging behind by {} chunks", state.head_pos - this.read_pos);
+            let chunks = (state.head_pos - this.read_pos) as u64;
+            tracing::debug!(?this.read_pos, ?state.head_pos, ?state.tail_pos, "lagging behind by {chunks} chunks");
+
+            if this.backpressure == Backpressure::Error {
+                this.lagged = true;
+                this.detach(&mut state);
+                return Poll::Ready(Some(Err(Lagged { chunks })));
+            }
+
+            // we're behind, update our read_pos to the current head. the
+            // consumer can tell from the gap in the buffers' sequence numbers.
             this.read_pos = state.head_pos;
             0
         }
@@ -280,7 +477,13 @@ impl Stream for Receiver {
             // there are buffers we can read
             let buffer = state.slots[queue_index].clone();
             this.read_pos += 1;
-            Poll::Ready(Some(buffer))
+
+            if let Some(read_pos) = state.blocking_receivers.get_mut(&this.receiver_id) {
+                *read_pos = this.read_pos;
+                this.shared.notify_receiver_progressed();
+            }
+
+            Poll::Ready(Some(Ok(buffer)))
         }
         else if state.num_senders == 0 {
             // there are no buffers left for us to read, and there are no writers left, so
@@ -307,27 +510,42 @@ impl Drop for Sender {
         let mut state = self.shared.state.lock();
         state.num_senders -= 1;
         if state.num_subscribers == 0 && state.num_receivers == 0 {
-            self.shared.receiver_count_changed.notify_all();
+            self.shared.notify_receiver_count_changed();
         }
     }
 }
 
 impl Sender {
+    /// Returns true if all receivers and subscribers dropped.
+    pub fn is_closed(&self) -> bool {
+        let state = self.shared.state.lock();
+        state.num_subscribers == 0 && state.num_receivers == 0
+    }
+
+    /// Returns true if pushing a buffer with
+    /// [`swap_buffers`][Self::swap_buffers] would wait right now, because
+    /// there are no receivers, or a receiver with [`Backpressure::Block`]
+    /// hasn't read the oldest buffer yet.
+    ///
+    /// Producers that must never wait can drop their data instead.
+    pub fn would_block(&self) -> bool {
+        let state = self.shared.state.lock();
+        state.num_receivers == 0
+            || (state.slots.len() + 1 >= state.capacity && state.head_is_unread())
+    }
+
     /// Returns a buffer to be filled with data. You can also pass in a
     /// buffer that you just filled.
     ///
     /// Returns None if all receivers and subscribers dropped.
     ///
-    /// If there are subscribers, but no receivers this will block until
-    /// there is a receiver.
-    ///
-    /// # TODO
+    /// If `block` is true and there are subscribers, but no receivers this
+    /// will block until there is a receiver. It will also block while a
+    /// receiver with [`Backpressure::Block`] hasn't read the oldest buffer
+    /// yet. If `block` is false, the oldest buffer is dropped instead.
     ///
-    /// Ideally we would like to have 3 methods:
-    ///
-    /// - block until receivers and swap
-    /// - await until receivers and swap
-    /// - just swap
+    /// Producers running on an async runtime should use
+    /// [`swap_buffers_async`][Self::swap_buffers_async] instead of blocking.
     pub fn swap_buffers(
         &mut self,
         push_buffer: Option<Buffer>,
@@ -351,6 +569,12 @@ impl Sender {
             state.push_buffer(buffer);
         }
 
+        // wait for receivers that don't want to lose buffers
+        while block && state.is_blocked() {
+            tracing::debug!("waiting for blocking receivers to catch up");
+            self.shared.receiver_progressed.wait(&mut state);
+        }
+
         // get a free buffer from the queue, or make a new one
         let buffer = state
             .pop_buffer()
@@ -358,6 +582,59 @@ impl Sender {
 
         Some(buffer)
     }
+
+    /// Like [`swap_buffers`][Self::swap_buffers] with `block = true`, but
+    /// waits asynchronously.
+    #[cfg(feature = "tcp")]
+    pub async fn swap_buffers_async(
+        &mut self,
+        push_buffer: Option<Buffer>,
+        buffer_size: usize,
+    ) -> Option<Buffer> {
+        // wait for receivers
+        loop {
+            // this must be created before we check the state, so that we don't
+            // miss a notification in between.
+            let changed = self.shared.changed.notified();
+
+            {
+                let state = self.shared.state.lock();
+                if state.num_receivers > 0 {
+                    break;
+                }
+                if state.num_subscribers == 0 {
+                    return None;
+                }
+            }
+
+            tracing::debug!("waiting for receivers");
+            changed.await;
+        }
+
+        // first push the buffer we filled in the last loop iteration
+        if let Some(buffer) = push_buffer {
+            self.shared.state.lock().push_buffer(buffer);
+        }
+
+        // wait for receivers that don't want to lose buffers
+        loop {
+            let changed = self.shared.changed.notified();
+
+            {
+                let mut state = self.shared.state.lock();
+                if !state.is_blocked() {
+                    // get a free buffer from the queue, or make a new one
+                    let buffer = state
+                        .pop_buffer()
+                        .unwrap_or_else(|| Buffer::new(buffer_size));
+                    return Some(buffer);
+                }
+            }
+
+            tracing::debug!("waiting for blocking receivers to catch up");
+            changed.await;
+        }
+    }
 }
 
 pub fn channel(num_buffers: usize) -> (Sender, Subscriber) {
@@ -374,8 +651,12 @@ pub fn channel(num_buffers: usize) -> (Sender, Subscriber) {
             capacity: num_buffers,
             wakers: HashMap::new(),
             next_receiver_id: 0,
+            blocking_receivers: HashMap::new(),
+            default_backpressure: Backpressure::default(),
         }),
         receiver_count_changed: Condvar::new(),
+        receiver_progressed: Condvar::new(),
+        changed: Notify::new(),
     });
 
     (
@@ -385,3 +666,162 @@ pub fn channel(num_buffers: usize) -> (Sender, Subscriber) {
         Subscriber { shared },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        thread,
+        time::Duration,
+    };
+
+    use futures_util::StreamExt;
+
+    use super::*;
+
+    const BUFFER_SIZE: usize = 16;
+
+    /// pushes `num_buffers` buffers from a synthetic producer
+    fn produce(sender: &mut Sender, num_buffers: usize, block: bool) {
+        let mut buffer = sender.swap_buffers(None, BUFFER_SIZE, block).unwrap();
+        for _ in 0..num_buffers {
+            buffer.end = BUFFER_SIZE;
+            buffer = sender
+                .swap_buffers(Some(buffer), BUFFER_SIZE, block)
+                .unwrap();
+        }
+    }
+
+    async fn sequences(receiver: &mut Receiver, num_buffers: usize) -> Vec<u64> {
+        let mut sequences = vec![];
+        for _ in 0..num_buffers {
+            sequences.push(receiver.next().await.unwrap().unwrap().sequence);
+        }
+        sequences
+    }
+
+    #[test]
+    fn sender_is_closed_when_everyone_dropped() {
+        let (sender, subscriber) = channel(4);
+        let receiver = subscriber.receiver();
+        drop(subscriber);
+        assert!(!sender.is_closed());
+        drop(receiver);
+        assert!(sender.is_closed());
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_skips_ahead() {
+        let (mut sender, subscriber) = channel(4);
+        let mut receiver = subscriber.receiver();
+
+        produce(&mut sender, 10, true);
+
+        // the sender holds on to one buffer, so only 3 are left for us.
+        assert_eq!(sequences(&mut receiver, 3).await, vec![7, 8, 9]);
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_receiver() {
+        let (mut sender, subscriber) = channel(4);
+        let mut receiver = subscriber.receiver();
+        receiver.set_backpressure(Backpressure::Block);
+
+        let producer = thread::spawn(move || {
+            produce(&mut sender, 20, true);
+        });
+
+        // let the producer run into the full queue
+        thread::sleep(Duration::from_millis(50));
+        assert!(!producer.is_finished());
+
+        assert_eq!(
+            sequences(&mut receiver, 20).await,
+            (0..20).collect::<Vec<_>>()
+        );
+        producer.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn block_is_released_when_receiver_is_dropped() {
+        let (mut sender, subscriber) = channel(4);
+        let mut receiver = subscriber.receiver();
+        receiver.set_backpressure(Backpressure::Block);
+
+        // a second receiver keeps the producer from waiting for receivers
+        let _other_receiver = subscriber.receiver();
+
+        let producer = thread::spawn(move || {
+            produce(&mut sender, 20, true);
+        });
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!producer.is_finished());
+
+        drop(receiver);
+        producer.join().unwrap();
+    }
+
+    #[cfg(feature = "tcp")]
+    #[tokio::test]
+    async fn swap_buffers_async_waits_for_receivers() {
+        let (mut sender, subscriber) = channel(4);
+
+        let producer = tokio::spawn(async move {
+            let buffer = sender.swap_buffers_async(None, BUFFER_SIZE).await;
+            assert!(buffer.is_some());
+            sender
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!producer.is_finished());
+
+        let receiver = subscriber.receiver();
+        let mut sender = producer.await.unwrap();
+
+        // once everybody is gone, it returns None
+        drop(receiver);
+        drop(subscriber);
+        assert!(sender.swap_buffers_async(None, BUFFER_SIZE).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn error_ends_lagging_receiver() {
+        let (mut sender, subscriber) = channel(4);
+        let mut receiver = subscriber.receiver();
+        receiver.set_backpressure(Backpressure::Error);
+
+        produce(&mut sender, 2, true);
+        assert_eq!(sequences(&mut receiver, 2).await, vec![0, 1]);
+
+        produce(&mut sender, 10, true);
+        assert_eq!(
+            receiver.next().await.unwrap().unwrap_err(),
+            Lagged { chunks: 7 }
+        );
+        assert!(receiver.next().await.is_none());
+    }
+
+    #[test]
+    fn lagged_receivers_are_detached() {
+        let (mut sender, subscriber) = channel(4);
+        let mut receiver = subscriber.receiver();
+        receiver.set_backpressure(Backpressure::Error);
+        let _clone = receiver.clone();
+
+        produce(&mut sender, 10, true);
+        assert!(matches!(
+            futures_util::FutureExt::now_or_never(receiver.next()),
+            Some(Some(Err(Lagged { .. })))
+        ));
+        assert_eq!(sender.shared.state.lock().num_receivers, 1);
+
+        // neither setting the backpressure, cloning nor dropping it changes
+        // anything now.
+        receiver.set_backpressure(Backpressure::Block);
+        drop(receiver.clone());
+        drop(receiver);
+        let state = sender.shared.state.lock();
+        assert_eq!(state.num_receivers, 1);
+        assert!(state.blocking_receivers.is_empty());
+    }
+}
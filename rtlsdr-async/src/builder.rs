@@ -0,0 +1,264 @@
+use std::time::Duration;
+
+use crate::{
+    Backpressure,
+    Bandwidth,
+    DEFAULT_BUFFER_SIZE,
+    DEFAULT_QUEUE_SIZE,
+    Error,
+    Gain,
+    ReaderMode,
+    RtlSdr,
+    handle::Handle,
+    supervisor::{
+        DEFAULT_POLL_INTERVAL,
+        Supervisor,
+    },
+};
+
+/// librtlsdr requires reads to be a multiple of this.
+const BUFFER_SIZE_MULTIPLE: usize = 512;
+
+/// Builder to configure an [`RtlSdr`] before it's opened.
+///
+/// All settings are applied to the device before the reader thread is started,
+/// so the first samples will already use them.
+#[derive(Clone, Debug)]
+pub struct RtlSdrBuilder {
+    queue_size: usize,
+    buffer_size: usize,
+    center_frequency: Option<u32>,
+    sample_rate: Option<u32>,
+    gain: Option<Gain>,
+    tuner_bandwidth: Option<Bandwidth>,
+    frequency_correction: Option<i32>,
+    bias_tee: Option<(u8, bool)>,
+    backpressure: Backpressure,
+    reconnect: Option<Duration>,
+    reader_mode: ReaderMode,
+}
+
+impl Default for RtlSdrBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RtlSdrBuilder {
+    pub fn new() -> Self {
+        Self {
+            queue_size: DEFAULT_QUEUE_SIZE,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            center_frequency: None,
+            sample_rate: None,
+            gain: None,
+            tuner_bandwidth: None,
+            frequency_correction: None,
+            bias_tee: None,
+            backpressure: Backpressure::default(),
+            reconnect: None,
+            reader_mode: ReaderMode::default(),
+        }
+    }
+
+    /// Number of buffers in the queue between the reader thread and the
+    /// [`Samples`][crate::Samples] streams. If a stream lags behind by more
+    /// than this, chunks are dropped.
+    ///
+    /// Must be at least 2, since the reader thread always holds one buffer.
+    /// Default is 64.
+    pub fn with_queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size;
+        self
+    }
+
+    /// What happens if a [`Samples`][crate::Samples] stream lags behind.
+    ///
+    /// This is the default for all streams of this device. Individual streams
+    /// can choose a different policy with
+    /// [`Samples::set_backpressure`][crate::Samples::set_backpressure].
+    pub fn with_backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// Size of each buffer in bytes.
+    ///
+    /// This must be a multiple of 512, and should really be a multiple of 16
+    /// KiB. Default is 16 KiB.
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    pub fn with_center_frequency(mut self, frequency: u32) -> Self {
+        self.center_frequency = Some(frequency);
+        self
+    }
+
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    pub fn with_gain(mut self, gain: Gain) -> Self {
+        self.gain = Some(gain);
+        self
+    }
+
+    /// Bandwidth of the tuner's IF filter.
+    pub fn with_tuner_bandwidth(mut self, bandwidth: Bandwidth) -> Self {
+        self.tuner_bandwidth = Some(bandwidth);
+        self
+    }
+
+    /// Frequency correction in ppm.
+    pub fn with_frequency_correction(mut self, ppm: i32) -> Self {
+        self.frequency_correction = Some(ppm);
+        self
+    }
+
+    /// Enable the bias-tee on GPIO 0. See
+    /// [`RtlSdr::set_bias_tee`][crate::RtlSdr::set_bias_tee].
+    pub fn with_bias_tee(self, enable: bool) -> Self {
+        self.with_bias_tee_gpio(0, enable)
+    }
+
+    /// Enable the bias-tee on the given GPIO pin. See
+    /// [`RtlSdr::set_bias_tee_gpio`][crate::RtlSdr::set_bias_tee_gpio].
+    pub fn with_bias_tee_gpio(mut self, pin: u8, enable: bool) -> Self {
+        self.bias_tee = Some((pin, enable));
+        self
+    }
+
+    /// How samples are read from the device. See [`ReaderMode`].
+    ///
+    /// Default is [`ReaderMode::Sync`].
+    pub fn with_reader_mode(mut self, reader_mode: ReaderMode) -> Self {
+        self.reader_mode = reader_mode;
+        self
+    }
+
+    /// Reopen the device when it's unplugged and plugged in again.
+    ///
+    /// Normally the [`Samples`][crate::Samples] streams end when the device
+    /// is disconnected. With this enabled, the reader waits for a device with
+    /// the same serial to show up, reopens it and restores all settings. The
+    /// streams continue, and the first chunk after the gap reports it with
+    /// [`Chunk::discontinuity`][crate::Chunk::discontinuity]. The restored
+    /// settings are reported as [config changes][crate::RtlSdr::config_changes]
+    /// again. If the device is present, but repeatedly fails to open or has a
+    /// different tuner, the streams end.
+    ///
+    /// The device is identified by its serial, so opening fails if it has
+    /// none, or if another device has the same serial.
+    pub fn with_reconnect(mut self, enable: bool) -> Self {
+        self.reconnect = enable.then_some(DEFAULT_POLL_INTERVAL);
+        self
+    }
+
+    /// How often to look for the device while it's disconnected. This also
+    /// enables [reconnecting][Self::with_reconnect].
+    ///
+    /// Default is 1 second.
+    pub fn with_reconnect_interval(mut self, interval: Duration) -> Self {
+        self.reconnect = Some(interval);
+        self
+    }
+
+    /// Open the RTL-SDR with the given index and apply the configuration.
+    pub fn open(self, index: u32) -> Result<RtlSdr, Error> {
+        self.validate()?;
+
+        let supervisor = self
+            .reconnect
+            .map(|poll_interval| Supervisor::new(index, poll_interval))
+            .transpose()?;
+
+        let handle = Handle::open(index)?;
+        self.configure(&handle)?;
+
+        let rtl_sdr = RtlSdr::from_handle(
+            handle,
+            self.queue_size,
+            self.buffer_size,
+            self.reader_mode,
+            supervisor,
+        );
+        rtl_sdr
+            .buffer_queue_subscriber
+            .set_default_backpressure(self.backpressure);
+
+        Ok(rtl_sdr)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.buffer_size == 0 || !self.buffer_size.is_multiple_of(BUFFER_SIZE_MULTIPLE) {
+            return Err(Error::InvalidBufferSize {
+                buffer_size: self.buffer_size,
+            });
+        }
+        if self.queue_size < 2 {
+            return Err(Error::InvalidQueueSize {
+                queue_size: self.queue_size,
+            });
+        }
+        Ok(())
+    }
+
+    fn configure(&self, handle: &Handle) -> Result<(), Error> {
+        // the sample rate goes first, since changing it will also change the
+        // tuner bandwidth.
+        if let Some(sample_rate) = self.sample_rate {
+            handle.lock().set_sample_rate(sample_rate)?;
+        }
+        if let Some(bandwidth) = self.tuner_bandwidth {
+            handle.lock().set_tuner_bandwidth(bandwidth.into())?;
+        }
+        if let Some(ppm) = self.frequency_correction {
+            handle.lock().set_frequency_correction(ppm)?;
+        }
+        if let Some(frequency) = self.center_frequency {
+            handle.lock().set_center_frequency(frequency)?;
+        }
+        if let Some(gain) = self.gain {
+            handle.set_tuner_gain(gain)?;
+        }
+        if let Some((pin, enable)) = self.bias_tee {
+            handle.lock().set_bias_tee(pin, enable)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_validates_buffer_size() {
+        for buffer_size in [0, 511, 1000] {
+            assert!(matches!(
+                RtlSdrBuilder::new().with_buffer_size(buffer_size).validate(),
+                Err(Error::InvalidBufferSize { buffer_size: size }) if size == buffer_size
+            ));
+        }
+        for buffer_size in [512, 0x4000, 0x40000] {
+            RtlSdrBuilder::new()
+                .with_buffer_size(buffer_size)
+                .validate()
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn it_validates_queue_size() {
+        for queue_size in [0, 1] {
+            assert!(matches!(
+                RtlSdrBuilder::new().with_queue_size(queue_size).validate(),
+                Err(Error::InvalidQueueSize { queue_size: invalid }) if invalid == queue_size
+            ));
+        }
+        RtlSdrBuilder::new().with_queue_size(2).validate().unwrap();
+    }
+}
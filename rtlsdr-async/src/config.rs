@@ -0,0 +1,146 @@
+use tokio::sync::broadcast;
+
+use crate::{
+    Bandwidth,
+    DirectSamplingMode,
+    Gain,
+};
+
+/// Number of events a [`ConfigChanges`] can lag behind before it misses some.
+pub(crate) const CONFIG_EVENT_QUEUE_SIZE: usize = 32;
+
+/// Snapshot of the configuration of a [`Backend`][crate::Backend].
+///
+/// A field is `None` if the backend doesn't know its value, e.g. because an
+/// [`RtlTcpClient`][crate::rtl_tcp::client::RtlTcpClient] never set it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceConfig {
+    /// Center frequency in Hz
+    pub center_frequency: Option<u32>,
+
+    /// Sample rate in Hz
+    pub sample_rate: Option<u32>,
+
+    pub tuner_gain: Option<Gain>,
+
+    /// IF gains that were set, as `(stage, gain)`.
+    pub tuner_if_gains: Vec<(i16, i16)>,
+
+    pub tuner_bandwidth: Option<Bandwidth>,
+
+    pub agc_mode: Option<bool>,
+
+    /// Frequency correction in ppm
+    pub frequency_correction: Option<i32>,
+
+    pub offset_tuning: Option<bool>,
+
+    /// Frequency of the RTL2832's crystal in Hz
+    pub rtl_xtal: Option<u32>,
+
+    /// Frequency of the tuner's crystal in Hz
+    pub tuner_xtal: Option<u32>,
+
+    pub bias_tee: Option<bool>,
+
+    /// `None` means direct sampling is disabled.
+    pub direct_sampling: Option<DirectSamplingMode>,
+}
+
+impl DeviceConfig {
+    /// Updates the config with a change.
+    pub fn apply(&mut self, event: &ConfigEvent) {
+        match *event {
+            ConfigEvent::CenterFrequency { frequency } => self.center_frequency = Some(frequency),
+            ConfigEvent::SampleRate { sample_rate } => self.sample_rate = Some(sample_rate),
+            ConfigEvent::TunerGain { gain } => self.tuner_gain = Some(gain),
+            ConfigEvent::TunerIfGain { stage, gain } => self.set_tuner_if_gain(stage, gain),
+            ConfigEvent::TunerBandwidth { bandwidth } => self.tuner_bandwidth = Some(bandwidth),
+            ConfigEvent::AgcMode { enable } => self.agc_mode = Some(enable),
+            ConfigEvent::FrequencyCorrection { ppm } => self.frequency_correction = Some(ppm),
+            ConfigEvent::OffsetTuning { enable } => self.offset_tuning = Some(enable),
+            ConfigEvent::RtlXtal { frequency } => self.rtl_xtal = Some(frequency),
+            ConfigEvent::TunerXtal { frequency } => self.tuner_xtal = Some(frequency),
+            ConfigEvent::BiasTee { pin, enable } => {
+                if pin == 0 {
+                    self.bias_tee = Some(enable);
+                }
+            }
+            ConfigEvent::DirectSampling { mode } => self.direct_sampling = mode,
+        }
+    }
+
+    /// Sets the gain of an IF stage, replacing the previous value for it.
+    pub(crate) fn set_tuner_if_gain(&mut self, stage: i16, gain: i16) {
+        if let Some(entry) = self
+            .tuner_if_gains
+            .iter_mut()
+            .find(|(existing, _)| *existing == stage)
+        {
+            entry.1 = gain;
+        }
+        else {
+            self.tuner_if_gains.push((stage, gain));
+        }
+    }
+}
+
+/// A setting that was changed successfully.
+///
+/// See e.g. [`RtlSdr::config_changes`][crate::RtlSdr::config_changes].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigEvent {
+    CenterFrequency { frequency: u32 },
+    SampleRate { sample_rate: u32 },
+    TunerGain { gain: Gain },
+    TunerIfGain { stage: i16, gain: i16 },
+    TunerBandwidth { bandwidth: Bandwidth },
+    AgcMode { enable: bool },
+    FrequencyCorrection { ppm: i32 },
+    OffsetTuning { enable: bool },
+    RtlXtal { frequency: u32 },
+    TunerXtal { frequency: u32 },
+    BiasTee { pin: u8, enable: bool },
+    DirectSampling { mode: Option<DirectSamplingMode> },
+}
+
+/// Receives a [`ConfigEvent`] for every setting that was changed.
+///
+/// Events are buffered, but if the receiver lags behind too much, the oldest
+/// ones are skipped. [`missed`][Self::missed] tells how many.
+#[derive(Debug)]
+pub struct ConfigChanges {
+    receiver: broadcast::Receiver<ConfigEvent>,
+    missed: u64,
+}
+
+impl ConfigChanges {
+    pub(crate) fn new(receiver: broadcast::Receiver<ConfigEvent>) -> Self {
+        Self {
+            receiver,
+            missed: 0,
+        }
+    }
+
+    /// Waits for the next change.
+    ///
+    /// Returns `None` once the device (or connection) is gone.
+    pub async fn recv(&mut self) -> Option<ConfigEvent> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => return Some(event),
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "config change receiver is lagging behind");
+                    self.missed += missed;
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Number of events that were skipped, because the receiver lagged
+    /// behind.
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+}
@@ -13,11 +13,17 @@ use tokio::sync::{
 use tracing::Span;
 
 use crate::{
+    ConfigChanges,
+    ConfigEvent,
+    DeviceConfig,
     DirectSamplingMode,
     Error,
     Gain,
-    TunerGainMode,
     TunerType,
+    eeprom::{
+        EEPROM_SIZE,
+        Eeprom,
+    },
     handle::Handle,
 };
 
@@ -41,6 +47,10 @@ impl Control {
         }
     }
 
+    pub fn config_changes(&self) -> ConfigChanges {
+        ConfigChanges::new(self.handle.subscribe_config_changes())
+    }
+
     pub async fn get_center_frequency(&self) -> Result<u32, Error> {
         let (result_sender, result_receiver) = oneshot::channel();
         self.control_queue_sender
@@ -329,12 +339,12 @@ impl Control {
         self.set_xtal_frequency(None, Some(frequency)).await
     }
 
-    pub async fn set_bias_tee(&self, enable: bool) -> Result<(), Error> {
+    pub async fn set_bias_tee(&self, pin: u8, enable: bool) -> Result<(), Error> {
         let (result_sender, result_receiver) = oneshot::channel();
         self.control_queue_sender
             .send(ControlMessage::SetBiasTee {
                 handle: self.handle.clone(),
-                pin: 0,
+                pin,
                 enable,
                 result_sender,
                 span: Span::current(),
@@ -346,6 +356,51 @@ impl Control {
             .map_err(|_| Error::ControlThreadDead)?
     }
 
+    pub async fn get_config(&self) -> Result<DeviceConfig, Error> {
+        let (result_sender, result_receiver) = oneshot::channel();
+        self.control_queue_sender
+            .send(ControlMessage::GetConfig {
+                handle: self.handle.clone(),
+                result_sender,
+                span: Span::current(),
+            })
+            .await
+            .map_err(|_| Error::ControlThreadDead)?;
+        result_receiver.await.map_err(|_| Error::ControlThreadDead)
+    }
+
+    pub async fn read_eeprom(&self) -> Result<Eeprom, Error> {
+        let (result_sender, result_receiver) = oneshot::channel();
+        self.control_queue_sender
+            .send(ControlMessage::ReadEeprom {
+                handle: self.handle.clone(),
+                result_sender,
+                span: Span::current(),
+            })
+            .await
+            .map_err(|_| Error::ControlThreadDead)?;
+        result_receiver
+            .await
+            .map_err(|_| Error::ControlThreadDead)?
+    }
+
+    pub async fn write_eeprom(&self, data: Vec<u8>, offset: u8) -> Result<(), Error> {
+        let (result_sender, result_receiver) = oneshot::channel();
+        self.control_queue_sender
+            .send(ControlMessage::WriteEeprom {
+                handle: self.handle.clone(),
+                data,
+                offset,
+                result_sender,
+                span: Span::current(),
+            })
+            .await
+            .map_err(|_| Error::ControlThreadDead)?;
+        result_receiver
+            .await
+            .map_err(|_| Error::ControlThreadDead)?
+    }
+
     pub(crate) async fn set_direct_sampling(
         &self,
         mode: Option<DirectSamplingMode>,
@@ -366,63 +421,39 @@ impl Control {
     }
 }
 
-/// a call to rtlsdr_set_center_freq takes about 50ms. that's too long for async
-/// - especially since we're shoving millions of samples per second at the same
-/// time.
+/// a call to rtlsdr_set_center_freq takes about 50ms. that's too long for
+/// async, especially since we're shoving millions of samples per second at the
+/// same time.
 ///
 /// therefore we use a separate thread to run all the slow control commands.
 /// we'll use one thread for all RtlSdr objects though.
 fn control_thread(mut control_queue_receiver: mpsc::Receiver<ControlMessage>) {
-    fn set_tuner_gain(handle: &Handle, gain: Gain) -> Result<(), Error> {
-        match gain {
-            Gain::ManualValue(gain) => {
-                // we need to find a supported gain value
-                let gain = handle
-                    .tuner_gains
-                    .iter()
-                    .min_by_key(|supported| (**supported - gain).abs())
-                    .ok_or(Error::NoSupportedGains)?;
-
-                let mut handle = handle.lock();
-
-                // manual gain mode must be enabled
-                handle.set_tuner_gain_mode(TunerGainMode::Manual)?;
-
-                handle.set_tuner_gain(*gain)
-            }
-            Gain::ManualIndex(index) => {
-                // we need to find a supported gain value
-                let gain = handle
-                    .tuner_gains
-                    .as_ref()
-                    .get(index)
-                    .ok_or(Error::InvalidGainIndex { index })?;
-
-                let mut handle = handle.lock();
-
-                // manual gain mode must be enabled
-                handle.set_tuner_gain_mode(TunerGainMode::Manual)?;
-
-                handle.set_tuner_gain(*gain)
-            }
-            Gain::Auto => {
-                let mut handle = handle.lock();
-                handle.set_tuner_gain_mode(TunerGainMode::Auto)
-            }
-        }
-    }
-
     fn set_xtal_frequency(
         handle: &Handle,
         rtl_xtal_frequency: Option<u32>,
         tuner_xtal_frequency: Option<u32>,
     ) -> Result<(), Error> {
-        let mut handle = handle.lock();
-        let current = handle.get_xtal_frequency()?;
-        handle.set_xtal_frequency(
+        let mut locked = handle.lock();
+        let current = locked.get_xtal_frequency()?;
+        locked.set_xtal_frequency(
             rtl_xtal_frequency.unwrap_or(current.0),
             tuner_xtal_frequency.unwrap_or(current.1),
-        )
+        )?;
+
+        if let Some(frequency) = rtl_xtal_frequency {
+            handle.notify(ConfigEvent::RtlXtal { frequency });
+        }
+        if let Some(frequency) = tuner_xtal_frequency {
+            handle.notify(ConfigEvent::TunerXtal { frequency });
+        }
+
+        Ok(())
+    }
+
+    fn read_eeprom(handle: &Handle) -> Result<Eeprom, Error> {
+        let mut data = [0; EEPROM_SIZE];
+        handle.lock().read_eeprom(&mut data, 0)?;
+        Ok(Eeprom::from_bytes(&data).expect("EEPROM image has the right size"))
     }
 
     while let Some(command) = control_queue_receiver.blocking_recv() {
@@ -444,8 +475,11 @@ fn control_thread(mut control_queue_receiver: mpsc::Receiver<ControlMessage>) {
                 span,
             } => {
                 let _guard = span.enter();
-                let mut handle = handle.lock();
-                let result = handle.set_center_frequency(frequency);
+                let mut locked = handle.lock();
+                let result = locked.set_center_frequency(frequency);
+                if result.is_ok() {
+                    handle.notify(ConfigEvent::CenterFrequency { frequency });
+                }
                 let _ = result_sender.send(result);
             }
             ControlMessage::GetSampleRate {
@@ -465,8 +499,11 @@ fn control_thread(mut control_queue_receiver: mpsc::Receiver<ControlMessage>) {
                 span,
             } => {
                 let _guard = span.enter();
-                let mut handle = handle.lock();
-                let result = handle.set_sample_rate(sample_rate);
+                let mut locked = handle.lock();
+                let result = locked.set_sample_rate(sample_rate);
+                if result.is_ok() {
+                    handle.notify(ConfigEvent::SampleRate { sample_rate });
+                }
                 let _ = result_sender.send(result);
             }
             ControlMessage::GetTunerGain {
@@ -486,7 +523,7 @@ fn control_thread(mut control_queue_receiver: mpsc::Receiver<ControlMessage>) {
                 span,
             } => {
                 let _guard = span.enter();
-                let result = set_tuner_gain(&handle, gain);
+                let result = handle.set_tuner_gain(gain);
                 let _ = result_sender.send(result);
             }
             ControlMessage::SetTunerIfGain {
@@ -497,8 +534,13 @@ fn control_thread(mut control_queue_receiver: mpsc::Receiver<ControlMessage>) {
                 span,
             } => {
                 let _guard = span.enter();
-                let mut handle = handle.lock();
-                let result = handle.set_tuner_if_gain(stage, gain);
+                let mut locked = handle.lock();
+                let result = locked.set_tuner_if_gain(stage, gain);
+                if result.is_ok()
+                    && let (Ok(stage), Ok(gain)) = (stage.try_into(), gain.try_into())
+                {
+                    handle.notify(ConfigEvent::TunerIfGain { stage, gain });
+                }
                 let _ = result_sender.send(result);
             }
             ControlMessage::SetTunerBandwidth {
@@ -508,8 +550,13 @@ fn control_thread(mut control_queue_receiver: mpsc::Receiver<ControlMessage>) {
                 span,
             } => {
                 let _guard = span.enter();
-                let mut handle = handle.lock();
-                let result = handle.set_tuner_bandwidth(bandwidth);
+                let mut locked = handle.lock();
+                let result = locked.set_tuner_bandwidth(bandwidth);
+                if result.is_ok() {
+                    handle.notify(ConfigEvent::TunerBandwidth {
+                        bandwidth: bandwidth.into(),
+                    });
+                }
                 let _ = result_sender.send(result);
             }
             ControlMessage::SetAgcMode {
@@ -519,8 +566,11 @@ fn control_thread(mut control_queue_receiver: mpsc::Receiver<ControlMessage>) {
                 span,
             } => {
                 let _guard = span.enter();
-                let mut handle = handle.lock();
-                let result = handle.set_agc_mode(enable);
+                let mut locked = handle.lock();
+                let result = locked.set_agc_mode(enable);
+                if result.is_ok() {
+                    handle.notify(ConfigEvent::AgcMode { enable });
+                }
                 let _ = result_sender.send(result);
             }
             ControlMessage::GetFrequencyCorrection {
@@ -540,8 +590,11 @@ fn control_thread(mut control_queue_receiver: mpsc::Receiver<ControlMessage>) {
                 span,
             } => {
                 let _guard = span.enter();
-                let mut handle = handle.lock();
-                let result = handle.set_frequency_correction(ppm);
+                let mut locked = handle.lock();
+                let result = locked.set_frequency_correction(ppm);
+                if result.is_ok() {
+                    handle.notify(ConfigEvent::FrequencyCorrection { ppm });
+                }
                 let _ = result_sender.send(result);
             }
             ControlMessage::GetOffsetTuning {
@@ -561,8 +614,11 @@ fn control_thread(mut control_queue_receiver: mpsc::Receiver<ControlMessage>) {
                 span,
             } => {
                 let _guard = span.enter();
-                let mut handle = handle.lock();
-                let result = handle.set_offset_tuning(enable);
+                let mut locked = handle.lock();
+                let result = locked.set_offset_tuning(enable);
+                if result.is_ok() {
+                    handle.notify(ConfigEvent::OffsetTuning { enable });
+                }
                 let _ = result_sender.send(result);
             }
             ControlMessage::GetXtalFrequency {
@@ -594,8 +650,11 @@ fn control_thread(mut control_queue_receiver: mpsc::Receiver<ControlMessage>) {
                 span,
             } => {
                 let _guard = span.enter();
-                let mut handle = handle.lock();
-                let result = handle.set_bias_tee(pin, enable);
+                let mut locked = handle.lock();
+                let result = locked.set_bias_tee(pin, enable);
+                if result.is_ok() {
+                    handle.notify(ConfigEvent::BiasTee { pin, enable });
+                }
                 let _ = result_sender.send(result);
             }
             ControlMessage::SetDirectSampling {
@@ -603,10 +662,43 @@ fn control_thread(mut control_queue_receiver: mpsc::Receiver<ControlMessage>) {
                 mode,
                 result_sender,
                 span,
+            } => {
+                let _guard = span.enter();
+                let mut locked = handle.lock();
+                let result = locked.set_direct_sampling(mode);
+                if let Ok(true) = result {
+                    handle.notify(ConfigEvent::DirectSampling { mode });
+                }
+                let _ = result_sender.send(result.map(|_| ()));
+            }
+            ControlMessage::GetConfig {
+                handle,
+                result_sender,
+                span,
             } => {
                 let _guard = span.enter();
                 let mut handle = handle.lock();
-                let result = handle.set_direct_sampling(mode);
+                let _ = result_sender.send(handle.config());
+            }
+            ControlMessage::ReadEeprom {
+                handle,
+                result_sender,
+                span,
+            } => {
+                let _guard = span.enter();
+                let result = read_eeprom(&handle);
+                let _ = result_sender.send(result);
+            }
+            ControlMessage::WriteEeprom {
+                handle,
+                data,
+                offset,
+                result_sender,
+                span,
+            } => {
+                let _guard = span.enter();
+                let mut handle = handle.lock();
+                let result = handle.write_eeprom(&data, offset);
                 let _ = result_sender.send(result);
             }
         }
@@ -735,4 +827,21 @@ enum ControlMessage {
         result_sender: oneshot::Sender<Result<(), Error>>,
         span: Span,
     },
+    GetConfig {
+        handle: Arc<Handle>,
+        result_sender: oneshot::Sender<DeviceConfig>,
+        span: Span,
+    },
+    ReadEeprom {
+        handle: Arc<Handle>,
+        result_sender: oneshot::Sender<Result<Eeprom, Error>>,
+        span: Span,
+    },
+    WriteEeprom {
+        handle: Arc<Handle>,
+        data: Vec<u8>,
+        offset: u8,
+        result_sender: oneshot::Sender<Result<(), Error>>,
+        span: Span,
+    },
 }
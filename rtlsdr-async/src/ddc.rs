@@ -0,0 +1,308 @@
+//! Digital down-conversion.
+//!
+//! A [`Ddc`] selects a narrower channel from a stream of IQ samples: It
+//! shifts the channel to 0 Hz, low-pass filters it, and decimates the result
+//! to the channel's sample rate. This is used to give every client of an
+//! `rtl_tcp` server its own
+//! [virtual receiver][crate::rtl_tcp::virtual_receiver].
+
+use std::f64::consts::{
+    PI,
+    TAU,
+};
+
+use crate::{
+    Iq,
+    u8_to_f32,
+};
+
+/// Number of filter taps per decimation step.
+const TAPS_PER_STEP: u32 = 16;
+
+/// Upper limit for the number of filter taps.
+const MAX_TAPS: u32 = 511;
+
+/// Digital down-converter.
+#[derive(Clone, Debug)]
+pub struct Ddc {
+    sample_rate: u32,
+    offset: i32,
+    decimation: u32,
+
+    /// the oscillator as `(cos, sin)` of its phase
+    oscillator: (f64, f64),
+
+    /// rotation of the oscillator per sample
+    step: (f64, f64),
+
+    taps: Vec<f32>,
+
+    /// the last `taps.len()` mixed samples, as ring buffer
+    history: Vec<(f32, f32)>,
+    position: usize,
+
+    /// number of samples until the next output sample
+    skip: u32,
+}
+
+impl Ddc {
+    /// Creates a down-converter for an input stream with `sample_rate`.
+    ///
+    /// The channel is centered `offset` Hz from the center of the input, and
+    /// the output has a sample rate of `sample_rate / decimation`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `decimation` is 0.
+    pub fn new(sample_rate: u32, offset: i32, decimation: u32) -> Self {
+        let mut ddc = Self {
+            sample_rate,
+            offset: 0,
+            decimation: 1,
+            oscillator: (1.0, 0.0),
+            step: (1.0, 0.0),
+            taps: vec![],
+            history: vec![],
+            position: 0,
+            skip: 0,
+        };
+        ddc.set_offset(offset);
+        ddc.set_decimation(decimation);
+        ddc
+    }
+
+    /// Sample rate of the input in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Offset of the channel from the center of the input in Hz.
+    pub fn offset(&self) -> i32 {
+        self.offset
+    }
+
+    pub fn decimation(&self) -> u32 {
+        self.decimation
+    }
+
+    /// Sample rate of the output in Hz.
+    pub fn output_sample_rate(&self) -> u32 {
+        self.sample_rate / self.decimation
+    }
+
+    /// Moves the channel.
+    ///
+    /// The phase of the oscillator is kept, so this doesn't cause a
+    /// discontinuity.
+    pub fn set_offset(&mut self, offset: i32) {
+        self.offset = offset;
+        let (sin, cos) = (-TAU * f64::from(offset) / f64::from(self.sample_rate)).sin_cos();
+        self.step = (cos, sin);
+    }
+
+    /// Changes the decimation, which also changes the filter.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `decimation` is 0.
+    pub fn set_decimation(&mut self, decimation: u32) {
+        assert!(decimation > 0, "decimation must not be 0");
+
+        self.decimation = decimation;
+        let num_taps = TAPS_PER_STEP
+            .saturating_mul(decimation)
+            .saturating_add(1)
+            .min(MAX_TAPS);
+        self.taps = lowpass(0.5 / f64::from(decimation), num_taps as usize);
+        self.history = vec![(0.0, 0.0); self.taps.len()];
+        self.position = 0;
+        self.skip = 0;
+    }
+
+    /// Down-converts `input` and appends the output samples to `output`.
+    ///
+    /// The filter state is kept between calls, so `input` can be split up
+    /// arbitrarily.
+    pub fn process(&mut self, input: &[Iq], output: &mut Vec<Iq>) {
+        if self.decimation == 1 && self.offset == 0 {
+            // the filter would only pass everything through
+            output.extend_from_slice(input);
+            return;
+        }
+
+        output.reserve(input.len() / self.decimation as usize + 1);
+
+        for sample in input {
+            let (cos, sin) = (self.oscillator.0 as f32, self.oscillator.1 as f32);
+            let i = u8_to_f32(sample.i);
+            let q = u8_to_f32(sample.q);
+            self.history[self.position] = (i * cos - q * sin, i * sin + q * cos);
+            self.position = (self.position + 1) % self.history.len();
+
+            let (cos, sin) = self.oscillator;
+            self.oscillator = (
+                cos * self.step.0 - sin * self.step.1,
+                cos * self.step.1 + sin * self.step.0,
+            );
+
+            if self.skip == 0 {
+                output.push(self.filter());
+                self.skip = self.decimation;
+            }
+            self.skip -= 1;
+        }
+
+        // rounding errors would slowly change the amplitude of the
+        // oscillator.
+        let amplitude = self.oscillator.0.hypot(self.oscillator.1);
+        self.oscillator = (self.oscillator.0 / amplitude, self.oscillator.1 / amplitude);
+    }
+
+    /// computes the filter output for the current history.
+    fn filter(&self) -> Iq {
+        // `position` points to the oldest sample, so the taps are applied
+        // from oldest to newest. the filter is symmetric, so the order
+        // doesn't matter otherwise.
+        let (newer, older) = self.history.split_at(self.position);
+        let (i, q) = older
+            .iter()
+            .chain(newer)
+            .zip(&self.taps)
+            .fold((0.0, 0.0), |(i, q), (sample, tap)| {
+                (i + sample.0 * tap, q + sample.1 * tap)
+            });
+        Iq {
+            i: f32_to_u8(i),
+            q: f32_to_u8(q),
+        }
+    }
+}
+
+/// Designs a windowed-sinc low-pass filter with unity gain at 0 Hz.
+///
+/// `cutoff` is relative to the sample rate.
+fn lowpass(cutoff: f64, num_taps: usize) -> Vec<f32> {
+    let middle = (num_taps - 1) as f64 / 2.0;
+
+    let taps = (0..num_taps)
+        .map(|n| {
+            let x = n as f64 - middle;
+            let sinc = if x == 0.0 {
+                2.0 * cutoff
+            }
+            else {
+                (TAU * cutoff * x).sin() / (PI * x)
+            };
+            // blackman window
+            let window = if num_taps > 1 {
+                let phase = TAU * n as f64 / (num_taps - 1) as f64;
+                0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos()
+            }
+            else {
+                1.0
+            };
+            sinc * window
+        })
+        .collect::<Vec<f64>>();
+
+    let sum = taps.iter().sum::<f64>();
+    taps.into_iter().map(|tap| (tap / sum) as f32).collect()
+}
+
+#[inline]
+fn f32_to_u8(x: f32) -> u8 {
+    // inverse of u8_to_f32
+    ((x + 1.0) * 127.5).round().clamp(0.0, 255.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 2_048_000;
+
+    fn tone(frequency: f64, amplitude: f64, num_samples: usize) -> Vec<Iq> {
+        (0..num_samples)
+            .map(|n| {
+                let (sin, cos) = (TAU * frequency * n as f64 / f64::from(SAMPLE_RATE)).sin_cos();
+                Iq {
+                    i: f32_to_u8((amplitude * cos) as f32),
+                    q: f32_to_u8((amplitude * sin) as f32),
+                }
+            })
+            .collect()
+    }
+
+    /// returns the power at `frequency` relative to the center frequency
+    fn power_at(samples: &[Iq], frequency: f64, sample_rate: u32) -> f64 {
+        let (mut re, mut im) = (0.0, 0.0);
+        for (n, sample) in samples.iter().enumerate() {
+            let i = f64::from(u8_to_f32(sample.i));
+            let q = f64::from(u8_to_f32(sample.q));
+            let (sin, cos) = (-TAU * frequency * n as f64 / f64::from(sample_rate)).sin_cos();
+            re += i * cos - q * sin;
+            im += i * sin + q * cos;
+        }
+        let n = samples.len() as f64;
+        (re / n).powi(2) + (im / n).powi(2)
+    }
+
+    #[test]
+    fn it_passes_through_without_offset_and_decimation() {
+        let input = tone(100_000.0, 0.5, 1024);
+        let mut output = vec![];
+        Ddc::new(SAMPLE_RATE, 0, 1).process(&input, &mut output);
+        assert_eq!(output.len(), input.len());
+        assert!(power_at(&output, 100_000.0, SAMPLE_RATE) > 0.2);
+    }
+
+    #[test]
+    fn it_shifts_the_channel_to_the_center() {
+        let input = tone(300_000.0, 0.5, 0x4000);
+        let mut ddc = Ddc::new(SAMPLE_RATE, 300_000, 8);
+        let mut output = vec![];
+        ddc.process(&input, &mut output);
+
+        assert_eq!(ddc.output_sample_rate(), 256_000);
+        assert_eq!(output.len(), 0x4000 / 8);
+        // skip the filter's transient
+        assert!(power_at(&output[64..], 0.0, 256_000) > 0.2);
+    }
+
+    #[test]
+    fn it_filters_out_other_channels() {
+        let input = tone(-400_000.0, 0.5, 0x4000);
+        let mut ddc = Ddc::new(SAMPLE_RATE, 300_000, 8);
+        let mut output = vec![];
+        ddc.process(&input, &mut output);
+
+        // the tone is 700 kHz away, so it would alias without filtering.
+        assert!(
+            output[64..]
+                .iter()
+                .all(|sample| { sample.i.abs_diff(128) <= 1 && sample.q.abs_diff(128) <= 1 })
+        );
+    }
+
+    #[test]
+    fn input_can_be_split_up() {
+        let input = tone(250_000.0, 0.5, 1000);
+        let mut ddc = Ddc::new(SAMPLE_RATE, 200_000, 4);
+        let mut expected = vec![];
+        ddc.clone().process(&input, &mut expected);
+
+        let mut output = vec![];
+        for chunk in input.chunks(7) {
+            ddc.process(chunk, &mut output);
+        }
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn it_handles_large_decimations() {
+        let mut ddc = Ddc::new(SAMPLE_RATE, 0, u32::MAX);
+        let mut output = vec![];
+        ddc.process(&tone(0.0, 0.5, 1000), &mut output);
+        assert_eq!(output.len(), 1);
+    }
+}
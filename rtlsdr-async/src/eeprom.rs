@@ -0,0 +1,421 @@
+//! Reading and writing the EEPROM of an RTL2832.
+//!
+//! The EEPROM holds the USB vendor and product IDs, and the manufacturer,
+//! product and serial strings the dongle reports. The layout is the same as
+//! the one used by `rtl_eeprom`:
+//!
+//! | offset | content                                          |
+//! |--------|--------------------------------------------------|
+//! | 0      | `0x28 0x32` header                               |
+//! | 2      | vendor ID (little endian)                        |
+//! | 4      | product ID (little endian)                       |
+//! | 6      | `0xa5` if the serial should be reported          |
+//! | 7      | flags: bit 0 remote wakeup, bit 1 IR enable      |
+//! | 9      | manufacturer, product and serial as USB string   |
+//! |        | descriptors, up to offset 78                     |
+//! | 78     | IR configuration                                 |
+//!
+//! Writing to the EEPROM can brick a dongle (although it can usually be
+//! recovered), so [`RtlSdr::write_eeprom`][crate::RtlSdr::write_eeprom] backs
+//! up the old contents unless told otherwise, and has a dry-run mode.
+
+use std::{
+    fs::OpenOptions,
+    io::{
+        ErrorKind,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    thread,
+};
+
+use tokio::sync::oneshot;
+
+/// Size of the EEPROM in bytes.
+pub const EEPROM_SIZE: usize = 256;
+
+const HEADER: [u8; 2] = [0x28, 0x32];
+const HAVE_SERIAL: u8 = 0xa5;
+const FLAG_REMOTE_WAKEUP: u8 = 0x01;
+const FLAG_ENABLE_IR: u8 = 0x02;
+const STRINGS_START: usize = 0x09;
+const STRINGS_END: usize = 78;
+const STRING_DESCRIPTOR_TYPE: u8 = 0x03;
+
+/// Errors returned when reading or writing the EEPROM
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("device error")]
+    Device(#[from] crate::Error),
+    #[error("EEPROM image must be {EEPROM_SIZE} bytes, but is {len} bytes")]
+    InvalidSize { len: usize },
+    #[error("invalid EEPROM header: {header:02x?}")]
+    InvalidHeader { header: [u8; 2] },
+    #[error("invalid string descriptor at offset {offset}")]
+    InvalidStringDescriptor { offset: usize },
+    #[error("strings need {len} bytes, but only {} bytes are available", STRINGS_END - STRINGS_START)]
+    StringsTooLong { len: usize },
+    #[error("EEPROM contents differ after writing at offset {offset}")]
+    VerifyFailed { offset: usize },
+    #[error("backup file already exists: {}", path.display())]
+    BackupExists { path: PathBuf },
+}
+
+/// The settings stored in the EEPROM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EepromConfig {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub manufacturer: String,
+    pub product: String,
+
+    /// The serial is only reported if this is `Some`.
+    pub serial: Option<String>,
+
+    pub enable_ir: bool,
+    pub remote_wakeup: bool,
+}
+
+/// Contents of the EEPROM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Eeprom {
+    data: [u8; EEPROM_SIZE],
+}
+
+impl Eeprom {
+    /// Creates an image with the given config, and defaults for everything
+    /// else, like `rtl_eeprom` does.
+    pub fn new(config: &EepromConfig) -> Result<Self, Error> {
+        let mut data = [0; EEPROM_SIZE];
+        data[7] = 0x14;
+        data[8] = 0x02;
+        let mut eeprom = Self { data };
+        eeprom.set_config(config)?;
+        Ok(eeprom)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        let data = data
+            .try_into()
+            .map_err(|_| Error::InvalidSize { len: data.len() })?;
+        Ok(Self { data })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Reads an image from a file, e.g. a backup.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    /// Writes the image to a file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        std::fs::write(path, self.data)?;
+        Ok(())
+    }
+
+    /// Writes the image to a new file.
+    ///
+    /// Unlike [`save`][Self::save] this fails if the file already exists, so
+    /// that an earlier backup is never overwritten.
+    pub fn save_backup(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|error| {
+                if error.kind() == ErrorKind::AlreadyExists {
+                    Error::BackupExists {
+                        path: path.to_owned(),
+                    }
+                }
+                else {
+                    error.into()
+                }
+            })?;
+        file.write_all(&self.data)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Parses the settings.
+    pub fn config(&self) -> Result<EepromConfig, Error> {
+        let header = [self.data[0], self.data[1]];
+        if header != HEADER {
+            return Err(Error::InvalidHeader { header });
+        }
+
+        let (manufacturer, offset) = self.read_string(STRINGS_START)?;
+        let (product, offset) = self.read_string(offset)?;
+        let (serial, _) = self.read_string(offset)?;
+
+        Ok(EepromConfig {
+            vendor_id: u16::from_le_bytes([self.data[2], self.data[3]]),
+            product_id: u16::from_le_bytes([self.data[4], self.data[5]]),
+            manufacturer,
+            product,
+            serial: (self.data[6] == HAVE_SERIAL).then_some(serial),
+            enable_ir: self.data[7] & FLAG_ENABLE_IR != 0,
+            remote_wakeup: self.data[7] & FLAG_REMOTE_WAKEUP != 0,
+        })
+    }
+
+    /// Changes the settings.
+    ///
+    /// Everything that isn't part of [`EepromConfig`] is left unchanged, e.g.
+    /// the IR configuration.
+    pub fn set_config(&mut self, config: &EepromConfig) -> Result<(), Error> {
+        let strings = [
+            &*config.manufacturer,
+            &*config.product,
+            config.serial.as_deref().unwrap_or_default(),
+        ]
+        .into_iter()
+        .flat_map(string_descriptor)
+        .collect::<Vec<u8>>();
+        if STRINGS_START + strings.len() > STRINGS_END {
+            return Err(Error::StringsTooLong { len: strings.len() });
+        }
+
+        self.data[0..2].copy_from_slice(&HEADER);
+        self.data[2..4].copy_from_slice(&config.vendor_id.to_le_bytes());
+        self.data[4..6].copy_from_slice(&config.product_id.to_le_bytes());
+        self.data[6] = if config.serial.is_some() {
+            HAVE_SERIAL
+        }
+        else {
+            0
+        };
+
+        let mut flags = self.data[7] & !(FLAG_ENABLE_IR | FLAG_REMOTE_WAKEUP);
+        if config.enable_ir {
+            flags |= FLAG_ENABLE_IR;
+        }
+        if config.remote_wakeup {
+            flags |= FLAG_REMOTE_WAKEUP;
+        }
+        self.data[7] = flags;
+
+        self.data[STRINGS_START..STRINGS_START + strings.len()].copy_from_slice(&strings);
+
+        Ok(())
+    }
+
+    /// Range of bytes that differ from `other`, or `None` if they're equal.
+    pub(crate) fn changed_range(&self, other: &Eeprom) -> Option<(usize, usize)> {
+        let differs = |i: &usize| self.data[*i] != other.data[*i];
+        let start = (0..EEPROM_SIZE).find(differs)?;
+        let end = (0..EEPROM_SIZE).rfind(differs)?;
+        Some((start, end + 1))
+    }
+
+    /// reads the string descriptor at `offset`. returns the string and the
+    /// offset of the next descriptor.
+    fn read_string(&self, offset: usize) -> Result<(String, usize), Error> {
+        let invalid = || Error::InvalidStringDescriptor { offset };
+
+        let len = usize::from(self.data[offset]);
+        if len < 2
+            || !len.is_multiple_of(2)
+            || offset + len > STRINGS_END
+            || self.data[offset + 1] != STRING_DESCRIPTOR_TYPE
+        {
+            return Err(invalid());
+        }
+
+        let units = self.data[offset + 2..offset + len]
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .collect::<Vec<u16>>();
+        let string = String::from_utf16(&units).map_err(|_| invalid())?;
+
+        Ok((string, offset + len))
+    }
+}
+
+/// encodes a string as USB string descriptor.
+fn string_descriptor(string: &str) -> Vec<u8> {
+    let mut descriptor = vec![0, STRING_DESCRIPTOR_TYPE];
+    descriptor.extend(string.encode_utf16().flat_map(u16::to_le_bytes));
+    // if this doesn't fit, the strings are too long anyway. set_config will catch
+    // that.
+    descriptor[0] = descriptor.len().try_into().unwrap_or(u8::MAX);
+    descriptor
+}
+
+/// [`Eeprom::save_backup`] on a separate thread, since file I/O is blocking.
+pub(crate) async fn save_backup(eeprom: Eeprom, path: PathBuf) -> Result<(), Error> {
+    let (result_sender, result_receiver) = oneshot::channel();
+    thread::spawn(move || {
+        let _ = result_sender.send(eeprom.save_backup(&path));
+    });
+    result_receiver
+        .await
+        .map_err(|_| std::io::Error::other("backup thread died"))?
+}
+
+/// Options for [`RtlSdr::write_eeprom`][crate::RtlSdr::write_eeprom].
+///
+/// A backup is made by default. Use [`without_backup`][Self::without_backup]
+/// to explicitly skip it.
+#[derive(Clone, Debug)]
+pub struct WriteOptions {
+    pub(crate) backup: Option<PathBuf>,
+    pub(crate) dry_run: bool,
+}
+
+impl WriteOptions {
+    /// Saves the current contents of the EEPROM to `backup` before writing.
+    ///
+    /// The backup file must not exist yet.
+    pub fn new(backup: impl Into<PathBuf>) -> Self {
+        Self {
+            backup: Some(backup.into()),
+            dry_run: false,
+        }
+    }
+
+    /// Writes without making a backup first.
+    pub fn without_backup() -> Self {
+        Self {
+            backup: None,
+            dry_run: false,
+        }
+    }
+
+    /// Don't actually write anything. The backup is still made.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// EEPROM of a generic dongle, with IR enabled.
+    #[rustfmt::skip]
+    const IMAGE: [u8; 80] = [
+        0x28, 0x32, 0xda, 0x0b, 0x38, 0x28, 0xa5, 0x16, 0x02, 0x10, 0x03, 0x52, 0x00, 0x65, 0x00, 0x61,
+        0x00, 0x6c, 0x00, 0x74, 0x00, 0x65, 0x00, 0x6b, 0x00, 0x1c, 0x03, 0x52, 0x00, 0x54, 0x00, 0x4c,
+        0x00, 0x32, 0x00, 0x38, 0x00, 0x33, 0x00, 0x38, 0x00, 0x55, 0x00, 0x48, 0x00, 0x49, 0x00, 0x44,
+        0x00, 0x49, 0x00, 0x52, 0x00, 0x12, 0x03, 0x30, 0x00, 0x30, 0x00, 0x30, 0x00, 0x30, 0x00, 0x30,
+        0x00, 0x30, 0x00, 0x30, 0x00, 0x31, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff,
+    ];
+
+    fn canned_image() -> Eeprom {
+        let mut data = [0xff; EEPROM_SIZE];
+        data[..IMAGE.len()].copy_from_slice(&IMAGE);
+        Eeprom::from_bytes(&data).unwrap()
+    }
+
+    fn canned_config() -> EepromConfig {
+        EepromConfig {
+            vendor_id: 0x0bda,
+            product_id: 0x2838,
+            manufacturer: "Realtek".to_owned(),
+            product: "RTL2838UHIDIR".to_owned(),
+            serial: Some("00000001".to_owned()),
+            enable_ir: true,
+            remote_wakeup: false,
+        }
+    }
+
+    #[test]
+    fn it_parses_a_canned_image() {
+        assert_eq!(canned_image().config().unwrap(), canned_config());
+    }
+
+    #[test]
+    fn it_changes_only_the_config() {
+        let mut eeprom = canned_image();
+        let config = EepromConfig {
+            serial: Some("00000042".to_owned()),
+            remote_wakeup: true,
+            ..canned_config()
+        };
+        eeprom.set_config(&config).unwrap();
+
+        assert_eq!(eeprom.config().unwrap(), config);
+        assert_eq!(eeprom.as_bytes()[7], 0x17);
+        assert_eq!(
+            &eeprom.as_bytes()[STRINGS_END..],
+            &canned_image().as_bytes()[STRINGS_END..]
+        );
+        assert_eq!(eeprom.changed_range(&canned_image()), Some((7, 70)));
+    }
+
+    #[test]
+    fn it_hides_the_serial() {
+        let config = EepromConfig {
+            serial: None,
+            ..canned_config()
+        };
+        let eeprom = Eeprom::new(&config).unwrap();
+        assert_eq!(eeprom.as_bytes()[6], 0);
+        assert_eq!(eeprom.config().unwrap(), config);
+    }
+
+    #[test]
+    fn it_rejects_invalid_images() {
+        let mut data = canned_image().as_bytes().to_owned();
+        data[0] = 0xff;
+        assert!(matches!(
+            Eeprom::from_bytes(&data).unwrap().config(),
+            Err(Error::InvalidHeader {
+                header: [0xff, 0x32]
+            })
+        ));
+
+        let mut data = canned_image().as_bytes().to_owned();
+        data[25] = 0x7f;
+        assert!(matches!(
+            Eeprom::from_bytes(&data).unwrap().config(),
+            Err(Error::InvalidStringDescriptor { offset: 25 })
+        ));
+
+        assert!(matches!(
+            Eeprom::from_bytes(&data[..128]),
+            Err(Error::InvalidSize { len: 128 })
+        ));
+    }
+
+    #[test]
+    fn it_rejects_long_strings() {
+        let config = EepromConfig {
+            product: "a very long product name that doesn't fit".to_owned(),
+            ..canned_config()
+        };
+        assert!(matches!(
+            canned_image().set_config(&config),
+            Err(Error::StringsTooLong { .. })
+        ));
+    }
+
+    #[test]
+    fn it_doesnt_overwrite_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("backup.bin");
+
+        let original = canned_image();
+        original.save_backup(&path).unwrap();
+
+        let mut modified = canned_image();
+        modified.data[EEPROM_SIZE - 1] ^= 0xff;
+        assert!(matches!(
+            modified.save_backup(&path),
+            Err(Error::BackupExists { .. })
+        ));
+
+        assert_eq!(Eeprom::load(&path).unwrap(), original);
+    }
+}
@@ -3,7 +3,10 @@ use std::{
         CStr,
         c_char,
     },
-    fmt::Debug,
+    fmt::{
+        Debug,
+        Display,
+    },
 };
 
 use crate::{
@@ -118,6 +121,173 @@ impl DeviceInfo {
     }
 }
 
+/// Selects devices by their USB strings.
+///
+/// Each pattern can contain `*` to match any number of characters, and `?` to
+/// match a single character. E.g. `0000*` matches all serials starting with
+/// `0000`. Without wildcards the string has to match exactly. A device matches
+/// if it matches all patterns that are set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceFilter {
+    manufacturer: Option<Pattern>,
+    product: Option<Pattern>,
+    serial: Option<Pattern>,
+}
+
+impl DeviceFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_manufacturer(mut self, pattern: impl Into<String>) -> Self {
+        self.manufacturer = Some(Pattern::Glob(pattern.into()));
+        self
+    }
+
+    pub fn with_product(mut self, pattern: impl Into<String>) -> Self {
+        self.product = Some(Pattern::Glob(pattern.into()));
+        self
+    }
+
+    pub fn with_serial(mut self, pattern: impl Into<String>) -> Self {
+        self.serial = Some(Pattern::Glob(pattern.into()));
+        self
+    }
+
+    /// Match the serial literally, i.e. `*` and `?` are not wildcards.
+    pub fn with_exact_serial(mut self, serial: impl Into<String>) -> Self {
+        self.serial = Some(Pattern::Exact(serial.into()));
+        self
+    }
+
+    pub fn matches(&self, device: &DeviceInfo) -> bool {
+        fn matches_field(pattern: &Option<Pattern>, value: Option<&str>) -> bool {
+            match (pattern, value) {
+                (None, _) => true,
+                (Some(Pattern::Glob(pattern)), Some(value)) => glob_match(pattern, value),
+                (Some(Pattern::Exact(pattern)), Some(value)) => pattern == value,
+                (Some(_), None) => false,
+            }
+        }
+
+        matches_field(&self.manufacturer, device.manufacturer())
+            && matches_field(&self.product, device.product())
+            && matches_field(&self.serial, device.serial())
+    }
+
+    /// Returns all available devices that match.
+    pub fn devices(&self) -> impl Iterator<Item = DeviceInfo> {
+        let filter = self.clone();
+        devices().filter(move |device| filter.matches(device))
+    }
+
+    /// Returns the only device that matches.
+    ///
+    /// Returns an error if no device, or more than one device matches.
+    pub fn find(&self) -> Result<DeviceInfo, Error> {
+        self.find_in(devices())
+    }
+
+    /// Opens the only device that matches.
+    ///
+    /// Returns an error if no device, or more than one device matches.
+    pub fn open(&self) -> Result<RtlSdr, Error> {
+        self.find()?.open()
+    }
+
+    fn find_in(&self, devices: impl IntoIterator<Item = DeviceInfo>) -> Result<DeviceInfo, Error> {
+        let mut matches = devices.into_iter().filter(|device| self.matches(device));
+
+        let device = matches.next().ok_or_else(|| {
+            Error::NoMatchingDevice {
+                filter: self.to_string(),
+            }
+        })?;
+
+        let others = matches.map(|device| device.index).collect::<Vec<_>>();
+        if !others.is_empty() {
+            return Err(Error::AmbiguousDevice {
+                filter: self.to_string(),
+                indices: std::iter::once(device.index).chain(others).collect(),
+            });
+        }
+
+        Ok(device)
+    }
+}
+
+impl Display for DeviceFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fields = [
+            ("manufacturer", &self.manufacturer),
+            ("product", &self.product),
+            ("serial", &self.serial),
+        ];
+        let mut first = true;
+        for (name, pattern) in fields {
+            if let Some(Pattern::Glob(pattern) | Pattern::Exact(pattern)) = pattern {
+                if !first {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{name}={pattern:?}")?;
+                first = false;
+            }
+        }
+        if first {
+            write!(f, "any device")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Pattern {
+    /// with `*` and `?` wildcards
+    Glob(String),
+    Exact(String),
+}
+
+/// matches `value` against a pattern with `*` and `?` wildcards.
+fn glob_match(pattern: &str, value: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let value = value.chars().collect::<Vec<_>>();
+
+    // position after the last `*` in the pattern, and the position in value
+    // where we tried to match it.
+    let mut backtrack = None;
+    let (mut p, mut v) = (0, 0);
+
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                backtrack = Some((p, v));
+            }
+            Some('?') => {
+                p += 1;
+                v += 1;
+            }
+            Some(c) if *c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => {
+                // let the last `*` match one more character
+                let Some((backtrack_p, backtrack_v)) = backtrack
+                else {
+                    return false;
+                };
+                p = backtrack_p;
+                v = backtrack_v + 1;
+                backtrack = Some((backtrack_p, v));
+            }
+        }
+    }
+
+    // only `*`s may be left in the pattern
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
 #[derive(Clone, Copy, Debug)]
 struct UsbStrings {
     manufacturer: UsbString,
@@ -161,3 +331,90 @@ impl Debug for UsbString {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usb_string(s: &str) -> UsbString {
+        let mut bytes = [0; UsbString::BUFFER_SIZE];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        UsbString::new(bytes)
+    }
+
+    fn device(index: u32, serial: &str) -> DeviceInfo {
+        DeviceInfo {
+            index,
+            device_name: c"Generic RTL2832U OEM",
+            usb_strings: Some(UsbStrings {
+                manufacturer: usb_string("Realtek"),
+                product: usb_string("RTL2838UHIDIR"),
+                serial: usb_string(serial),
+            }),
+        }
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("00000001", "00000001"));
+        assert!(!glob_match("00000001", "000000011"));
+        assert!(glob_match("0000*", "00000001"));
+        assert!(glob_match("*01", "00000001"));
+        assert!(glob_match("0*0*1", "00000001"));
+        assert!(glob_match("0000000?", "00000001"));
+        assert!(!glob_match("0000000?", "0000001"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("1*", "00000001"));
+    }
+
+    #[test]
+    fn find_reports_missing_and_ambiguous_matches() {
+        let devices = [device(0, "00000001"), device(1, "00000002")];
+
+        let found = DeviceFilter::new()
+            .with_serial("00000002")
+            .find_in(devices)
+            .unwrap();
+        assert_eq!(found.index(), 1);
+
+        let found = DeviceFilter::new()
+            .with_manufacturer("Realtek")
+            .with_serial("*1")
+            .find_in(devices)
+            .unwrap();
+        assert_eq!(found.index(), 0);
+
+        assert!(matches!(
+            DeviceFilter::new().with_serial("0000*").find_in(devices),
+            Err(Error::AmbiguousDevice { indices, .. }) if indices == [0, 1]
+        ));
+
+        let error = DeviceFilter::new()
+            .with_product("RTL2838*")
+            .with_serial("1234")
+            .find_in(devices)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            r#"no device matches product="RTL2838*", serial="1234""#
+        );
+    }
+
+    #[test]
+    fn exact_serials_have_no_wildcards() {
+        let devices = [device(0, "00000001"), device(1, "0000*")];
+
+        let found = DeviceFilter::new()
+            .with_exact_serial("0000*")
+            .find_in(devices)
+            .unwrap();
+        assert_eq!(found.index(), 1);
+
+        assert!(matches!(
+            DeviceFilter::new()
+                .with_exact_serial("0000000?")
+                .find_in(devices),
+            Err(Error::NoMatchingDevice { .. })
+        ));
+    }
+}
@@ -0,0 +1,738 @@
+//! Replay of recorded `.cu8` captures.
+//!
+//! A `.cu8` file is what `rtl_sdr` writes: interleaved unsigned 8 bit I and Q
+//! samples, without any header. [`FileBackend`] streams such a file through the
+//! [`Backend`] trait, so it can be used in place of a live [`RtlSdr`][1].
+//!
+//! Since the file itself doesn't contain the center frequency and sample rate,
+//! they need to be passed in as [`Metadata`] or read from a sidecar file (see
+//! [`Metadata::read_sidecar`]).
+//!
+//! [1]: crate::RtlSdr
+
+use std::{
+    fs::File,
+    io::{
+        BufRead,
+        BufReader,
+        Read,
+        Seek,
+        SeekFrom,
+        Write,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::Arc,
+    thread,
+    time::Instant,
+};
+
+use parking_lot::Mutex;
+
+use crate::{
+    Backend,
+    Bandwidth,
+    DEFAULT_BUFFER_SIZE,
+    DEFAULT_QUEUE_SIZE,
+    DeviceConfig,
+    DirectSamplingMode,
+    DongleInfo,
+    Gain,
+    Iq,
+    SampleType,
+    Samples,
+    TunerType,
+    buffer_queue::{
+        self,
+        Backpressure,
+        Buffer,
+    },
+    pacing::{
+        Pacer,
+        Pacing,
+    },
+};
+
+/// Errors returned by a [`FileBackend`]
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("invalid sidecar file: {reason}")]
+    InvalidSidecar { reason: String },
+    #[error("sample offset {offset} is beyond the end of the file ({num_samples} samples)")]
+    InvalidOffset { offset: u64, num_samples: u64 },
+    #[error("operation not supported by file backend")]
+    Unsupported,
+}
+
+/// Center frequency and sample rate of a recording.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Metadata {
+    /// Center frequency in Hz
+    pub center_frequency: u32,
+
+    /// Sample rate in Hz
+    pub sample_rate: u32,
+}
+
+impl Metadata {
+    /// Returns the path of the sidecar file for a recording.
+    ///
+    /// This is the path of the recording with `.meta` appended, e.g.
+    /// `capture.cu8.meta` for `capture.cu8`.
+    pub fn sidecar_path(path: impl AsRef<Path>) -> PathBuf {
+        let mut path = path.as_ref().as_os_str().to_owned();
+        path.push(".meta");
+        path.into()
+    }
+
+    /// Reads the sidecar file for the recording at `path`.
+    ///
+    /// The sidecar file contains one `key = value` pair per line, e.g.:
+    ///
+    /// ```text
+    /// center_frequency = 100000000
+    /// sample_rate = 2048000
+    /// ```
+    ///
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn read_sidecar(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let reader = BufReader::new(File::open(Self::sidecar_path(path))?);
+
+        let mut center_frequency = None;
+        let mut sample_rate = None;
+
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                Error::InvalidSidecar {
+                    reason: format!("expected `key = value`, but got: {line}"),
+                }
+            })?;
+            let (key, value) = (key.trim(), value.trim());
+            let parse = || {
+                value.parse::<u32>().map_err(|_| {
+                    Error::InvalidSidecar {
+                        reason: format!("invalid value for {key}: {value}"),
+                    }
+                })
+            };
+
+            match key {
+                "center_frequency" => center_frequency = Some(parse()?),
+                "sample_rate" => sample_rate = Some(parse()?),
+                _ => tracing::debug!(?key, "ignoring unknown key in sidecar file"),
+            }
+        }
+
+        let missing = |key| {
+            Error::InvalidSidecar {
+                reason: format!("missing {key}"),
+            }
+        };
+
+        Ok(Self {
+            center_frequency: center_frequency.ok_or_else(|| missing("center_frequency"))?,
+            sample_rate: sample_rate.ok_or_else(|| missing("sample_rate"))?,
+        })
+    }
+
+    /// Writes the sidecar file for the recording at `path`.
+    pub fn write_sidecar(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let mut file = File::create(Self::sidecar_path(path))?;
+        writeln!(file, "center_frequency = {}", self.center_frequency)?;
+        writeln!(file, "sample_rate = {}", self.sample_rate)?;
+        Ok(())
+    }
+}
+
+/// Replays a `.cu8` recording.
+///
+/// The file is read in a separate thread, which feeds the samples into the
+/// same kind of buffer queue that the reader thread of an
+/// [`RtlSdr`][crate::RtlSdr] uses. By default the samples are replayed at the
+/// recorded sample rate, and the stream ends at the end of the file.
+///
+/// Streams default to [`Backpressure::Block`], so playback waits for slow
+/// consumers instead of skipping samples. Chunks after a [seek][Self::seek] or
+/// loop are marked as a [discontinuity][crate::Chunk::discontinuity].
+///
+/// Since a recording can't be retuned, setting the center frequency or sample
+/// rate to anything else than the recorded values will fail with
+/// [`Error::Unsupported`]. Gain settings and the like are accepted, but have no
+/// effect.
+///
+/// This is cheaply cloneable. All clones share the same playback position.
+#[derive(Clone, Debug)]
+pub struct FileBackend {
+    metadata: Metadata,
+    playback: Playback,
+}
+
+impl FileBackend {
+    /// Open the recording at `path` and replay it in real-time.
+    pub fn open(path: impl AsRef<Path>, metadata: Metadata) -> Result<Self, Error> {
+        Self::open_with_pacing(path, metadata, Pacing::RealTime)
+    }
+
+    /// Open the recording at `path` and read its metadata from the sidecar
+    /// file.
+    ///
+    /// See [`Metadata::read_sidecar`].
+    pub fn open_with_sidecar(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let metadata = Metadata::read_sidecar(&path)?;
+        Self::open(path, metadata)
+    }
+
+    /// Open the recording at `path` with the given pacing.
+    ///
+    /// With [`Pacing::Unpaced`] the file is read as fast as the consumers can
+    /// process it.
+    pub fn open_with_pacing(
+        path: impl AsRef<Path>,
+        metadata: Metadata,
+        pacing: Pacing,
+    ) -> Result<Self, Error> {
+        let playback = Playback::open(
+            path,
+            SampleFormat::Cu8,
+            vec![Segment {
+                sample_start: 0,
+                sample_rate: metadata.sample_rate,
+                center_frequency: Some(metadata.center_frequency),
+            }],
+            pacing,
+        )?;
+
+        Ok(Self { metadata, playback })
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Number of IQ samples in the recording.
+    pub fn num_samples(&self) -> u64 {
+        self.playback.num_samples()
+    }
+
+    /// Restart from the beginning when the end of the file is reached.
+    pub fn set_looping(&self, looping: bool) {
+        self.playback.set_looping(looping);
+    }
+
+    /// Continue playback at the given sample offset.
+    ///
+    /// Samples that were already read into the buffer queue will still be
+    /// delivered.
+    pub fn seek(&self, offset: u64) -> Result<(), Error> {
+        if offset > self.num_samples() {
+            return Err(Error::InvalidOffset {
+                offset,
+                num_samples: self.num_samples(),
+            });
+        }
+        self.playback.seek(offset);
+        Ok(())
+    }
+
+    fn check_unchanged(current: u32, requested: u32) -> Result<(), Error> {
+        if current == requested {
+            Ok(())
+        }
+        else {
+            Err(Error::Unsupported)
+        }
+    }
+
+    pub async fn samples(&self) -> Result<Samples<Iq>, Error> {
+        Ok(self.playback.samples())
+    }
+}
+
+impl Backend for FileBackend {
+    type Error = Error;
+
+    fn dongle_info(&self) -> DongleInfo {
+        DongleInfo {
+            tuner_type: TunerType::UNKNOWN,
+            tuner_gain_count: 0,
+        }
+    }
+
+    async fn config(&self) -> Result<DeviceConfig, Error> {
+        Ok(DeviceConfig {
+            center_frequency: Some(self.metadata.center_frequency),
+            sample_rate: Some(self.metadata.sample_rate),
+            ..Default::default()
+        })
+    }
+
+    async fn set_center_frequency(&self, frequency: u32) -> Result<(), Error> {
+        Self::check_unchanged(self.metadata.center_frequency, frequency)
+    }
+
+    async fn set_sample_rate(&self, sample_rate: u32) -> Result<(), Error> {
+        Self::check_unchanged(self.metadata.sample_rate, sample_rate)
+    }
+
+    async fn set_tuner_gain(&self, _gain: Gain) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_tuner_bandwidth(&self, _bandwidth: Bandwidth) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_agc_mode(&self, _enable: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_frequency_correction(&self, _ppm: i32) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_tuner_if_gain(&self, _stage: i16, _gain: i16) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_offset_tuning(&self, _enable: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_rtl_xtal(&self, _frequency: u32) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_tuner_xtal(&self, _frequency: u32) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_bias_tee(&self, _enable: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_bias_tee_gpio(&self, _pin: u8, _enable: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_direct_sampling(&self, mode: Option<DirectSamplingMode>) -> Result<(), Error> {
+        // recordings only have IQ samples
+        if mode.is_some() {
+            Err(Error::Unsupported)
+        }
+        else {
+            Ok(())
+        }
+    }
+
+    async fn samples(&self) -> Result<Samples<Iq>, Error> {
+        FileBackend::samples(self).await
+    }
+
+    async fn direct_samples(&self, _mode: DirectSamplingMode) -> Result<Samples<u8>, Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+/// Format of the samples in a recording.
+///
+/// Everything that isn't `cu8` is converted to `cu8` when it's read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(not(feature = "sigmf"), allow(dead_code))]
+pub(crate) enum SampleFormat {
+    /// unsigned 8 bit IQ, what the RTL-SDR produces
+    Cu8,
+    /// signed 8 bit IQ
+    Ci8,
+    /// signed 16 bit little-endian IQ
+    Ci16Le,
+    /// 32 bit little-endian float IQ
+    Cf32Le,
+}
+
+impl SampleFormat {
+    /// size of one IQ sample in bytes
+    fn sample_size(&self) -> usize {
+        match self {
+            Self::Cu8 | Self::Ci8 => 2,
+            Self::Ci16Le => 4,
+            Self::Cf32Le => 8,
+        }
+    }
+
+    /// converts the raw samples in `input` to `cu8` and writes them to
+    /// `output`. `output` must be large enough to hold them.
+    fn convert(&self, input: &[u8], output: &mut [u8]) {
+        match self {
+            Self::Cu8 => output[..input.len()].copy_from_slice(input),
+            Self::Ci8 => {
+                for (x, y) in input.iter().zip(output) {
+                    *y = x ^ 0x80;
+                }
+            }
+            Self::Ci16Le => {
+                for (x, y) in input.chunks_exact(2).zip(output) {
+                    // keep the most significant byte
+                    *y = x[1] ^ 0x80;
+                }
+            }
+            Self::Cf32Le => {
+                for (x, y) in input.chunks_exact(4).zip(output) {
+                    let x = f32::from_le_bytes(x.try_into().unwrap());
+                    *y = ((x + 1.0) * 127.5).round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        }
+    }
+}
+
+/// A part of a recording with the same sample rate and center frequency.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Segment {
+    pub sample_start: u64,
+    pub sample_rate: u32,
+    pub center_frequency: Option<u32>,
+}
+
+/// Plays back a file of IQ samples in a separate thread.
+///
+/// This is shared by the [`FileBackend`] and the SigMF reader.
+#[derive(Clone, Debug)]
+pub(crate) struct Playback {
+    num_samples: u64,
+    state: Arc<Mutex<PlaybackState>>,
+    buffer_queue_subscriber: buffer_queue::Subscriber,
+}
+
+impl Playback {
+    /// `segments` must not be empty, and must be sorted by `sample_start`.
+    pub fn open(
+        path: impl AsRef<Path>,
+        format: SampleFormat,
+        segments: Vec<Segment>,
+        pacing: Pacing,
+    ) -> Result<Self, std::io::Error> {
+        assert!(!segments.is_empty(), "no segments");
+
+        let file = File::open(path)?;
+        let num_samples = file.metadata()?.len() / format.sample_size() as u64;
+
+        let state = Arc::new(Mutex::new(PlaybackState {
+            looping: false,
+            seek: None,
+            position: 0,
+        }));
+
+        let (buffer_queue_sender, buffer_queue_subscriber) =
+            buffer_queue::channel(DEFAULT_QUEUE_SIZE);
+        // unlike a device, we can wait for the consumers without losing anything
+        buffer_queue_subscriber.set_default_backpressure(Backpressure::Block);
+
+        thread::spawn({
+            let state = state.clone();
+            move || {
+                let mut playback_thread = PlaybackThread {
+                    buffer_queue_sender,
+                    file,
+                    format,
+                    segments,
+                    state,
+                    num_samples,
+                    position: 0,
+                    scratch: vec![],
+                };
+                if let Err(error) = playback_thread.run(pacing, DEFAULT_BUFFER_SIZE) {
+                    tracing::error!(?error, "playback thread error");
+                }
+            }
+        });
+
+        Ok(Self {
+            num_samples,
+            state,
+            buffer_queue_subscriber,
+        })
+    }
+
+    pub fn num_samples(&self) -> u64 {
+        self.num_samples
+    }
+
+    /// index of the next sample that will be read from the file
+    #[cfg_attr(not(feature = "sigmf"), allow(dead_code))]
+    pub fn position(&self) -> u64 {
+        self.state.lock().position
+    }
+
+    pub fn set_looping(&self, looping: bool) {
+        self.state.lock().looping = looping;
+    }
+
+    pub fn seek(&self, offset: u64) {
+        self.state.lock().seek = Some(offset);
+    }
+
+    pub fn samples(&self) -> Samples<Iq> {
+        Samples::new(self.buffer_queue_subscriber.receiver(), SampleType::Iq)
+    }
+}
+
+#[derive(Debug)]
+struct PlaybackState {
+    looping: bool,
+
+    /// sample offset to seek to before the next read
+    seek: Option<u64>,
+
+    /// the playback thread's position, for the outside world
+    position: u64,
+}
+
+struct PlaybackThread {
+    buffer_queue_sender: buffer_queue::Sender,
+    file: File,
+    format: SampleFormat,
+    segments: Vec<Segment>,
+    state: Arc<Mutex<PlaybackState>>,
+    num_samples: u64,
+    position: u64,
+    /// buffer for raw samples that need to be converted
+    scratch: Vec<u8>,
+}
+
+impl PlaybackThread {
+    fn run(&mut self, pacing: Pacing, buffer_size: usize) -> Result<(), std::io::Error> {
+        let _guard = tracing::debug_span!("playback thread").entered();
+
+        let mut pacer = Pacer::new(pacing);
+        let mut push_buffer: Option<Buffer> = None;
+        let mut sample_index = 0;
+
+        tracing::debug!("playback thread spawned");
+
+        loop {
+            let Some(mut buffer) =
+                self.buffer_queue_sender
+                    .swap_buffers(push_buffer.take(), buffer_size, true)
+            else {
+                // all receivers and subscribers dropped
+                tracing::debug!("all readers dropped. exiting");
+                break;
+            };
+
+            let Some(segment) = self.read_to_buffer(&mut buffer, buffer_size)?
+            else {
+                tracing::debug!("end of file. exiting");
+                break;
+            };
+
+            buffer.sample_index = sample_index;
+            sample_index += buffer.num_samples() as u64;
+
+            pacer.wait(buffer.num_samples(), segment.sample_rate);
+            buffer.timestamp = Instant::now();
+
+            push_buffer = Some(buffer);
+        }
+
+        Ok(())
+    }
+
+    /// fills the buffer from the file, handling seeks and looping. a buffer
+    /// never spans multiple segments. returns the segment the samples are from,
+    /// or `None` if we're at the end of the file and not looping.
+    fn read_to_buffer(
+        &mut self,
+        buffer: &mut Buffer,
+        buffer_size: usize,
+    ) -> Result<Option<Segment>, std::io::Error> {
+        let mut discontinuity = false;
+        let looping = {
+            let mut state = self.state.lock();
+            if let Some(offset) = state.seek.take() {
+                discontinuity = true;
+                self.position = offset.min(self.num_samples);
+                self.file.seek(SeekFrom::Start(
+                    self.position * self.format.sample_size() as u64,
+                ))?;
+            }
+            state.looping
+        };
+
+        if self.position >= self.num_samples {
+            if looping && self.num_samples > 0 {
+                discontinuity = true;
+                self.position = 0;
+                self.file.seek(SeekFrom::Start(0))?;
+            }
+            else {
+                return Ok(None);
+            }
+        }
+
+        let segment_index = self
+            .segments
+            .partition_point(|segment| segment.sample_start <= self.position)
+            .saturating_sub(1);
+        let segment = self.segments[segment_index];
+        let segment_end = self
+            .segments
+            .get(segment_index + 1)
+            .map_or(self.num_samples, |next| next.sample_start);
+
+        let num_samples = ((buffer_size / 2) as u64)
+            .min(segment_end.min(self.num_samples) - self.position)
+            as usize;
+
+        self.scratch
+            .resize(num_samples * self.format.sample_size(), 0);
+        self.file.read_exact(&mut self.scratch)?;
+
+        let buffer_mut = buffer.reclaim_or_allocate(buffer_size);
+        self.format.convert(&self.scratch, buffer_mut);
+
+        buffer.start = 0;
+        buffer.end = 2 * num_samples;
+        buffer.sample_rate = segment.sample_rate;
+        buffer.sample_type = SampleType::Iq;
+        buffer.center_frequency = segment.center_frequency;
+        buffer.discontinuity = discontinuity;
+
+        self.position += num_samples as u64;
+        self.state.lock().position = self.position;
+
+        Ok(Some(segment))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::TryStreamExt;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const METADATA: Metadata = Metadata {
+        center_frequency: 100_000_000,
+        sample_rate: 1_024_000,
+    };
+
+    /// writes a recording with `num_samples` samples, where each sample is its
+    /// index (modulo 256) in both components.
+    ///
+    /// the recording is deleted when the returned directory is dropped.
+    fn write_recording(num_samples: usize) -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.cu8");
+        let data = (0..num_samples)
+            .flat_map(|i| [i as u8, i as u8])
+            .collect::<Vec<u8>>();
+        std::fs::write(&path, data).unwrap();
+        (dir, path)
+    }
+
+    /// reads up to `limit` samples, and returns them together with the
+    /// sample offsets at which chunks were marked as discontinuities.
+    async fn read_all(backend: &FileBackend, limit: usize) -> (Vec<Iq>, Vec<usize>) {
+        let mut stream = backend.samples().await.unwrap();
+        let mut samples = vec![];
+        let mut discontinuities = vec![];
+        while samples.len() < limit {
+            let Some(chunk) = stream.try_next().await.unwrap()
+            else {
+                break;
+            };
+            assert_eq!(chunk.sample_rate(), METADATA.sample_rate);
+            if chunk.discontinuity() {
+                discontinuities.push(samples.len());
+            }
+            samples.extend_from_slice(chunk.samples());
+        }
+        (samples, discontinuities)
+    }
+
+    #[tokio::test]
+    async fn it_replays_the_whole_file() {
+        let (_dir, path) = write_recording(20_000);
+        let backend = FileBackend::open_with_pacing(&path, METADATA, Pacing::Unpaced).unwrap();
+        assert_eq!(backend.num_samples(), 20_000);
+
+        let (samples, discontinuities) = read_all(&backend, usize::MAX).await;
+        assert_eq!(samples.len(), 20_000);
+        assert!(discontinuities.is_empty());
+        assert!(
+            samples
+                .iter()
+                .enumerate()
+                .all(|(index, sample)| sample.i == index as u8 && sample.q == index as u8)
+        );
+    }
+
+    #[tokio::test]
+    async fn it_loops_and_seeks() {
+        let (_dir, path) = write_recording(1000);
+        let backend = FileBackend::open_with_pacing(&path, METADATA, Pacing::Unpaced).unwrap();
+        backend.set_looping(true);
+        backend.seek(500).unwrap();
+        assert!(backend.seek(1001).is_err());
+
+        let (samples, discontinuities) = read_all(&backend, 3000).await;
+        assert!(samples.len() >= 3000);
+        assert!(
+            samples
+                .iter()
+                .enumerate()
+                .all(|(index, sample)| sample.i == ((index + 500) % 1000) as u8)
+        );
+        // the seek, and then every time we wrap around
+        assert_eq!(discontinuities[..4], [0, 500, 1500, 2500]);
+    }
+
+    #[tokio::test]
+    async fn it_waits_for_slow_consumers_when_unpaced() {
+        let num_samples = 100 * DEFAULT_BUFFER_SIZE / 2;
+        let (_dir, path) = write_recording(num_samples);
+        let backend = FileBackend::open_with_pacing(&path, METADATA, Pacing::Unpaced).unwrap();
+
+        let mut stream = backend.samples().await.unwrap();
+        let mut samples = vec![];
+        while let Some(chunk) = stream.try_next().await.unwrap() {
+            assert_eq!(chunk.dropped(), 0);
+            samples.extend_from_slice(chunk.samples());
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+
+        assert_eq!(samples.len(), num_samples);
+        assert!(
+            samples
+                .iter()
+                .enumerate()
+                .all(|(index, sample)| sample.i == index as u8)
+        );
+    }
+
+    #[test]
+    fn sidecar_roundtrip() {
+        let (_dir, path) = write_recording(0);
+        METADATA.write_sidecar(&path).unwrap();
+        assert_eq!(Metadata::read_sidecar(&path).unwrap(), METADATA);
+    }
+
+    #[tokio::test]
+    async fn it_rejects_retuning() {
+        let (_dir, path) = write_recording(0);
+        let backend = FileBackend::open(&path, METADATA).unwrap();
+        backend.set_center_frequency(100_000_000).await.unwrap();
+        assert!(backend.set_center_frequency(101_000_000).await.is_err());
+        assert!(backend.set_sample_rate(2_048_000).await.is_err());
+    }
+}
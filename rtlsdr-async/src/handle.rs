@@ -5,18 +5,31 @@ use std::{
     },
     fmt::Debug,
     ptr::null_mut,
+    sync::{
+        Arc,
+        atomic::{
+            AtomicU32,
+            AtomicU64,
+            Ordering,
+        },
+    },
 };
 
 use parking_lot::{
     Mutex,
     MutexGuard,
 };
+use tokio::sync::broadcast;
 
 use crate::{
+    ConfigEvent,
+    DeviceConfig,
     DirectSamplingMode,
     Error,
+    Gain,
     TunerGainMode,
     TunerType,
+    config::CONFIG_EVENT_QUEUE_SIZE,
 };
 
 /// This used to be somewhat unsafe, but now it isn't anymore!
@@ -30,7 +43,18 @@ pub(crate) struct Handle {
     // interacting with the handle. thus this contains the handle.
     locked: Mutex<LockedHandle>,
 
-    pub index: u32,
+    /// incremented whenever the sample rate or sampling mode changes. this is
+    /// shared with the [`LockedHandle`].
+    stream_generation: Arc<AtomicU64>,
+
+    /// incremented for every [`ConfigEvent`].
+    config_generation: AtomicU64,
+
+    config_events: broadcast::Sender<ConfigEvent>,
+
+    /// changes when the device is [reopened][Self::reopen].
+    index: AtomicU32,
+
     pub tuner_type: TunerType,
     pub tuner_gains: TunerGains,
 }
@@ -40,99 +64,397 @@ unsafe impl Sync for Handle {}
 
 impl Handle {
     pub fn open(index: u32) -> Result<Self, Error> {
-        let mut handle: rtlsdr_sys::rtlsdr_dev_t = null_mut();
-        let ret =
-            unsafe { rtlsdr_sys::rtlsdr_open(&mut handle as *mut rtlsdr_sys::rtlsdr_dev_t, index) };
-        tracing::debug!(?index, ?ret, "rtlsdr_open");
-        if ret != 0 {
-            return Err(Error::from_lib("rtlsdr_open", ret));
-        }
-        assert!(
-            !handle.is_null(),
-            "rtlsdr_open returned 0, but handle is still NULL"
-        );
-
-        // get the tuner type.
-        let ret: u32 = unsafe { rtlsdr_sys::rtlsdr_get_tuner_type(handle) } as u32;
-        tracing::debug!(ret, "rtlsdr_get_tuner_type");
-        if ret == 0 {
-            return Err(Error::UnknownTuner);
-        }
-        let tuner_type = TunerType(ret);
-
-        // get the tuner gains now, so we can hand them out as a slice later. this way
-        // we don't need to allocate a Vec everytime get_tuner_gains is called.
-        // furthermore the arrays returned by librtlsdr are fixed and as of writing
-        // don't exceed 29 entries.
-        let ret = unsafe { rtlsdr_sys::rtlsdr_get_tuner_gains(handle, null_mut()) };
-        tracing::debug!(ret, "rtlsdr_get_tuner_gains");
-        let mut tuner_gains = TunerGains::default();
-        if let Ok(num_gains) = ret.try_into() {
-            if num_gains < TunerGains::CAPACITY {
-                tuner_gains.length = num_gains;
-                let ret2 = unsafe {
-                    rtlsdr_sys::rtlsdr_get_tuner_gains(handle, tuner_gains.values.as_mut_ptr())
-                };
-                assert_eq!(
-                    ret, ret2,
-                    "rtlsdr_get_tuner_gains returned 2 different lengths"
-                );
-            }
-            else {
-                // instead of failing we could just allocate.
-                tracing::warn!(
-                    ?num_gains,
-                    capacity = TunerGains::CAPACITY,
-                    "bug: number of tuner gains available exceeds capacity."
-                );
-            }
-        }
-        tracing::debug!(gains = ?tuner_gains, "rtlsdr_get_tuner_gains");
+        let handle = open_device(index)?;
+        let (tuner_type, tuner_gains) = get_tuner(handle)?;
 
         // this is needed for reading to work
         // note: only fails if the dev pointer is null, which it is not
         let ret = unsafe { rtlsdr_sys::rtlsdr_reset_buffer(handle) };
         assert_eq!(ret, 0, "rtlsdr_reset_buffer didn't return 0");
 
+        let stream_generation = Arc::new(AtomicU64::new(0));
+
         Ok(Handle {
             locked: Mutex::new(LockedHandle {
                 handle,
                 tuner_gain_mode: None,
+                settings: Settings::default(),
+                stream_generation: stream_generation.clone(),
+                reading_async: false,
             }),
-            index,
+            stream_generation,
+            config_generation: AtomicU64::new(0),
+            config_events: broadcast::Sender::new(CONFIG_EVENT_QUEUE_SIZE),
+            index: AtomicU32::new(index),
             tuner_type,
             tuner_gains,
         })
     }
 
+    pub fn index(&self) -> u32 {
+        self.index.load(Ordering::Relaxed)
+    }
+
     pub fn lock(&self) -> MutexGuard<'_, LockedHandle> {
         self.locked.lock()
     }
+
+    /// changes whenever the sample rate or sampling mode changes.
+    pub fn stream_generation(&self) -> u64 {
+        self.stream_generation.load(Ordering::Acquire)
+    }
+
+    /// changes whenever a setting is changed.
+    pub fn config_generation(&self) -> u64 {
+        self.config_generation.load(Ordering::Acquire)
+    }
+
+    /// called after a setting was changed successfully. this should be done
+    /// while holding the lock, so that the reader thread sees the change with
+    /// the first samples that are read with the new setting.
+    pub fn notify(&self, event: ConfigEvent) {
+        tracing::trace!(?event, "config changed");
+        self.config_generation.fetch_add(1, Ordering::Release);
+        // this only fails if nobody is subscribed
+        let _ = self.config_events.send(event);
+    }
+
+    pub fn subscribe_config_changes(&self) -> broadcast::Receiver<ConfigEvent> {
+        self.config_events.subscribe()
+    }
+
+    /// reads with `rtlsdr_read_async` until `callback` returns `false`.
+    ///
+    /// this doesn't hold the lock while reading, so control commands can run
+    /// concurrently. librtlsdr supports this, `rtl_tcp` does the same.
+    pub fn read_async<F: FnMut(&[u8]) -> bool>(
+        &self,
+        buffer_count: u32,
+        buffer_length: usize,
+        callback: F,
+    ) -> Result<(), Error> {
+        struct Context<F> {
+            handle: rtlsdr_sys::rtlsdr_dev_t,
+            callback: F,
+        }
+
+        extern "C" fn trampoline<F: FnMut(&[u8]) -> bool>(
+            buffer: *mut u8,
+            length: u32,
+            context: *mut c_void,
+        ) {
+            let context = unsafe { &mut *(context as *mut Context<F>) };
+            let data = unsafe { std::slice::from_raw_parts(buffer, length as usize) };
+            if !(context.callback)(data) {
+                let ret = unsafe { rtlsdr_sys::rtlsdr_cancel_async(context.handle) };
+                tracing::debug!(ret, "rtlsdr_cancel_async");
+            }
+        }
+
+        let mut context = {
+            let mut locked = self.lock();
+            let handle = locked.handle()?;
+            locked.reading_async = true;
+            Context { handle, callback }
+        };
+
+        let ret = unsafe {
+            rtlsdr_sys::rtlsdr_read_async(
+                context.handle,
+                trampoline::<F>,
+                &mut context as *mut Context<_> as *mut c_void,
+                buffer_count,
+                buffer_length
+                    .try_into()
+                    .expect("buffer size too large for u32"),
+            )
+        };
+        tracing::debug!(ret, "rtlsdr_read_async");
+        self.lock().reading_async = false;
+        if ret == 0 {
+            Ok(())
+        }
+        else {
+            Err(Error::from_lib("rtlsdr_read_async", ret))
+        }
+    }
+
+    /// replaces the device with a freshly opened one, e.g. after it was
+    /// unplugged and plugged in again. `index` is the device's new index.
+    ///
+    /// all settings that were successfully applied to the old device are
+    /// restored, and [notified][Self::notify] again. this fails if the
+    /// device has a different tuner than the old one, since the tuner type and
+    /// gains were already handed out.
+    ///
+    /// the old device is [closed][Self::close] first, since it couldn't be
+    /// opened again while we hold it, if it's still plugged in.
+    pub fn reopen(&self, index: u32) -> Result<(), Error> {
+        let mut locked = self.lock();
+
+        locked.close();
+        let handle = open_device(index)?;
+        locked.handle = handle;
+
+        // from here on we close the device again on failure, so that we can retry.
+        match get_tuner(handle) {
+            Ok((tuner_type, tuner_gains))
+                if tuner_type == self.tuner_type
+                    && tuner_gains.as_ref() == self.tuner_gains.as_ref() => {}
+            Ok((tuner_type, _)) => {
+                tracing::warn!(?tuner_type, expected = ?self.tuner_type, "tuner changed");
+                locked.close();
+                return Err(Error::TunerChanged);
+            }
+            Err(error) => {
+                locked.close();
+                return Err(error);
+            }
+        }
+        self.index.store(index, Ordering::Relaxed);
+
+        let settings = std::mem::take(&mut locked.settings);
+        let tuner_gain_mode = locked.tuner_gain_mode.take();
+        let events = locked.restore(settings, tuner_gain_mode);
+
+        let ret = unsafe { rtlsdr_sys::rtlsdr_reset_buffer(handle) };
+        tracing::debug!(ret, "rtlsdr_reset_buffer");
+        if ret != 0 {
+            // a flaky device. close it again, so that we can retry.
+            locked.close();
+            return Err(Error::from_lib("rtlsdr_reset_buffer", ret));
+        }
+
+        for event in events {
+            self.notify(event);
+        }
+
+        Ok(())
+    }
+
+    /// closes the device, e.g. after it was lost. until it's
+    /// [reopened][Self::reopen], all operations on it fail.
+    pub fn close(&self) {
+        self.lock().close();
+    }
+
+    /// sets the gain mode and picks a supported gain value. since this locks
+    /// the handle itself, it also [notifies][Self::notify] subscribers.
+    pub fn set_tuner_gain(&self, gain: Gain) -> Result<(), Error> {
+        match gain {
+            Gain::ManualValue(gain) => {
+                // we need to find a supported gain value
+                let gain = self
+                    .tuner_gains
+                    .iter()
+                    .min_by_key(|supported| (**supported - gain).abs())
+                    .ok_or(Error::NoSupportedGains)?;
+
+                let mut handle = self.lock();
+
+                // manual gain mode must be enabled
+                handle.set_tuner_gain_mode(TunerGainMode::Manual)?;
+
+                handle.set_tuner_gain(*gain)?;
+                self.notify(ConfigEvent::TunerGain {
+                    gain: Gain::ManualValue(*gain),
+                });
+                Ok(())
+            }
+            Gain::ManualIndex(index) => {
+                // we need to find a supported gain value
+                let gain = self
+                    .tuner_gains
+                    .as_ref()
+                    .get(index)
+                    .ok_or(Error::InvalidGainIndex { index })?;
+
+                let mut handle = self.lock();
+
+                // manual gain mode must be enabled
+                handle.set_tuner_gain_mode(TunerGainMode::Manual)?;
+
+                handle.set_tuner_gain(*gain)?;
+                self.notify(ConfigEvent::TunerGain {
+                    gain: Gain::ManualValue(*gain),
+                });
+                Ok(())
+            }
+            Gain::Auto => {
+                let mut handle = self.lock();
+                handle.set_tuner_gain_mode(TunerGainMode::Auto)?;
+                self.notify(ConfigEvent::TunerGain { gain: Gain::Auto });
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl Handle {
+    /// a handle without a device, for testing code that only follows the
+    /// generations and config events.
+    pub fn closed() -> Self {
+        let stream_generation = Arc::new(AtomicU64::new(0));
+        Handle {
+            locked: Mutex::new(LockedHandle {
+                handle: null_mut(),
+                tuner_gain_mode: None,
+                settings: Settings::default(),
+                stream_generation: stream_generation.clone(),
+                reading_async: false,
+            }),
+            stream_generation,
+            config_generation: AtomicU64::new(0),
+            config_events: broadcast::Sender::new(CONFIG_EVENT_QUEUE_SIZE),
+            index: AtomicU32::new(0),
+            tuner_type: TunerType::R820T,
+            tuner_gains: TunerGains::default(),
+        }
+    }
+
+    /// what changing the sample rate or sampling mode does to the generations.
+    pub fn change_stream(&self) {
+        self.stream_generation.fetch_add(1, Ordering::Release);
+    }
 }
 
 impl Drop for Handle {
     fn drop(&mut self) {
-        tracing::debug!(index = self.index, "rtl_sdr_close");
-        let state = self.locked.lock();
-        unsafe {
-            rtlsdr_sys::rtlsdr_close(state.handle);
+        self.locked.lock().close();
+    }
+}
+
+/// functions that are missing from the `rtlsdr_sys` bindings. these were added
+/// in librtlsdr 0.6.0, so they're behind a feature.
+#[cfg(feature = "bias-tee")]
+mod ffi {
+    use std::ffi::c_int;
+
+    unsafe extern "C" {
+        pub fn rtlsdr_set_bias_tee(dev: rtlsdr_sys::rtlsdr_dev_t, on: c_int) -> c_int;
+        pub fn rtlsdr_set_bias_tee_gpio(
+            dev: rtlsdr_sys::rtlsdr_dev_t,
+            gpio: c_int,
+            on: c_int,
+        ) -> c_int;
+    }
+}
+
+/// gets the tuner type and the gains it supports.
+fn get_tuner(handle: rtlsdr_sys::rtlsdr_dev_t) -> Result<(TunerType, TunerGains), Error> {
+    // get the tuner type.
+    let ret: u32 = unsafe { rtlsdr_sys::rtlsdr_get_tuner_type(handle) } as u32;
+    tracing::debug!(ret, "rtlsdr_get_tuner_type");
+    if ret == 0 {
+        return Err(Error::UnknownTuner);
+    }
+    let tuner_type = TunerType(ret);
+
+    // get the tuner gains now, so we can hand them out as a slice later. this way
+    // we don't need to allocate a Vec everytime get_tuner_gains is called.
+    // furthermore the arrays returned by librtlsdr are fixed and as of writing
+    // don't exceed 29 entries.
+    let ret = unsafe { rtlsdr_sys::rtlsdr_get_tuner_gains(handle, null_mut()) };
+    tracing::debug!(ret, "rtlsdr_get_tuner_gains");
+    let mut tuner_gains = TunerGains::default();
+    if let Ok(num_gains) = ret.try_into() {
+        if num_gains < TunerGains::CAPACITY {
+            tuner_gains.length = num_gains;
+            let ret2 = unsafe {
+                rtlsdr_sys::rtlsdr_get_tuner_gains(handle, tuner_gains.values.as_mut_ptr())
+            };
+            assert_eq!(
+                ret, ret2,
+                "rtlsdr_get_tuner_gains returned 2 different lengths"
+            );
+        }
+        else {
+            // instead of failing we could just allocate.
+            tracing::warn!(
+                ?num_gains,
+                capacity = TunerGains::CAPACITY,
+                "bug: number of tuner gains available exceeds capacity."
+            );
         }
     }
+    tracing::debug!(gains = ?tuner_gains, "rtlsdr_get_tuner_gains");
+
+    Ok((tuner_type, tuner_gains))
+}
+
+/// opens the device with librtlsdr
+fn open_device(index: u32) -> Result<rtlsdr_sys::rtlsdr_dev_t, Error> {
+    let mut handle: rtlsdr_sys::rtlsdr_dev_t = null_mut();
+    let ret =
+        unsafe { rtlsdr_sys::rtlsdr_open(&mut handle as *mut rtlsdr_sys::rtlsdr_dev_t, index) };
+    tracing::debug!(?index, ?ret, "rtlsdr_open");
+    if ret != 0 {
+        return Err(Error::from_lib("rtlsdr_open", ret));
+    }
+    assert!(
+        !handle.is_null(),
+        "rtlsdr_open returned 0, but handle is still NULL"
+    );
+    Ok(handle)
 }
 
 #[derive(Debug)]
 pub(crate) struct LockedHandle {
+    /// NULL while the device is [closed][Handle::close].
     handle: rtlsdr_sys::rtlsdr_dev_t,
 
     /// the tuner gain mode we set previously. we store this so we can skip
     /// setting it if we would set it to the same mode gain. librtlsdr doesn't
     /// do this check. initially we don't know the mode, so this is an Option.
     tuner_gain_mode: Option<TunerGainMode>,
+
+    /// settings that were successfully applied, so we can restore them when
+    /// the device is reopened.
+    settings: Settings,
+
+    /// see [`Handle::stream_generation`]
+    stream_generation: Arc<AtomicU64>,
+
+    /// set while [`Handle::read_async`] is running.
+    reading_async: bool,
 }
 
 impl LockedHandle {
+    /// the device pointer, or an error if the device is
+    /// [closed][Handle::close].
+    fn handle(&self) -> Result<rtlsdr_sys::rtlsdr_dev_t, Error> {
+        if self.handle.is_null() {
+            Err(Error::DeviceClosed)
+        }
+        else {
+            Ok(self.handle)
+        }
+    }
+
+    /// called after the sample rate or sampling mode changed. a running async
+    /// read is cancelled right away, so that it's restarted with the new
+    /// settings.
+    fn change_stream(&mut self) {
+        self.stream_generation.fetch_add(1, Ordering::Release);
+        if self.reading_async {
+            let ret = unsafe { rtlsdr_sys::rtlsdr_cancel_async(self.handle) };
+            tracing::debug!(ret, "rtlsdr_cancel_async");
+        }
+    }
+
+    fn close(&mut self) {
+        if !self.handle.is_null() {
+            tracing::debug!("rtlsdr_close");
+            unsafe {
+                rtlsdr_sys::rtlsdr_close(self.handle);
+            }
+            self.handle = null_mut();
+        }
+    }
+
     pub fn get_center_frequency(&mut self) -> Result<u32, Error> {
-        let ret = unsafe { rtlsdr_sys::rtlsdr_get_center_freq(self.handle) };
+        let ret = unsafe { rtlsdr_sys::rtlsdr_get_center_freq(self.handle()?) };
         tracing::debug!(ret, "rtlsdr_get_center_freq");
         if ret == 0 {
             Err(Error::from_lib("rtlsdr_get_center_freq", 0))
@@ -143,9 +465,10 @@ impl LockedHandle {
     }
 
     pub fn set_center_frequency(&mut self, frequency: u32) -> Result<(), Error> {
-        let ret = unsafe { rtlsdr_sys::rtlsdr_set_center_freq(self.handle, frequency) };
+        let ret = unsafe { rtlsdr_sys::rtlsdr_set_center_freq(self.handle()?, frequency) };
         tracing::debug!(ret, frequency, "rtlsdr_set_center_freq");
         if ret == 0 {
+            self.settings.center_frequency = Some(frequency);
             Ok(())
         }
         else {
@@ -157,7 +480,7 @@ impl LockedHandle {
         // this returns 0 if dev is NULL, but it isn't. otherwise it straight up gives
         // us dev->rate. dev->rate might be 0 if the sample rate hasn't been set
         // yet. so should we return a Result, Option, or just plain u32?
-        let ret = unsafe { rtlsdr_sys::rtlsdr_get_sample_rate(self.handle) };
+        let ret = unsafe { rtlsdr_sys::rtlsdr_get_sample_rate(self.handle()?) };
         tracing::trace!(ret, "rtlsdr_get_sample_rate");
         if ret != 0 {
             Ok(ret)
@@ -168,9 +491,11 @@ impl LockedHandle {
     }
 
     pub fn set_sample_rate(&mut self, sample_rate: u32) -> Result<(), Error> {
-        let ret = unsafe { rtlsdr_sys::rtlsdr_set_sample_rate(self.handle, sample_rate) };
+        let ret = unsafe { rtlsdr_sys::rtlsdr_set_sample_rate(self.handle()?, sample_rate) };
         tracing::debug!(ret, sample_rate, "rtlsdr_set_sample_rate");
         if ret == 0 {
+            self.settings.sample_rate = Some(sample_rate);
+            self.change_stream();
             Ok(())
         }
         else {
@@ -180,16 +505,13 @@ impl LockedHandle {
 
     pub fn set_tuner_gain_mode(&mut self, mode: TunerGainMode) -> Result<(), Error> {
         // if the mode is already set, don't set it again
-        if self
-            .tuner_gain_mode
-            .map_or(false, |current| current == mode)
-        {
+        if self.tuner_gain_mode == Some(mode) {
             return Ok(());
         }
 
         let ret = unsafe {
             rtlsdr_sys::rtlsdr_set_tuner_gain_mode(
-                self.handle,
+                self.handle()?,
                 match mode {
                     TunerGainMode::Manual => 1,
                     TunerGainMode::Auto => 0,
@@ -207,7 +529,7 @@ impl LockedHandle {
     }
 
     pub fn get_tuner_gain(&mut self) -> Result<i32, Error> {
-        let ret = unsafe { rtlsdr_sys::rtlsdr_get_tuner_gain(self.handle) };
+        let ret = unsafe { rtlsdr_sys::rtlsdr_get_tuner_gain(self.handle()?) };
         tracing::debug!(ret, "rtlsdr_get_tuner_gain");
         // note: looking at the librtlsdr source it looks like that 0 is also a valid
         // gain value. rtlsdr_get_tuner_gain only fails if the provided dev
@@ -222,9 +544,10 @@ impl LockedHandle {
     }
 
     pub fn set_tuner_gain(&mut self, gain: i32) -> Result<(), Error> {
-        let ret = unsafe { rtlsdr_sys::rtlsdr_set_tuner_gain(self.handle, gain) };
+        let ret = unsafe { rtlsdr_sys::rtlsdr_set_tuner_gain(self.handle()?, gain) };
         tracing::debug!(ret, gain, "rtlsdr_set_tuner_gain");
         if ret == 0 {
+            self.settings.tuner_gain = Some(gain);
             Ok(())
         }
         else {
@@ -233,9 +556,11 @@ impl LockedHandle {
     }
 
     pub fn set_tuner_if_gain(&mut self, stage: i32, gain: i32) -> Result<(), Error> {
-        let ret = unsafe { rtlsdr_sys::rtlsdr_set_tuner_if_gain(self.handle, stage, gain) };
+        let ret = unsafe { rtlsdr_sys::rtlsdr_set_tuner_if_gain(self.handle()?, stage, gain) };
         tracing::debug!(ret, gain, "rtlsdr_set_tuner_if_gain");
         if ret == 0 {
+            self.settings.tuner_if_gains.retain(|(s, _)| *s != stage);
+            self.settings.tuner_if_gains.push((stage, gain));
             Ok(())
         }
         else {
@@ -244,9 +569,10 @@ impl LockedHandle {
     }
 
     pub fn set_tuner_bandwidth(&mut self, bandwidth: u32) -> Result<(), Error> {
-        let ret = unsafe { rtlsdr_sys::rtlsdr_set_tuner_bandwidth(self.handle, bandwidth) };
+        let ret = unsafe { rtlsdr_sys::rtlsdr_set_tuner_bandwidth(self.handle()?, bandwidth) };
         tracing::debug!(ret, bandwidth, "rtlsdr_set_tuner_bandwidth");
         if ret == 0 {
+            self.settings.tuner_bandwidth = Some(bandwidth);
             Ok(())
         }
         else {
@@ -255,9 +581,10 @@ impl LockedHandle {
     }
 
     pub fn set_agc_mode(&mut self, enable: bool) -> Result<(), Error> {
-        let ret = unsafe { rtlsdr_sys::rtlsdr_set_agc_mode(self.handle, enable as i32) };
+        let ret = unsafe { rtlsdr_sys::rtlsdr_set_agc_mode(self.handle()?, enable as i32) };
         tracing::debug!(ret, ?enable, "rtlsdr_set_agc_mode");
         if ret == 0 {
+            self.settings.agc_mode = Some(enable);
             Ok(())
         }
         else {
@@ -266,17 +593,19 @@ impl LockedHandle {
     }
 
     pub fn get_frequency_correction(&mut self) -> Result<i32, Error> {
-        let ret = unsafe { rtlsdr_sys::rtlsdr_get_freq_correction(self.handle) };
+        let ret = unsafe { rtlsdr_sys::rtlsdr_get_freq_correction(self.handle()?) };
         tracing::debug!(ret, "rtlsdr_get_freq_correction");
-        // note: only returns errors for dev=null, and besides 0 is a valid return value
+        // note: only returns errors for dev=null, which we checked. besides 0 is a
+        // valid return value
         Ok(ret)
     }
 
     pub fn set_frequency_correction(&mut self, ppm: i32) -> Result<(), Error> {
-        let ret = unsafe { rtlsdr_sys::rtlsdr_set_freq_correction(self.handle, ppm) };
+        let ret = unsafe { rtlsdr_sys::rtlsdr_set_freq_correction(self.handle()?, ppm) };
         tracing::debug!(ret, ?ppm, "rtlsdr_set_freq_correction");
         // -2 means that this value is already set, so not really an error
         if ret == 0 || ret == -2 {
+            self.settings.frequency_correction = Some(ppm);
             Ok(())
         }
         else {
@@ -285,17 +614,20 @@ impl LockedHandle {
     }
 
     pub fn get_offset_tuning(&mut self) -> Result<bool, Error> {
-        let ret = unsafe { rtlsdr_sys::rtlsdr_get_offset_tuning(self.handle) };
+        let ret = unsafe { rtlsdr_sys::rtlsdr_get_offset_tuning(self.handle()?) };
         tracing::debug!(ret, "rtlsdr_get_offset_tuning");
-        // note: only returns errors for dev=null
-        assert!(ret == 0 || ret == 1);
-        Ok(ret == 1)
+        match ret {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::from_lib("rtlsdr_get_offset_tuning", ret)),
+        }
     }
 
     pub fn set_offset_tuning(&mut self, enable: bool) -> Result<(), Error> {
-        let ret = unsafe { rtlsdr_sys::rtlsdr_set_offset_tuning(self.handle, enable as i32) };
+        let ret = unsafe { rtlsdr_sys::rtlsdr_set_offset_tuning(self.handle()?, enable as i32) };
         tracing::debug!(ret, ?enable, "rtlsdr_set_offset_tuning");
         if ret == 0 {
+            self.settings.offset_tuning = Some(enable);
             Ok(())
         }
         else {
@@ -308,7 +640,7 @@ impl LockedHandle {
         let mut tuner_frequency = 0;
         let ret = unsafe {
             rtlsdr_sys::rtlsdr_get_xtal_freq(
-                self.handle,
+                self.handle()?,
                 &mut rtl_frequency as *mut u32,
                 &mut tuner_frequency as *mut u32,
             )
@@ -319,9 +651,12 @@ impl LockedHandle {
             ?tuner_frequency,
             "rtlsdr_get_xtal_freq"
         );
-        // note: only returns errors for dev=null
-        assert_eq!(ret, 0);
-        Ok((rtl_frequency, tuner_frequency))
+        if ret == 0 {
+            Ok((rtl_frequency, tuner_frequency))
+        }
+        else {
+            Err(Error::from_lib("rtlsdr_get_xtal_freq", ret))
+        }
     }
 
     pub fn set_xtal_frequency(
@@ -330,7 +665,7 @@ impl LockedHandle {
         tuner_frequency: u32,
     ) -> Result<(), Error> {
         let ret = unsafe {
-            rtlsdr_sys::rtlsdr_set_xtal_freq(self.handle, rtl_frequency, tuner_frequency)
+            rtlsdr_sys::rtlsdr_set_xtal_freq(self.handle()?, rtl_frequency, tuner_frequency)
         };
         tracing::debug!(
             ret,
@@ -339,6 +674,7 @@ impl LockedHandle {
             "rtlsdr_set_xtal_freq"
         );
         if ret == 0 {
+            self.settings.xtal_frequency = Some((rtl_frequency, tuner_frequency));
             Ok(())
         }
         else {
@@ -346,27 +682,43 @@ impl LockedHandle {
         }
     }
 
+    /// `pin` is the GPIO the bias-tee is connected to. This is 0 on most
+    /// dongles, e.g. the RTL-SDR Blog V3 and V4.
+    #[cfg(feature = "bias-tee")]
     pub fn set_bias_tee(&mut self, pin: u8, enable: bool) -> Result<(), Error> {
-        // todo: missing from ffi bindings
-
-        /*let _guard = self.control_lock.lock();
-        let ret = unsafe { rtlsdr_sys::rtlsdr_set_bias_tee_gpio(self.handle, pin.into(), enable as i32) };
-        tracing::debug!(ret, ?rtl_frequency, ?tuner_frequency, "rtlsdr_set_bias_tee_gpio");
+        let (function, ret) = if pin == 0 {
+            let ret = unsafe { ffi::rtlsdr_set_bias_tee(self.handle()?, enable as c_int) };
+            ("rtlsdr_set_bias_tee", ret)
+        }
+        else {
+            let ret = unsafe {
+                ffi::rtlsdr_set_bias_tee_gpio(self.handle()?, pin.into(), enable as c_int)
+            };
+            ("rtlsdr_set_bias_tee_gpio", ret)
+        };
+        tracing::debug!(ret, pin, ?enable, function);
         if ret == 0 {
+            self.settings.bias_tee.retain(|(p, _)| *p != pin);
+            self.settings.bias_tee.push((pin, enable));
             Ok(())
         }
         else {
-            Err(Error::from_lib("rtlsdr_set_bias_tee_gpio", ret))
-        }*/
-
-        tracing::warn!(?pin, ?enable, "todo: rtlsdr_set_bias_tee_gpio");
+            Err(Error::from_lib(function, ret))
+        }
+    }
 
-        // we will just return Ok(()) and pretend we set it
-        Ok(())
+    #[cfg(not(feature = "bias-tee"))]
+    pub fn set_bias_tee(&mut self, pin: u8, enable: bool) -> Result<(), Error> {
+        tracing::warn!(
+            pin,
+            ?enable,
+            "bias-tee support is disabled. enable the `bias-tee` feature."
+        );
+        Err(Error::Unsupported)
     }
 
     pub fn get_direct_sampling(&mut self) -> Result<Option<DirectSamplingMode>, Error> {
-        let ret = unsafe { rtlsdr_sys::rtlsdr_get_direct_sampling(self.handle) };
+        let ret = unsafe { rtlsdr_sys::rtlsdr_get_direct_sampling(self.handle()?) };
         tracing::trace!(ret, "rtlsdr_get_direct_sampling");
         match ret {
             0 => Ok(None),
@@ -376,11 +728,12 @@ impl LockedHandle {
         }
     }
 
-    pub fn set_direct_sampling(&mut self, mode: Option<DirectSamplingMode>) -> Result<(), Error> {
+    /// returns whether the mode was changed.
+    pub fn set_direct_sampling(&mut self, mode: Option<DirectSamplingMode>) -> Result<bool, Error> {
         // librtlsdr doesn't do this
         let current = self.get_direct_sampling()?;
         if current == mode {
-            return Ok(());
+            return Ok(false);
         }
 
         let mode_value = match mode {
@@ -388,16 +741,226 @@ impl LockedHandle {
             Some(DirectSamplingMode::I) => 1,
             Some(DirectSamplingMode::Q) => 2,
         };
-        let ret = unsafe { rtlsdr_sys::rtlsdr_set_direct_sampling(self.handle, mode_value) };
+        let ret = unsafe { rtlsdr_sys::rtlsdr_set_direct_sampling(self.handle()?, mode_value) };
         tracing::debug!(ret, ?mode, "rtlsdr_set_direct_sampling");
         if ret == 0 {
-            Ok(())
+            self.settings.direct_sampling = mode;
+            self.change_stream();
+            Ok(true)
         }
         else {
             Err(Error::from_lib("rtlsdr_set_direct_sampling", ret))
         }
     }
 
+    pub fn read_eeprom(&mut self, data: &mut [u8], offset: u8) -> Result<(), Error> {
+        let ret = unsafe {
+            rtlsdr_sys::rtlsdr_read_eeprom(
+                self.handle()?,
+                data.as_mut_ptr(),
+                offset,
+                data.len().try_into().expect("EEPROM read too large"),
+            )
+        };
+        tracing::debug!(ret, offset, len = data.len(), "rtlsdr_read_eeprom");
+        if ret == 0 {
+            Ok(())
+        }
+        else {
+            Err(Error::from_lib("rtlsdr_read_eeprom", ret))
+        }
+    }
+
+    /// note: librtlsdr writes this byte by byte, and waits 5 ms after each
+    /// byte.
+    pub fn write_eeprom(&mut self, data: &[u8], offset: u8) -> Result<(), Error> {
+        let ret = unsafe {
+            rtlsdr_sys::rtlsdr_write_eeprom(
+                self.handle()?,
+                data.as_ptr(),
+                offset,
+                data.len().try_into().expect("EEPROM write too large"),
+            )
+        };
+        tracing::debug!(ret, offset, len = data.len(), "rtlsdr_write_eeprom");
+        if ret == 0 {
+            Ok(())
+        }
+        else {
+            Err(Error::from_lib("rtlsdr_write_eeprom", ret))
+        }
+    }
+
+    /// the gain that was set last, if any.
+    pub fn tuner_gain(&self) -> Option<Gain> {
+        match self.tuner_gain_mode {
+            Some(TunerGainMode::Auto) => Some(Gain::Auto),
+            Some(TunerGainMode::Manual) => self.settings.tuner_gain.map(Gain::ManualValue),
+            None => None,
+        }
+    }
+
+    /// returns the current configuration. most values are queried from
+    /// librtlsdr, which just returns what was set last. the rest is taken
+    /// from the settings we recorded.
+    pub fn config(&mut self) -> DeviceConfig {
+        let tuner_gain = self.tuner_gain();
+        let xtal_frequency = self.get_xtal_frequency().ok();
+
+        DeviceConfig {
+            center_frequency: self.get_center_frequency().ok(),
+            sample_rate: self.get_sample_rate().ok(),
+            tuner_gain,
+            tuner_if_gains: self
+                .settings
+                .tuner_if_gains
+                .iter()
+                .filter_map(|(stage, gain)| {
+                    Some(((*stage).try_into().ok()?, (*gain).try_into().ok()?))
+                })
+                .collect(),
+            tuner_bandwidth: self.settings.tuner_bandwidth.map(Into::into),
+            agc_mode: self.settings.agc_mode,
+            frequency_correction: self.get_frequency_correction().ok(),
+            offset_tuning: self.get_offset_tuning().ok(),
+            rtl_xtal: xtal_frequency.map(|(rtl_frequency, _)| rtl_frequency),
+            tuner_xtal: xtal_frequency.map(|(_, tuner_frequency)| tuner_frequency),
+            bias_tee: self
+                .settings
+                .bias_tee
+                .iter()
+                .find_map(|(pin, enable)| (*pin == 0).then_some(*enable)),
+            direct_sampling: self.get_direct_sampling().ok().flatten(),
+        }
+    }
+
+    /// applies settings to a freshly opened device. failures are only logged,
+    /// since we'd rather have a device with some settings missing than none
+    /// at all.
+    ///
+    /// returns the events for the settings that were restored.
+    fn restore(
+        &mut self,
+        settings: Settings,
+        tuner_gain_mode: Option<TunerGainMode>,
+    ) -> Vec<ConfigEvent> {
+        let mut events = vec![];
+        let mut restored = |setting: &str, result: Result<(), Error>, new_events: &[ConfigEvent]| {
+            match result {
+                Ok(()) => events.extend_from_slice(new_events),
+                Err(error) => tracing::warn!(setting, ?error, "failed to restore setting"),
+            }
+        };
+
+        // the sample rate goes before the bandwidth, since setting it will also
+        // change the bandwidth.
+        if let Some((rtl_frequency, tuner_frequency)) = settings.xtal_frequency {
+            restored(
+                "xtal_frequency",
+                self.set_xtal_frequency(rtl_frequency, tuner_frequency),
+                &[
+                    ConfigEvent::RtlXtal {
+                        frequency: rtl_frequency,
+                    },
+                    ConfigEvent::TunerXtal {
+                        frequency: tuner_frequency,
+                    },
+                ],
+            );
+        }
+        if let Some(sample_rate) = settings.sample_rate {
+            restored(
+                "sample_rate",
+                self.set_sample_rate(sample_rate),
+                &[ConfigEvent::SampleRate { sample_rate }],
+            );
+        }
+        if let Some(ppm) = settings.frequency_correction {
+            restored(
+                "frequency_correction",
+                self.set_frequency_correction(ppm),
+                &[ConfigEvent::FrequencyCorrection { ppm }],
+            );
+        }
+        if let Some(mode) = settings.direct_sampling {
+            restored(
+                "direct_sampling",
+                self.set_direct_sampling(Some(mode)).map(|_| ()),
+                &[ConfigEvent::DirectSampling { mode: Some(mode) }],
+            );
+        }
+        if let Some(enable) = settings.offset_tuning {
+            restored(
+                "offset_tuning",
+                self.set_offset_tuning(enable),
+                &[ConfigEvent::OffsetTuning { enable }],
+            );
+        }
+        if let Some(frequency) = settings.center_frequency {
+            restored(
+                "center_frequency",
+                self.set_center_frequency(frequency),
+                &[ConfigEvent::CenterFrequency { frequency }],
+            );
+        }
+        if let Some(bandwidth) = settings.tuner_bandwidth {
+            restored(
+                "tuner_bandwidth",
+                self.set_tuner_bandwidth(bandwidth),
+                &[ConfigEvent::TunerBandwidth {
+                    bandwidth: bandwidth.into(),
+                }],
+            );
+        }
+        if let Some(mode) = tuner_gain_mode {
+            // in manual mode, the event is sent for the gain
+            restored(
+                "tuner_gain_mode",
+                self.set_tuner_gain_mode(mode),
+                (mode == TunerGainMode::Auto)
+                    .then_some(ConfigEvent::TunerGain { gain: Gain::Auto })
+                    .as_slice(),
+            );
+        }
+        if let (Some(TunerGainMode::Manual), Some(gain)) = (tuner_gain_mode, settings.tuner_gain) {
+            restored(
+                "tuner_gain",
+                self.set_tuner_gain(gain),
+                &[ConfigEvent::TunerGain {
+                    gain: Gain::ManualValue(gain),
+                }],
+            );
+        }
+        for (stage, gain) in settings.tuner_if_gains {
+            let event = stage
+                .try_into()
+                .ok()
+                .zip(gain.try_into().ok())
+                .map(|(stage, gain)| ConfigEvent::TunerIfGain { stage, gain });
+            restored(
+                "tuner_if_gain",
+                self.set_tuner_if_gain(stage, gain),
+                event.as_slice(),
+            );
+        }
+        if let Some(enable) = settings.agc_mode {
+            restored(
+                "agc_mode",
+                self.set_agc_mode(enable),
+                &[ConfigEvent::AgcMode { enable }],
+            );
+        }
+        for (pin, enable) in settings.bias_tee {
+            restored(
+                "bias_tee",
+                self.set_bias_tee(pin, enable),
+                &[ConfigEvent::BiasTee { pin, enable }],
+            );
+        }
+
+        events
+    }
+
     /// this is synchronized with the rest of the methods on this.
     /// initially it wasn't to allow for asynchronous control and sampling. but
     /// i believe it's better this was, as this way we know exactly what state
@@ -409,7 +972,7 @@ impl LockedHandle {
 
         let ret = unsafe {
             rtlsdr_sys::rtlsdr_read_sync(
-                self.handle,
+                self.handle()?,
                 buffer.as_mut_ptr() as *mut c_void,
                 buffer
                     .len()
@@ -428,6 +991,23 @@ impl LockedHandle {
     }
 }
 
+/// settings that were applied to a [`LockedHandle`]. `None` means the setting
+/// wasn't changed from the default.
+#[derive(Clone, Debug, Default)]
+struct Settings {
+    center_frequency: Option<u32>,
+    sample_rate: Option<u32>,
+    tuner_gain: Option<i32>,
+    tuner_if_gains: Vec<(i32, i32)>,
+    tuner_bandwidth: Option<u32>,
+    agc_mode: Option<bool>,
+    frequency_correction: Option<i32>,
+    offset_tuning: Option<bool>,
+    xtal_frequency: Option<(u32, u32)>,
+    bias_tee: Vec<(u8, bool)>,
+    direct_sampling: Option<DirectSamplingMode>,
+}
+
 // todo: we could make this type public
 #[derive(Clone, Copy)]
 pub(crate) struct TunerGains {
@@ -463,3 +1043,54 @@ impl Debug for TunerGains {
         f.debug_list().entries(self.iter()).finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn closed_handle() -> LockedHandle {
+        LockedHandle {
+            handle: null_mut(),
+            tuner_gain_mode: Some(TunerGainMode::Auto),
+            settings: Settings {
+                agc_mode: Some(true),
+                ..Default::default()
+            },
+            stream_generation: Default::default(),
+            reading_async: false,
+        }
+    }
+
+    #[test]
+    fn a_closed_handle_reports_its_recorded_settings() {
+        let config = closed_handle().config();
+
+        assert_eq!(config.tuner_gain, Some(Gain::Auto));
+        assert_eq!(config.agc_mode, Some(true));
+        assert_eq!(config.center_frequency, None);
+        assert_eq!(config.rtl_xtal, None);
+        assert_eq!(config.offset_tuning, None);
+    }
+
+    #[test]
+    fn operations_on_a_closed_handle_fail() {
+        let mut handle = closed_handle();
+
+        assert!(matches!(
+            handle.get_xtal_frequency(),
+            Err(Error::DeviceClosed)
+        ));
+        assert!(matches!(
+            handle.get_offset_tuning(),
+            Err(Error::DeviceClosed)
+        ));
+        assert!(matches!(
+            handle.set_center_frequency(100_000_000),
+            Err(Error::DeviceClosed)
+        ));
+        assert!(matches!(
+            handle.read_sync(&mut [0; 512]),
+            Err(Error::DeviceClosed)
+        ));
+    }
+}
@@ -5,13 +5,23 @@
 //! [1]: https://gitea.osmocom.org/sdr/rtl-sdr
 
 mod buffer_queue;
+mod builder;
+mod config;
 mod control;
+pub mod ddc;
+pub mod eeprom;
 mod enumerate;
+pub mod file;
 mod handle;
+mod pacing;
 mod sampling;
+mod supervisor;
 
 #[cfg(feature = "tcp")]
 pub mod rtl_tcp;
+#[cfg(feature = "sigmf")]
+pub mod sigmf;
+pub mod simulated;
 
 use std::{
     fmt::Debug,
@@ -26,6 +36,7 @@ use std::{
         Context,
         Poll,
     },
+    time::Instant,
 };
 
 use bytemuck::{
@@ -36,16 +47,32 @@ use futures_util::Stream;
 #[cfg(feature = "num-complex")]
 use num_complex::Complex;
 
-pub use crate::enumerate::{
-    DeviceInfo,
-    DeviceIter,
-    devices,
+pub use crate::{
+    buffer_queue::Backpressure,
+    builder::RtlSdrBuilder,
+    config::{
+        ConfigChanges,
+        ConfigEvent,
+        DeviceConfig,
+    },
+    enumerate::{
+        DeviceFilter,
+        DeviceInfo,
+        DeviceIter,
+        devices,
+    },
+    pacing::Pacing,
+    sampling::ReaderMode,
 };
 use crate::{
-    buffer_queue::Buffer,
+    buffer_queue::{
+        Buffer,
+        Lagged,
+    },
     control::Control,
     handle::Handle,
     sampling::spawn_reader_thread,
+    supervisor::Supervisor,
 };
 
 /// default buffer size is 16 KiB
@@ -76,6 +103,22 @@ pub enum Error {
     Unsupported,
     #[error("invalid gain index: {index}")]
     InvalidGainIndex { index: usize },
+    #[error("invalid buffer size: {buffer_size} (must be a non-zero multiple of 512)")]
+    InvalidBufferSize { buffer_size: usize },
+    #[error("invalid queue size: {queue_size} (must be at least 2)")]
+    InvalidQueueSize { queue_size: usize },
+    #[error("sample stream lagged behind by {chunks} chunks")]
+    Lagged { chunks: u64 },
+    #[error("no device matches {filter}")]
+    NoMatchingDevice { filter: String },
+    #[error("multiple devices match {filter}: {indices:?}")]
+    AmbiguousDevice { filter: String, indices: Vec<u32> },
+    #[error("device {index} has no serial to identify it when it's reconnected")]
+    MissingSerial { index: u32 },
+    #[error("device is closed, because it was lost")]
+    DeviceClosed,
+    #[error("the reopened device has a different tuner")]
+    TunerChanged,
 }
 
 impl Error {
@@ -111,24 +154,49 @@ pub struct RtlSdr {
 impl RtlSdr {
     /// Open an RTL-SDR with the given index.
     ///
-    /// You can enumerate the available devices with [`devices`].
+    /// You can enumerate the available devices with [`devices`]. Use
+    /// [`RtlSdr::builder`] to configure the device before it starts reading.
     pub fn open(index: u32) -> Result<Self, Error> {
-        Self::open_impl(index, DEFAULT_QUEUE_SIZE, DEFAULT_BUFFER_SIZE)
+        RtlSdrBuilder::new().open(index)
+    }
+
+    /// Open the RTL-SDR with the given USB serial number.
+    ///
+    /// The serial is matched literally. Returns an error if no device, or more
+    /// than one device has this serial. Use [`DeviceFilter`] for more flexible
+    /// matching.
+    pub fn open_by_serial(serial: &str) -> Result<Self, Error> {
+        DeviceFilter::new().with_exact_serial(serial).open()
+    }
+
+    /// Returns a builder to configure the device before it's opened.
+    pub fn builder() -> RtlSdrBuilder {
+        RtlSdrBuilder::new()
     }
 
     /// `buffer_size` must be somewhat carefully chosen. from the librtlsdr doc
     /// it seems like it must be at least a multiple of 512, and should really
-    /// be a multiple of 16KiB
-    fn open_impl(index: u32, queue_size: usize, buffer_size: usize) -> Result<Self, Error> {
-        let handle = Arc::new(Handle::open(index)?);
+    /// be a multiple of 16KiB. this is checked by the builder.
+    ///
+    /// If a `supervisor` is given, the device is reopened when it's
+    /// disconnected.
+    pub(crate) fn from_handle(
+        handle: Handle,
+        queue_size: usize,
+        buffer_size: usize,
+        reader_mode: ReaderMode,
+        supervisor: Option<Supervisor>,
+    ) -> Self {
+        let handle = Arc::new(handle);
 
         let control = Control::new(handle.clone());
-        let buffer_queue_subscriber = spawn_reader_thread(handle, buffer_size, queue_size);
+        let buffer_queue_subscriber =
+            spawn_reader_thread(handle, buffer_size, queue_size, reader_mode, supervisor);
 
-        Ok(Self {
+        Self {
             control,
             buffer_queue_subscriber,
-        })
+        }
     }
 
     pub async fn get_center_frequency(&self) -> Result<u32, Error> {
@@ -171,8 +239,8 @@ impl RtlSdr {
         self.control.set_tuner_if_gain(stage, gain).await
     }
 
-    pub async fn set_tuner_bandwidth(&self, bandwidth: u32) -> Result<(), Error> {
-        self.control.set_tuner_bandwidth(bandwidth).await
+    pub async fn set_tuner_bandwidth(&self, bandwidth: Bandwidth) -> Result<(), Error> {
+        self.control.set_tuner_bandwidth(bandwidth.into()).await
     }
 
     pub async fn set_agc_mode(&self, enable: bool) -> Result<(), Error> {
@@ -211,26 +279,110 @@ impl RtlSdr {
         self.control.set_tuner_xtal(frequency).await
     }
 
+    /// Enables the bias-tee on GPIO 0, which is where it is on most dongles.
+    ///
+    /// This requires the `bias-tee` feature, and returns
+    /// [`Error::Unsupported`] otherwise.
     pub async fn set_bias_tee(&self, enable: bool) -> Result<(), Error> {
-        self.control.set_bias_tee(enable).await
+        self.control.set_bias_tee(0, enable).await
+    }
+
+    /// Enables the bias-tee connected to the given GPIO pin.
+    ///
+    /// This requires the `bias-tee` feature, and returns
+    /// [`Error::Unsupported`] otherwise.
+    pub async fn set_bias_tee_gpio(&self, pin: u8, enable: bool) -> Result<(), Error> {
+        self.control.set_bias_tee(pin, enable).await
+    }
+
+    /// Returns a receiver for changes of the configuration.
+    ///
+    /// This receives an event for every setter that succeeds from now on,
+    /// including the ones called on clones of this [`RtlSdr`].
+    pub fn config_changes(&self) -> ConfigChanges {
+        self.control.config_changes()
+    }
+
+    /// Returns the current configuration.
+    pub async fn get_config(&self) -> Result<DeviceConfig, Error> {
+        self.control.get_config().await
+    }
+
+    /// Reads the contents of the EEPROM.
+    ///
+    /// Use [`Eeprom::config`][eeprom::Eeprom::config] to parse it.
+    pub async fn read_eeprom(&self) -> Result<eeprom::Eeprom, Error> {
+        self.control.read_eeprom().await
+    }
+
+    /// Writes to the EEPROM.
+    ///
+    /// The current contents are first saved to the backup file given in
+    /// `options`, which must not exist yet. Only the bytes that differ from
+    /// them are written. Afterwards the EEPROM is read back to verify the
+    /// write. The changes take effect when the dongle is plugged in again.
+    ///
+    /// Returns the previous contents of the EEPROM.
+    pub async fn write_eeprom(
+        &self,
+        eeprom: &eeprom::Eeprom,
+        options: &eeprom::WriteOptions,
+    ) -> Result<eeprom::Eeprom, eeprom::Error> {
+        let previous = self.control.read_eeprom().await?;
+
+        if let Some(path) = &options.backup {
+            eeprom::save_backup(previous.clone(), path.clone()).await?;
+            tracing::info!(?path, "EEPROM backup saved");
+        }
+
+        let Some((start, end)) = eeprom.changed_range(&previous)
+        else {
+            tracing::info!("EEPROM unchanged");
+            return Ok(previous);
+        };
+
+        if options.dry_run {
+            tracing::info!(start, end, "dry-run: not writing EEPROM");
+            return Ok(previous);
+        }
+
+        self.control
+            .write_eeprom(
+                eeprom.as_bytes()[start..end].to_owned(),
+                start.try_into().expect("EEPROM offset out of range"),
+            )
+            .await?;
+
+        let written = self.control.read_eeprom().await?;
+        if let Some((offset, _)) = written.changed_range(eeprom) {
+            return Err(eeprom::Error::VerifyFailed { offset });
+        }
+
+        Ok(previous)
+    }
+
+    /// Sets the direct sampling mode, or `None` for IQ sampling.
+    ///
+    /// Streams that are already open end, and continue as streams for the new
+    /// sample type where possible.
+    pub async fn set_direct_sampling(&self, mode: Option<DirectSamplingMode>) -> Result<(), Error> {
+        self.control.set_direct_sampling(mode).await
     }
 
     pub async fn samples(&self) -> Result<Samples<Iq>, Error> {
         self.control.set_direct_sampling(None).await?;
-        Ok(Samples {
-            receiver: self.buffer_queue_subscriber.receiver(),
-            sample_type: SampleType::Iq,
-            _phantom: PhantomData,
-        })
+        Ok(Samples::new(
+            self.buffer_queue_subscriber.receiver(),
+            SampleType::Iq,
+        ))
     }
 
     pub async fn direct_samples(&self, mode: DirectSamplingMode) -> Result<Samples<u8>, Error> {
         self.control.set_direct_sampling(Some(mode)).await?;
-        Ok(Samples {
-            receiver: self.buffer_queue_subscriber.receiver(),
-            sample_type: mode.into(),
-            _phantom: PhantomData,
-        })
+        Ok(Samples::new(
+            self.buffer_queue_subscriber.receiver(),
+            mode.into(),
+        ))
     }
 }
 
@@ -248,6 +400,10 @@ impl Backend for RtlSdr {
         }
     }
 
+    async fn config(&self) -> Result<DeviceConfig, Error> {
+        RtlSdr::get_config(self).await
+    }
+
     async fn set_center_frequency(&self, frequency: u32) -> Result<(), Error> {
         RtlSdr::set_center_frequency(self, frequency).await
     }
@@ -260,6 +416,10 @@ impl Backend for RtlSdr {
         RtlSdr::set_tuner_gain(self, gain).await
     }
 
+    async fn set_tuner_bandwidth(&self, bandwidth: Bandwidth) -> Result<(), Error> {
+        RtlSdr::set_tuner_bandwidth(self, bandwidth).await
+    }
+
     async fn set_agc_mode(&self, enable: bool) -> Result<(), Error> {
         RtlSdr::set_agc_mode(self, enable).await
     }
@@ -288,6 +448,14 @@ impl Backend for RtlSdr {
         RtlSdr::set_bias_tee(self, enable).await
     }
 
+    async fn set_bias_tee_gpio(&self, pin: u8, enable: bool) -> Result<(), Error> {
+        RtlSdr::set_bias_tee_gpio(self, pin, enable).await
+    }
+
+    async fn set_direct_sampling(&self, mode: Option<DirectSamplingMode>) -> Result<(), Error> {
+        RtlSdr::set_direct_sampling(self, mode).await
+    }
+
     async fn samples(&self) -> Result<Samples<Iq>, Error> {
         RtlSdr::samples(self).await
     }
@@ -301,12 +469,66 @@ impl Backend for RtlSdr {
 pub struct Samples<T> {
     receiver: buffer_queue::Receiver,
     sample_type: SampleType,
+    /// buffer that was received after the sampling mode changed. it ended this
+    /// stream and is kept around so that it's not lost when switching to a
+    /// stream for the new sample type.
+    pending: Option<Buffer>,
+    /// sequence number of the last chunk, to detect dropped chunks.
+    last_sequence: Option<u64>,
     _phantom: PhantomData<fn() -> T>,
 }
 
-// this could just return the buffers without the Result, because the buffer
-// queue doesn't return errors, but for future compatibility we will make it
-// return Results.
+impl<T> Samples<T> {
+    pub(crate) fn new(receiver: buffer_queue::Receiver, sample_type: SampleType) -> Self {
+        Self {
+            receiver,
+            sample_type,
+            pending: None,
+            last_sequence: None,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Sets what happens if this stream lags behind.
+    ///
+    /// By default this is [`Backpressure::DropOldest`], unless the device
+    /// was configured otherwise. Recordings default to
+    /// [`Backpressure::Block`].
+    pub fn set_backpressure(&mut self, backpressure: Backpressure) {
+        self.receiver.set_backpressure(backpressure);
+    }
+
+    pub fn with_backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.set_backpressure(backpressure);
+        self
+    }
+
+    /// If this stream ended because the sampling mode changed, returns the new
+    /// sample type.
+    #[cfg(feature = "tcp")]
+    pub(crate) fn switched_sample_type(&self) -> Option<SampleType> {
+        self.pending.as_ref().map(|buffer| buffer.sample_type)
+    }
+
+    /// If this stream ended because the sampling mode changed, returns a
+    /// stream that continues with the new sample type. No samples are lost.
+    #[cfg(feature = "tcp")]
+    pub(crate) fn switch<U>(&mut self) -> Option<Samples<U>> {
+        let pending = self.pending.take()?;
+        Some(Samples {
+            receiver: self.receiver.clone(),
+            sample_type: pending.sample_type,
+            pending: Some(pending),
+            last_sequence: self.last_sequence,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+// if the consumer lags behind, chunks are dropped by default. this is not an
+// error, since the stream can continue. instead each chunk reports how many
+// chunks were dropped before it. only with `Backpressure::Error` the stream
+// yields an error.
 impl<T> Stream for Samples<T> {
     type Item = Result<Chunk<T>, Error>;
 
@@ -314,22 +536,39 @@ impl<T> Stream for Samples<T> {
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Option<Result<Chunk<T>, Error>>> {
-        match Pin::new(&mut self.receiver).poll_next(cx) {
-            Poll::Pending => Poll::Pending,
-            Poll::Ready(None) => Poll::Ready(None),
-            //Poll::Ready(Some(Err(error))) => Poll::Ready(Some(Err(error))),
-            Poll::Ready(Some(buffer)) => {
-                if self.sample_type == buffer.sample_type {
-                    Poll::Ready(Some(Ok(Chunk {
-                        buffer,
-                        _phantom: PhantomData,
-                    })))
-                }
-                else {
-                    // switched sampling mode, so this stream ends
-                    Poll::Ready(None)
+        let buffer = if let Some(buffer) = self.pending.take() {
+            buffer
+        }
+        else {
+            match Pin::new(&mut self.receiver).poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Ready(Some(Ok(buffer))) => buffer,
+                Poll::Ready(Some(Err(Lagged { chunks }))) => {
+                    return Poll::Ready(Some(Err(Error::Lagged { chunks })));
                 }
             }
+        };
+
+        if self.sample_type == buffer.sample_type {
+            let dropped = self.last_sequence.map_or(0, |last_sequence| {
+                buffer.sequence.saturating_sub(last_sequence + 1)
+            });
+            if dropped > 0 {
+                tracing::debug!(dropped, "lagging behind");
+            }
+            self.last_sequence = Some(buffer.sequence);
+
+            Poll::Ready(Some(Ok(Chunk {
+                buffer,
+                dropped,
+                _phantom: PhantomData,
+            })))
+        }
+        else {
+            // switched sampling mode, so this stream ends
+            self.pending = Some(buffer);
+            Poll::Ready(None)
         }
     }
 }
@@ -337,6 +576,7 @@ impl<T> Stream for Samples<T> {
 #[derive(Clone, Debug)]
 pub struct Chunk<T> {
     buffer: Buffer,
+    dropped: u64,
     _phantom: PhantomData<fn() -> T>,
 }
 
@@ -358,11 +598,97 @@ impl<T> Chunk<T> {
         self.buffer.sample_rate
     }
 
+    /// Center frequency in Hz the samples were received at, if known.
+    #[inline]
+    pub fn center_frequency(&self) -> Option<u32> {
+        self.buffer.center_frequency
+    }
+
+    /// Frequency correction in ppm that was in effect, if known.
+    #[inline]
+    pub fn frequency_correction(&self) -> Option<i32> {
+        self.buffer.frequency_correction
+    }
+
+    /// Tuner gain that was in effect, if known.
+    #[inline]
+    pub fn tuner_gain(&self) -> Option<Gain> {
+        self.buffer.tuner_gain
+    }
+
+    /// Sequence number of this chunk.
+    ///
+    /// This counts the chunks that were produced, not the ones that were
+    /// received, so a gap means that chunks were dropped.
+    #[inline]
+    pub fn sequence(&self) -> u64 {
+        self.buffer.sequence
+    }
+
+    /// Number of chunks that were dropped right before this chunk, because
+    /// the stream wasn't polled fast enough.
+    #[inline]
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Whether samples are missing right before this chunk for reasons other
+    /// than the stream lagging behind, e.g. because the device was unplugged
+    /// and reopened, or a recording was seeked or looped.
+    ///
+    /// The [`timestamp`][Self::timestamp] tells how long the gap was, but the
+    /// [`sample_index`][Self::sample_index] doesn't account for it.
+    #[inline]
+    pub fn discontinuity(&self) -> bool {
+        self.buffer.discontinuity
+    }
+
+    /// Whether a setting was changed right before this chunk, e.g. because the
+    /// receiver was retuned.
+    ///
+    /// The change took effect somewhere around the first sample of this chunk.
+    /// How precise this is depends on the backend.
+    #[inline]
+    pub fn config_changed(&self) -> bool {
+        self.buffer.config_changed
+    }
+
+    /// Monotonic time at which the chunk was captured.
+    ///
+    /// This is taken when the read that filled the chunk returned, so it
+    /// roughly corresponds to the time of the last sample. Use
+    /// [`sample_index`][Self::sample_index] and the sample rate to get
+    /// more accurate times for individual samples.
+    #[inline]
+    pub fn timestamp(&self) -> Instant {
+        self.buffer.timestamp
+    }
+
+    /// Index of the first sample in this chunk, counted from when the stream
+    /// started.
+    ///
+    /// This keeps counting across retunes and sample rate changes, and also
+    /// counts samples in dropped chunks, so it can be used to align chunks.
+    #[inline]
+    pub fn sample_index(&self) -> u64 {
+        self.buffer.sample_index
+    }
+
     #[inline]
     pub fn as_bytes(&self) -> &[u8] {
         self.buffer.filled()
     }
 
+    /// Reinterprets the chunk as raw bytes.
+    #[inline]
+    pub fn into_bytes(self) -> Chunk<u8> {
+        Chunk {
+            buffer: self.buffer,
+            dropped: self.dropped,
+            _phantom: PhantomData,
+        }
+    }
+
     #[inline]
     pub fn slice(&mut self, range: impl RangeBounds<usize>) {
         let width = size_of::<T>();
@@ -398,8 +724,8 @@ where
     F: FnMut(&T) -> U,
 {
     (
-        map_bound(bounds.start_bound().clone(), &mut f),
-        map_bound(bounds.end_bound().clone(), &mut f),
+        map_bound(bounds.start_bound(), &mut f),
+        map_bound(bounds.end_bound(), &mut f),
     )
 }
 
@@ -415,7 +741,7 @@ impl<T: Pod> AsRef<[T]> for Chunk<T> {
 /// reference][1] on the format.
 ///
 /// [1]: https://k3xec.com/packrat-processing-iq/
-#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Pod, Zeroable)]
 #[repr(C)]
 pub struct Iq {
     /// I: in-phase / real component
@@ -449,13 +775,62 @@ fn u8_to_f32(x: u8) -> f32 {
 /// RTL-SDR backend.
 ///
 /// This is basically all the methods shared by
-/// [`RtlTcpClient`][crate::rtl_tcp::client::RtlTcpClient], and [`RtlSdr`], so
-/// that they can be used interchangeably.
+/// [`RtlTcpClient`][crate::rtl_tcp::client::RtlTcpClient],
+/// [`SimulatedSdr`][crate::simulated::SimulatedSdr],
+/// [`FileBackend`][crate::file::FileBackend] and [`RtlSdr`], so that they can
+/// be used interchangeably.
 pub trait Backend {
     type Error: std::error::Error + Send;
 
     fn dongle_info(&self) -> DongleInfo;
 
+    /// Returns a snapshot of the current configuration.
+    fn config(&self) -> impl Future<Output = Result<DeviceConfig, Self::Error>> + Send + Sync;
+
+    /// Tuner frequency in Hz, if known.
+    fn center_frequency(
+        &self,
+    ) -> impl Future<Output = Result<Option<u32>, Self::Error>> + Send + Sync
+    where
+        Self: Sync,
+    {
+        async move { Ok(self.config().await?.center_frequency) }
+    }
+
+    /// Sample rate in Hz, if known.
+    fn sample_rate(&self) -> impl Future<Output = Result<Option<u32>, Self::Error>> + Send + Sync
+    where
+        Self: Sync,
+    {
+        async move { Ok(self.config().await?.sample_rate) }
+    }
+
+    /// Tuner gain, if known.
+    fn tuner_gain(&self) -> impl Future<Output = Result<Option<Gain>, Self::Error>> + Send + Sync
+    where
+        Self: Sync,
+    {
+        async move { Ok(self.config().await?.tuner_gain) }
+    }
+
+    /// Frequency correction in ppm, if known.
+    fn frequency_correction(
+        &self,
+    ) -> impl Future<Output = Result<Option<i32>, Self::Error>> + Send + Sync
+    where
+        Self: Sync,
+    {
+        async move { Ok(self.config().await?.frequency_correction) }
+    }
+
+    /// Whether offset tuning is enabled, if known.
+    fn offset_tuning(&self) -> impl Future<Output = Result<Option<bool>, Self::Error>> + Send + Sync
+    where
+        Self: Sync,
+    {
+        async move { Ok(self.config().await?.offset_tuning) }
+    }
+
     /// Set tuner frequency in Hz
     fn set_center_frequency(
         &self,
@@ -474,6 +849,12 @@ pub trait Backend {
         gain: Gain,
     ) -> impl Future<Output = Result<(), Self::Error>> + Send + Sync;
 
+    /// Set the bandwidth of the tuner's IF filter
+    fn set_tuner_bandwidth(
+        &self,
+        bandwidth: Bandwidth,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send + Sync;
+
     /// Set the automatic gain correction, a software step to correct the
     /// incoming signal, this is not automatic gain control on the hardware
     /// chip, that is controlled by tuner gain mode.
@@ -513,6 +894,23 @@ pub trait Backend {
         enable: bool,
     ) -> impl Future<Output = Result<(), Self::Error>> + Send + Sync;
 
+    /// Enable the bias-tee connected to the given GPIO pin
+    fn set_bias_tee_gpio(
+        &self,
+        pin: u8,
+        enable: bool,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send + Sync;
+
+    /// Set the direct sampling mode, or `None` for IQ sampling.
+    ///
+    /// This is what [`samples`][Self::samples] and
+    /// [`direct_samples`][Self::direct_samples] do before they return a
+    /// stream.
+    fn set_direct_sampling(
+        &self,
+        mode: Option<DirectSamplingMode>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send + Sync;
+
     fn samples(&self) -> impl Future<Output = Result<Samples<Iq>, Self::Error>> + Send + Sync;
 
     fn direct_samples(
@@ -534,6 +932,36 @@ pub enum Gain {
     Auto,
 }
 
+/// Bandwidth of the tuner's IF filter
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Bandwidth {
+    /// Bandwidth in Hz
+    Manual(u32),
+    /// The bandwidth is chosen to match the sample rate.
+    Auto,
+}
+
+impl From<Bandwidth> for u32 {
+    /// librtlsdr, and the `rtl_tcp` command, use 0 for automatic bandwidth.
+    fn from(value: Bandwidth) -> Self {
+        match value {
+            Bandwidth::Manual(bandwidth) => bandwidth,
+            Bandwidth::Auto => 0,
+        }
+    }
+}
+
+impl From<u32> for Bandwidth {
+    fn from(value: u32) -> Self {
+        if value == 0 {
+            Self::Auto
+        }
+        else {
+            Self::Manual(value)
+        }
+    }
+}
+
 /// Tuner gain mode
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum TunerGainMode {
@@ -545,8 +973,7 @@ pub enum TunerGainMode {
 
 /// Direct sampling mode
 ///
-/// Direct sampling is not yet supported by [`RtlSdr`], but it can be used with
-/// [`rtl_tcp`].
+/// See [`RtlSdr::set_direct_sampling`] and [`RtlSdr::direct_samples`].
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum DirectSamplingMode {
     /// Direct sampling of I branch
@@ -596,25 +1023,51 @@ impl TunerType {
     pub fn is_r82xx(&self) -> bool {
         matches!(*self, TunerType::R828D | TunerType::R820T)
     }
+
+    /// Name of the tuner, e.g. `"R820T"`, or `None` if the tuner type isn't
+    /// known to us.
+    pub fn name(&self) -> Option<&'static str> {
+        match *self {
+            Self::UNKNOWN => Some("UNKNOWN"),
+            Self::E4000 => Some("E4000"),
+            Self::FC0012 => Some("FC0012"),
+            Self::FC0013 => Some("FC0013"),
+            Self::FC2580 => Some("FC2580"),
+            Self::R820T => Some("R820T"),
+            Self::R828D => Some("R828D"),
+            _ => None,
+        }
+    }
+
+    /// Inverse of [`Self::name`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Self::UNKNOWN,
+            Self::E4000,
+            Self::FC0012,
+            Self::FC0013,
+            Self::FC2580,
+            Self::R820T,
+            Self::R828D,
+        ]
+        .into_iter()
+        .find(|tuner_type| tuner_type.name() == Some(name))
+    }
 }
 
 impl Debug for TunerType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match *self {
-            Self::UNKNOWN => write!(f, "TunerType::UNKNOWN"),
-            Self::E4000 => write!(f, "TunerType::E4000"),
-            Self::FC0012 => write!(f, "TunerType::FC0012"),
-            Self::FC0013 => write!(f, "TunerType::FC0013"),
-            Self::FC2580 => write!(f, "TunerType::FC2580"),
-            Self::R820T => write!(f, "TunerType::R820T"),
-            Self::R828D => write!(f, "TunerType::R828D"),
-            _ => write!(f, "TunerType({})", self.0),
+        if let Some(name) = self.name() {
+            write!(f, "TunerType::{name}")
+        }
+        else {
+            write!(f, "TunerType({})", self.0)
         }
     }
 }
 
 /// Information about the SDR dongle that is sent by the server.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct DongleInfo {
     /// Tuner type as reported by librtlsdr
     pub tuner_type: TunerType,
@@ -622,3 +1075,36 @@ pub struct DongleInfo {
     /// Number of gain levels supported by the tuner.
     pub tuner_gain_count: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use futures_util::TryStreamExt;
+
+    use super::*;
+
+    fn push(sender: &mut buffer_queue::Sender, num_buffers: usize) {
+        let mut buffer = sender.swap_buffers(None, 16, false).unwrap();
+        for _ in 0..num_buffers {
+            buffer.end = 16;
+            buffer = sender.swap_buffers(Some(buffer), 16, false).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn chunks_report_drops() {
+        let (mut sender, subscriber) = buffer_queue::channel(4);
+        let mut samples = Samples::<Iq>::new(subscriber.receiver(), SampleType::Iq);
+
+        push(&mut sender, 2);
+        let chunk = samples.try_next().await.unwrap().unwrap();
+        assert_eq!((chunk.sequence(), chunk.dropped()), (0, 0));
+
+        // overflow the queue. the sender always holds one buffer to fill, so
+        // only the last 3 buffers are kept.
+        push(&mut sender, 6);
+        let chunk = samples.try_next().await.unwrap().unwrap();
+        assert_eq!((chunk.sequence(), chunk.dropped()), (5, 4));
+        let chunk = samples.try_next().await.unwrap().unwrap();
+        assert_eq!((chunk.sequence(), chunk.dropped()), (6, 0));
+    }
+}
@@ -0,0 +1,75 @@
+use std::{
+    thread,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+/// How fast a synthetic source produces samples.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Pacing {
+    /// Produce samples at the configured sample rate, like a real device
+    /// would.
+    #[default]
+    RealTime,
+    /// Produce samples as fast as the consumers can take them.
+    Unpaced,
+}
+
+/// Keeps a producer thread in step with the sample rate.
+///
+/// This counts the samples produced since the last change of the sample rate
+/// and sleeps until the wall clock catches up with them. If the producer falls
+/// behind, e.g. because it waited for the consumers, it starts counting again.
+#[derive(Debug)]
+pub(crate) struct Pacer {
+    pacing: Pacing,
+    start: Instant,
+    sample_rate: u32,
+    num_samples: u64,
+}
+
+impl Pacer {
+    pub fn new(pacing: Pacing) -> Self {
+        Self {
+            pacing,
+            start: Instant::now(),
+            sample_rate: 0,
+            num_samples: 0,
+        }
+    }
+
+    /// Call this after `num_samples` samples at `sample_rate` were produced.
+    pub fn wait(&mut self, num_samples: usize, sample_rate: u32) {
+        if self.pacing == Pacing::Unpaced || sample_rate == 0 {
+            return;
+        }
+
+        if sample_rate != self.sample_rate {
+            // the sample rate changed, so we start counting again.
+            self.start = Instant::now();
+            self.sample_rate = sample_rate;
+            self.num_samples = 0;
+        }
+
+        self.num_samples += num_samples as u64;
+
+        let duration = |num_samples: u64| {
+            Duration::from_secs_f64(num_samples as f64 / f64::from(self.sample_rate))
+        };
+        let mut deadline = self.start + duration(self.num_samples);
+        let now = Instant::now();
+        if deadline + duration(num_samples as u64) < now {
+            // we're behind by more than this buffer, because the consumers
+            // made the producer wait. catching up would produce the backlog in
+            // one burst, so we start counting again.
+            self.start = now;
+            self.num_samples = num_samples as u64;
+            deadline = now + duration(self.num_samples);
+        }
+        if deadline > now {
+            thread::sleep(deadline - now);
+        }
+    }
+}
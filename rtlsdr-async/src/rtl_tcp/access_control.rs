@@ -0,0 +1,637 @@
+//! Access control for [`RtlTcpServer`][super::server::RtlTcpServer].
+//!
+//! [`AccessControlHandler`] wraps another [`Handler`] and enforces an
+//! [`AccessPolicy`]: Connections are only accepted from allowed addresses and
+//! up to a maximum number of clients, and commands that the policy doesn't
+//! allow are ignored.
+
+use std::{
+    collections::HashSet,
+    net::{
+        IpAddr,
+        SocketAddr,
+    },
+    ops::RangeInclusive,
+    str::FromStr,
+    sync::Arc,
+};
+
+use parking_lot::Mutex;
+
+use crate::{
+    DeviceConfig,
+    DongleInfo,
+    rtl_tcp::{
+        Command,
+        CommandKind,
+        server::{
+            ConnectionHandler,
+            Handler,
+        },
+    },
+};
+
+/// A block of IP addresses in CIDR notation, e.g. `192.168.0.0/16`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    address: IpAddr,
+    prefix_length: u8,
+}
+
+impl Cidr {
+    /// # Panics
+    ///
+    /// Panics if `prefix_length` is longer than the address.
+    pub fn new(address: IpAddr, prefix_length: u8) -> Self {
+        assert!(
+            prefix_length <= max_prefix_length(&address),
+            "prefix length too long"
+        );
+        Self {
+            address,
+            prefix_length,
+        }
+    }
+
+    pub fn address(&self) -> IpAddr {
+        self.address
+    }
+
+    pub fn prefix_length(&self) -> u8 {
+        self.prefix_length
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        // clients connecting to a dual-stack socket via IPv4 have IPv4-mapped
+        // IPv6 addresses.
+        match (self.address, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_length))
+                    .unwrap_or_default();
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_length))
+                    .unwrap_or_default();
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<IpAddr> for Cidr {
+    /// A block containing only `address`.
+    fn from(address: IpAddr) -> Self {
+        Self::new(address, max_prefix_length(&address))
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = InvalidCidr;
+
+    /// Parses a block like `10.0.0.0/8`. A single address without prefix
+    /// length is also accepted.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidCidr(s.to_owned());
+
+        if let Some((address, prefix_length)) = s.split_once('/') {
+            let address: IpAddr = address.parse().map_err(|_| invalid())?;
+            let prefix_length: u8 = prefix_length.parse().map_err(|_| invalid())?;
+            if prefix_length > max_prefix_length(&address) {
+                return Err(invalid());
+            }
+            Ok(Self::new(address, prefix_length))
+        }
+        else {
+            let address: IpAddr = s.parse().map_err(|_| invalid())?;
+            Ok(address.into())
+        }
+    }
+}
+
+fn max_prefix_length(address: &IpAddr) -> u8 {
+    match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/// Error for when a [`Cidr`] can't be parsed.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[error("Invalid CIDR block: {0}")]
+pub struct InvalidCidr(pub String);
+
+/// Who may connect, and what they may do.
+///
+/// The default policy allows everything.
+#[derive(Clone, Debug, Default)]
+pub struct AccessPolicy {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+    max_clients: Option<usize>,
+    first_client_controls: bool,
+    allowed_commands: Option<HashSet<CommandKind>>,
+    frequency_range: Option<RangeInclusive<u32>>,
+}
+
+impl AccessPolicy {
+    /// Only accept connections from addresses in `block`.
+    ///
+    /// This can be called multiple times to allow multiple blocks. If it's
+    /// never called, all addresses that aren't denied are allowed.
+    pub fn with_allow(mut self, block: Cidr) -> Self {
+        self.allow.push(block);
+        self
+    }
+
+    /// Reject connections from addresses in `block`.
+    ///
+    /// This takes precedence over [`with_allow`][Self::with_allow].
+    pub fn with_deny(mut self, block: Cidr) -> Self {
+        self.deny.push(block);
+        self
+    }
+
+    /// Maximum number of clients that can be connected at the same time.
+    pub fn with_max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = Some(max_clients);
+        self
+    }
+
+    /// Only one client at a time can send commands, and all others are
+    /// read-only.
+    ///
+    /// The first client gets control. When it disconnects, the next client
+    /// that sends a command takes over.
+    ///
+    /// Default is `false`.
+    pub fn with_first_client_controls(mut self, enable: bool) -> Self {
+        self.first_client_controls = enable;
+        self
+    }
+
+    /// Only allow these commands. By default all commands are allowed.
+    ///
+    /// Commands that [`Command`] doesn't know, e.g. vendor extensions, aren't
+    /// allowed then either. They're left to the server's
+    /// [`UnknownCommandPolicy`][super::server::UnknownCommandPolicy].
+    pub fn with_allowed_commands(
+        mut self,
+        commands: impl IntoIterator<Item = CommandKind>,
+    ) -> Self {
+        self.allowed_commands = Some(commands.into_iter().collect());
+        self
+    }
+
+    /// Only allow tuning to center frequencies in `range`.
+    pub fn with_frequency_range(mut self, range: RangeInclusive<u32>) -> Self {
+        self.frequency_range = Some(range);
+        self
+    }
+
+    pub fn is_address_allowed(&self, address: IpAddr) -> bool {
+        !self.deny.iter().any(|block| block.contains(address))
+            && (self.allow.is_empty() || self.allow.iter().any(|block| block.contains(address)))
+    }
+
+    /// Whether the policy allows commands that [`Command`] doesn't know, for
+    /// clients that are allowed to send commands at all.
+    pub fn is_unknown_command_allowed(&self) -> bool {
+        self.allowed_commands.is_none()
+    }
+
+    /// Whether the policy allows `command` for clients that are allowed to
+    /// send commands at all.
+    pub fn is_command_allowed(&self, command: &Command) -> bool {
+        if let Some(allowed_commands) = &self.allowed_commands
+            && !allowed_commands.contains(&command.kind())
+        {
+            return false;
+        }
+
+        if let (Command::SetCenterFrequency { frequency }, Some(range)) =
+            (command, &self.frequency_range)
+            && !range.contains(frequency)
+        {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// [`Handler`] that enforces an [`AccessPolicy`].
+#[derive(Debug)]
+pub struct AccessControlHandler<H> {
+    handler: H,
+    policy: Arc<AccessPolicy>,
+    clients: Arc<Mutex<Clients>>,
+}
+
+impl<H> AccessControlHandler<H> {
+    pub fn new(handler: H, policy: AccessPolicy) -> Self {
+        Self {
+            handler,
+            policy: Arc::new(policy),
+            clients: Default::default(),
+        }
+    }
+
+    pub fn policy(&self) -> &AccessPolicy {
+        &self.policy
+    }
+
+    /// Number of clients that are currently connected.
+    pub fn num_clients(&self) -> usize {
+        self.clients.lock().num_clients
+    }
+}
+
+impl<H> Handler for AccessControlHandler<H>
+where
+    H: Handler + Send,
+    H::ConnectionHandler: Send,
+{
+    type Error = H::Error;
+    type ConnectionHandler = AccessControlConnectionHandler<H::ConnectionHandler>;
+
+    fn is_address_allowed(&self, address: SocketAddr) -> bool {
+        if !self.policy.is_address_allowed(address.ip()) {
+            tracing::warn!(%address, "rejecting connection from address that is not allowed");
+            return false;
+        }
+
+        // the connection would be rejected once it's established anyway
+        if let Some(max_clients) = self.policy.max_clients
+            && self.clients.lock().num_clients >= max_clients
+        {
+            tracing::warn!(%address, max_clients, "rejecting connection, since there are too many clients");
+            return false;
+        }
+
+        self.handler.is_address_allowed(address)
+    }
+
+    async fn accept_connection(
+        &mut self,
+        address: SocketAddr,
+    ) -> Result<Option<Self::ConnectionHandler>, Self::Error> {
+        if !self.policy.is_address_allowed(address.ip()) {
+            tracing::warn!(%address, "rejecting connection from address that is not allowed");
+            return Ok(None);
+        }
+
+        if let Some(max_clients) = self.policy.max_clients
+            && self.clients.lock().num_clients >= max_clients
+        {
+            tracing::warn!(%address, max_clients, "rejecting connection, since there are too many clients");
+            return Ok(None);
+        }
+
+        let Some(handler) = self.handler.accept_connection(address).await?
+        else {
+            return Ok(None);
+        };
+
+        let id = {
+            let mut clients = self.clients.lock();
+            clients.num_clients += 1;
+            let id = clients.next_id;
+            clients.next_id += 1;
+            if self.policy.first_client_controls && clients.controller.is_none() {
+                clients.controller = Some(id);
+            }
+            id
+        };
+
+        Ok(Some(AccessControlConnectionHandler {
+            handler,
+            policy: self.policy.clone(),
+            clients: self.clients.clone(),
+            id,
+        }))
+    }
+}
+
+/// The connected clients, shared by all connections.
+#[derive(Debug, Default)]
+struct Clients {
+    num_clients: usize,
+    next_id: u64,
+
+    /// the client that controls the dongle, if
+    /// [`AccessPolicy::with_first_client_controls`] is enabled.
+    controller: Option<u64>,
+}
+
+/// The [`ConnectionHandler`] of a [`AccessControlHandler`].
+#[derive(Debug)]
+pub struct AccessControlConnectionHandler<H> {
+    handler: H,
+    policy: Arc<AccessPolicy>,
+    clients: Arc<Mutex<Clients>>,
+    id: u64,
+}
+
+impl<H> AccessControlConnectionHandler<H> {
+    /// Whether this client may send commands at all.
+    ///
+    /// If only one client has control and nobody has it right now, this
+    /// client takes over.
+    pub fn has_control(&self) -> bool {
+        if !self.policy.first_client_controls {
+            return true;
+        }
+
+        let mut clients = self.clients.lock();
+        *clients.controller.get_or_insert(self.id) == self.id
+    }
+}
+
+impl<H> Drop for AccessControlConnectionHandler<H> {
+    fn drop(&mut self) {
+        let mut clients = self.clients.lock();
+        clients.num_clients -= 1;
+        if clients.controller == Some(self.id) {
+            clients.controller = None;
+        }
+    }
+}
+
+impl<H> ConnectionHandler for AccessControlConnectionHandler<H>
+where
+    H: ConnectionHandler + Send,
+{
+    type Error = H::Error;
+
+    fn dongle_info(&self) -> DongleInfo {
+        self.handler.dongle_info()
+    }
+
+    fn config(&self) -> impl Future<Output = Result<Option<DeviceConfig>, Self::Error>> + Send {
+        self.handler.config()
+    }
+
+    async fn handle_command(&mut self, command: Command) -> Result<bool, Self::Error> {
+        if !self.has_control() {
+            tracing::debug!(?command, "ignoring command from read-only client");
+            Ok(false)
+        }
+        else if !self.policy.is_command_allowed(&command) {
+            tracing::warn!(?command, "ignoring command that is not allowed");
+            Ok(false)
+        }
+        else {
+            self.handler.handle_command(command).await
+        }
+    }
+
+    async fn unknown_command(
+        &mut self,
+        command: u8,
+        arguments: [u8; 4],
+    ) -> Result<bool, Self::Error> {
+        // denied commands are not handled, so that the server's
+        // `UnknownCommandPolicy` still applies to them.
+        if !self.has_control() {
+            tracing::debug!(command, "ignoring unknown command from read-only client");
+            Ok(false)
+        }
+        else if !self.policy.is_unknown_command_allowed() {
+            tracing::warn!(command, "ignoring unknown command that is not allowed");
+            Ok(false)
+        }
+        else {
+            self.handler.unknown_command(command, arguments).await
+        }
+    }
+
+    fn read_samples(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> impl Future<Output = Result<usize, Self::Error>> + Send {
+        self.handler.read_samples(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TunerType;
+
+    /// records the commands of all its connections
+    #[derive(Clone, Debug, Default)]
+    struct MockHandler {
+        commands: Arc<Mutex<Vec<(u64, Command)>>>,
+        unknown_commands: Arc<Mutex<Vec<(u64, u8)>>>,
+        next_id: u64,
+    }
+
+    impl Handler for MockHandler {
+        type Error = std::io::Error;
+        type ConnectionHandler = MockConnectionHandler;
+
+        async fn accept_connection(
+            &mut self,
+            _address: SocketAddr,
+        ) -> Result<Option<Self::ConnectionHandler>, Self::Error> {
+            let id = self.next_id;
+            self.next_id += 1;
+            Ok(Some(MockConnectionHandler {
+                id,
+                commands: self.commands.clone(),
+                unknown_commands: self.unknown_commands.clone(),
+            }))
+        }
+    }
+
+    #[derive(Debug)]
+    struct MockConnectionHandler {
+        id: u64,
+        commands: Arc<Mutex<Vec<(u64, Command)>>>,
+        unknown_commands: Arc<Mutex<Vec<(u64, u8)>>>,
+    }
+
+    impl ConnectionHandler for MockConnectionHandler {
+        type Error = std::io::Error;
+
+        fn dongle_info(&self) -> DongleInfo {
+            DongleInfo {
+                tuner_type: TunerType::R820T,
+                tuner_gain_count: 0,
+            }
+        }
+
+        async fn handle_command(&mut self, command: Command) -> Result<bool, Self::Error> {
+            self.commands.lock().push((self.id, command));
+            Ok(true)
+        }
+
+        async fn unknown_command(
+            &mut self,
+            command: u8,
+            _arguments: [u8; 4],
+        ) -> Result<bool, Self::Error> {
+            self.unknown_commands.lock().push((self.id, command));
+            Ok(true)
+        }
+
+        async fn read_samples(&mut self, _buffer: &mut [u8]) -> Result<usize, Self::Error> {
+            Ok(0)
+        }
+    }
+
+    fn address(address: &str) -> SocketAddr {
+        SocketAddr::new(address.parse().unwrap(), 1234)
+    }
+
+    async fn connect(
+        handler: &mut AccessControlHandler<MockHandler>,
+        address: &str,
+    ) -> Option<AccessControlConnectionHandler<MockConnectionHandler>> {
+        handler
+            .accept_connection(self::address(address))
+            .await
+            .unwrap()
+    }
+
+    const SET_FREQUENCY: Command = Command::SetCenterFrequency {
+        frequency: 100_000_000,
+    };
+
+    #[test]
+    fn it_parses_cidr_blocks() {
+        let block: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(block.contains("10.1.2.3".parse().unwrap()));
+        assert!(block.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!block.contains("11.0.0.1".parse().unwrap()));
+
+        let block: Cidr = "fd00::/8".parse().unwrap();
+        assert!(block.contains("fd12::1".parse().unwrap()));
+        assert!(!block.contains("fe80::1".parse().unwrap()));
+
+        let block: Cidr = "127.0.0.1".parse().unwrap();
+        assert_eq!(block.prefix_length(), 32);
+        assert!(block.contains("127.0.0.1".parse().unwrap()));
+        assert!(!block.contains("127.0.0.2".parse().unwrap()));
+
+        assert!(
+            "0.0.0.0/0"
+                .parse::<Cidr>()
+                .unwrap()
+                .contains("1.2.3.4".parse().unwrap())
+        );
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/".parse::<Cidr>().is_err());
+        assert!("localhost".parse::<Cidr>().is_err());
+    }
+
+    #[tokio::test]
+    async fn it_only_accepts_allowed_addresses() {
+        let policy = AccessPolicy::default()
+            .with_allow("10.0.0.0/8".parse().unwrap())
+            .with_deny("10.1.0.0/16".parse().unwrap());
+        let mut handler = AccessControlHandler::new(MockHandler::default(), policy);
+
+        assert!(connect(&mut handler, "10.2.0.1").await.is_some());
+        assert!(connect(&mut handler, "10.1.0.1").await.is_none());
+        assert!(connect(&mut handler, "192.168.0.1").await.is_none());
+
+        // this is checked before the connection is established, too
+        assert!(handler.is_address_allowed(address("10.2.0.1")));
+        assert!(!handler.is_address_allowed(address("10.1.0.1")));
+        assert!(!handler.is_address_allowed(address("192.168.0.1")));
+    }
+
+    #[tokio::test]
+    async fn it_limits_the_number_of_clients() {
+        let policy = AccessPolicy::default().with_max_clients(2);
+        let mut handler = AccessControlHandler::new(MockHandler::default(), policy);
+
+        let first = connect(&mut handler, "127.0.0.1").await.unwrap();
+        let _second = connect(&mut handler, "127.0.0.1").await.unwrap();
+        assert!(connect(&mut handler, "127.0.0.1").await.is_none());
+        assert!(!handler.is_address_allowed(address("127.0.0.1")));
+        assert_eq!(handler.num_clients(), 2);
+
+        drop(first);
+        assert!(handler.is_address_allowed(address("127.0.0.1")));
+        assert!(connect(&mut handler, "127.0.0.1").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn only_the_first_client_controls_the_dongle() {
+        let mock = MockHandler::default();
+        let policy = AccessPolicy::default().with_first_client_controls(true);
+        let mut handler = AccessControlHandler::new(mock.clone(), policy);
+
+        let mut first = connect(&mut handler, "127.0.0.1").await.unwrap();
+        let mut second = connect(&mut handler, "127.0.0.1").await.unwrap();
+        assert!(first.handle_command(SET_FREQUENCY).await.unwrap());
+        assert!(!second.handle_command(SET_FREQUENCY).await.unwrap());
+        assert!(!second.unknown_command(0xff, [0; 4]).await.unwrap());
+        assert_eq!(*mock.commands.lock(), [(0, SET_FREQUENCY)]);
+        assert!(mock.unknown_commands.lock().is_empty());
+
+        // the second client takes over once the first is gone
+        drop(first);
+        second.handle_command(SET_FREQUENCY).await.unwrap();
+        let mut third = connect(&mut handler, "127.0.0.1").await.unwrap();
+        third.handle_command(SET_FREQUENCY).await.unwrap();
+        assert_eq!(
+            *mock.commands.lock(),
+            [(0, SET_FREQUENCY), (1, SET_FREQUENCY)]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_passes_unknown_commands_through_by_default() {
+        let mock = MockHandler::default();
+        let mut handler = AccessControlHandler::new(mock.clone(), AccessPolicy::default());
+        let mut connection = connect(&mut handler, "127.0.0.1").await.unwrap();
+
+        assert!(connection.unknown_command(0xf0, [0; 4]).await.unwrap());
+        assert_eq!(*mock.unknown_commands.lock(), [(0, 0xf0)]);
+    }
+
+    #[tokio::test]
+    async fn it_filters_commands() {
+        let mock = MockHandler::default();
+        let policy = AccessPolicy::default()
+            .with_allowed_commands([CommandKind::SetCenterFrequency, CommandKind::SetSampleRate])
+            .with_frequency_range(88_000_000..=108_000_000);
+        let mut handler = AccessControlHandler::new(mock.clone(), policy);
+        let mut connection = connect(&mut handler, "127.0.0.1").await.unwrap();
+
+        let commands = [
+            Command::SetCenterFrequency {
+                frequency: 100_000_000,
+            },
+            Command::SetCenterFrequency {
+                frequency: 433_920_000,
+            },
+            Command::SetSampleRate {
+                sample_rate: 2_048_000,
+            },
+            Command::SetBiasT { enable: true },
+        ];
+        let mut applied = vec![];
+        for command in commands {
+            applied.push(connection.handle_command(command).await.unwrap());
+        }
+
+        // denied commands are reported as not applied, so no config events are
+        // sent for them.
+        assert_eq!(applied, [true, false, true, false]);
+        assert_eq!(*mock.commands.lock(), [(0, commands[0]), (0, commands[2])]);
+
+        // vendor commands aren't in the list of allowed commands either. they're
+        // reported as not handled, so they count towards the server's
+        // `UnknownCommandPolicy`.
+        assert!(!connection.unknown_command(0xf0, [0; 4]).await.unwrap());
+        assert!(mock.unknown_commands.lock().is_empty());
+    }
+}
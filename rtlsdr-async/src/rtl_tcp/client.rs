@@ -1,4 +1,7 @@
-use std::marker::PhantomData;
+use std::{
+    sync::Arc,
+    time::Instant,
+};
 
 use bytes::Buf;
 use parking_lot::Mutex;
@@ -16,6 +19,7 @@ use tokio::{
         ToSocketAddrs,
     },
     sync::{
+        broadcast,
         mpsc,
         oneshot,
     },
@@ -23,6 +27,10 @@ use tokio::{
 
 use crate::{
     Backend,
+    Bandwidth,
+    ConfigChanges,
+    ConfigEvent,
+    DeviceConfig,
     DirectSamplingMode,
     DongleInfo,
     Gain,
@@ -31,65 +39,138 @@ use crate::{
     Samples,
     TunerType,
     buffer_queue,
+    config::CONFIG_EVENT_QUEUE_SIZE,
     rtl_tcp::{
         BufReadBytesExt,
+        COMMAND_LENGTH,
+        CONFIG_MAGIC,
         Command,
         HEADER_LENGTH,
+        InvalidCommand,
         MAGIC,
         TunerGainMode,
     },
 };
 
-/// size of the read buffer: 8 KiB
-const READ_BUFFER_SIZE: usize = 0x2000;
+/// default size of the read buffer: 8 KiB
+const DEFAULT_READ_BUFFER_SIZE: usize = 0x2000;
 
 /// size of the write buffer: 1 KiB, plenty for a few command
 const WRITE_BUFFER_SIZE: usize = 0x400;
 
 const COMMAND_QUEUE_SIZE: usize = 32;
-const SAMPLE_BUFFER_QUEUE_SIZE: usize = 32;
-const SAMPLE_BUFFER_SIZE: usize = READ_BUFFER_SIZE;
+const DEFAULT_SAMPLE_BUFFER_QUEUE_SIZE: usize = 32;
 
 #[derive(Debug, thiserror::Error)]
 #[error("rtl_tcp client error")]
 pub enum Error {
     Io(#[from] std::io::Error),
     InvalidMagic([u8; 4]),
+    InvalidConfigMagic([u8; 4]),
+    InvalidCommand(#[from] InvalidCommand),
     ConnectionClosed,
+    InvalidBufferSize { buffer_size: usize },
+    InvalidQueueSize { queue_size: usize },
 }
 
-/// A client for `rtl_tcp`
+/// Builder to configure a [`RtlTcpClient`] before connecting.
 #[derive(Clone, Debug)]
-pub struct RtlTcpClient {
-    dongle_info: DongleInfo,
-    command_sender: mpsc::Sender<ControlMessage>,
-    buffer_queue_subscriber: buffer_queue::Subscriber,
+pub struct RtlTcpClientBuilder {
+    queue_size: usize,
+    buffer_size: usize,
+    config_snapshot: bool,
 }
 
-impl RtlTcpClient {
+impl Default for RtlTcpClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RtlTcpClientBuilder {
+    pub fn new() -> Self {
+        Self {
+            queue_size: DEFAULT_SAMPLE_BUFFER_QUEUE_SIZE,
+            buffer_size: DEFAULT_READ_BUFFER_SIZE,
+            config_snapshot: false,
+        }
+    }
+
+    /// Number of buffers in the queue between the connection and the
+    /// [`Samples`] streams. If a stream lags behind by more than this, chunks
+    /// are dropped.
+    ///
+    /// Must be at least 2, since the connection always holds one buffer.
+    /// Default is 32.
+    pub fn with_queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = queue_size;
+        self
+    }
+
+    /// Size of the read buffer, and of each chunk, in bytes.
+    ///
+    /// This must be a non-zero multiple of 2, so that chunks contain whole IQ
+    /// samples. Default is 8 KiB.
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// Expect a config snapshot from the server after the header.
+    ///
+    /// This is an extension to the protocol, and the server must be configured
+    /// to send it (see
+    /// [`RtlTcpServer::with_config_snapshot`][super::server::RtlTcpServer::with_config_snapshot]).
+    /// The snapshot is used to initialize [`RtlTcpClient::config`].
+    ///
+    /// Default is `false`.
+    pub fn with_config_snapshot(mut self, enable: bool) -> Self {
+        self.config_snapshot = enable;
+        self
+    }
+
     /// Connnect to a `rtl_tcp` server.
+    pub async fn connect<A: ToSocketAddrs>(self, address: A) -> Result<RtlTcpClient, Error> {
+        self.validate()?;
+        let tcp = TcpStream::connect(address).await?;
+        self.connect_stream(tcp).await
+    }
+
+    /// Talk to a `rtl_tcp` server over an already established `stream`.
     ///
-    /// This implements [`AsyncReadSamples`] for async reading of IQ samples,
-    /// and [`Configure`] to configure the receiver.
-    pub async fn connect<A: ToSocketAddrs>(address: A) -> Result<Self, Error> {
+    /// This can be used to connect over other transports than plain TCP.
+    pub async fn connect_stream<S>(self, stream: S) -> Result<RtlTcpClient, Error>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        self.validate()?;
+
         let (connect_result_sender, connect_result_receiver) = oneshot::channel();
         let (command_sender, command_receiver) = mpsc::channel(COMMAND_QUEUE_SIZE);
-        let (buffer_queue_sender, buffer_queue_subscriber) =
-            buffer_queue::channel(SAMPLE_BUFFER_QUEUE_SIZE);
+        let (buffer_queue_sender, buffer_queue_subscriber) = buffer_queue::channel(self.queue_size);
 
-        let tcp = TcpStream::connect(address).await?;
+        let shared = Arc::new(Shared {
+            state: Default::default(),
+            config_events: broadcast::Sender::new(CONFIG_EVENT_QUEUE_SIZE),
+        });
 
-        tokio::spawn(async move {
-            if let Err(error) = handle_connection(
-                tcp,
-                connect_result_sender,
-                command_receiver,
-                buffer_queue_sender,
-            )
-            .await
-            {
-                // todo: propagate error correctly
-                tracing::error!(?error);
+        tokio::spawn({
+            let shared = shared.clone();
+            async move {
+                if let Err(error) = handle_connection(
+                    stream,
+                    connect_result_sender,
+                    command_receiver,
+                    buffer_queue_sender,
+                    &shared,
+                    self.buffer_size,
+                    self.config_snapshot,
+                )
+                .await
+                {
+                    // todo: propagate error correctly
+                    tracing::error!(?error);
+                }
             }
         });
 
@@ -99,17 +180,73 @@ impl RtlTcpClient {
 
         tracing::debug!(?dongle_info);
 
-        Ok(Self {
+        Ok(RtlTcpClient {
             dongle_info,
             command_sender,
             buffer_queue_subscriber,
+            shared,
         })
     }
 
+    fn validate(&self) -> Result<(), Error> {
+        if self.buffer_size == 0 || !self.buffer_size.is_multiple_of(2) {
+            return Err(Error::InvalidBufferSize {
+                buffer_size: self.buffer_size,
+            });
+        }
+        if self.queue_size < 2 {
+            return Err(Error::InvalidQueueSize {
+                queue_size: self.queue_size,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A client for `rtl_tcp`
+#[derive(Clone, Debug)]
+pub struct RtlTcpClient {
+    dongle_info: DongleInfo,
+    command_sender: mpsc::Sender<ControlMessage>,
+    buffer_queue_subscriber: buffer_queue::Subscriber,
+    shared: Arc<Shared>,
+}
+
+impl RtlTcpClient {
+    /// Connnect to a `rtl_tcp` server.
+    ///
+    /// This implements [`AsyncReadSamples`] for async reading of IQ samples,
+    /// and [`Configure`] to configure the receiver.
+    pub async fn connect<A: ToSocketAddrs>(address: A) -> Result<Self, Error> {
+        RtlTcpClientBuilder::new().connect(address).await
+    }
+
+    /// Returns a builder to configure the client before connecting.
+    pub fn builder() -> RtlTcpClientBuilder {
+        RtlTcpClientBuilder::new()
+    }
+
     pub fn dongle_info(&self) -> &DongleInfo {
         &self.dongle_info
     }
 
+    /// Returns the config of the server, as far as we know it.
+    ///
+    /// The server doesn't report its config, so this is tracked from the
+    /// commands we sent, starting from the config snapshot if it was enabled
+    /// with [`RtlTcpClientBuilder::with_config_snapshot`].
+    pub fn config(&self) -> DeviceConfig {
+        self.shared.state.lock().config.clone()
+    }
+
+    /// Returns a receiver for changes of the configuration.
+    ///
+    /// This receives an event for every command that was sent successfully,
+    /// including the ones sent by clones of this client.
+    pub fn config_changes(&self) -> ConfigChanges {
+        ConfigChanges::new(self.shared.config_events.subscribe())
+    }
+
     /// Sends a command to the server.
     pub async fn send_command(&self, command: Command) -> Result<(), Error> {
         tracing::debug!(?command, "sending command");
@@ -166,6 +303,15 @@ impl RtlTcpClient {
         Ok(())
     }
 
+    /// Sets the bandwidth of the tuner's IF filter.
+    ///
+    /// This is an extension of the librtlsdr fork's `rtl_tcp`. Other servers
+    /// will ignore it, or close the connection.
+    pub async fn set_tuner_bandwidth(&self, bandwidth: Bandwidth) -> Result<(), Error> {
+        self.send_command(Command::SetTunerBandwidth { bandwidth })
+            .await
+    }
+
     pub async fn set_agc_mode(&self, enable: bool) -> Result<(), Error> {
         self.send_command(Command::SetAgcMode { enable }).await
     }
@@ -196,24 +342,38 @@ impl RtlTcpClient {
         self.send_command(Command::SetBiasT { enable }).await
     }
 
+    /// Enables the bias-tee connected to the given GPIO pin.
+    ///
+    /// This uses [`Command::WriteGpio`], which only servers that support the
+    /// librtlsdr fork's extensions understand.
+    pub async fn set_bias_tee_gpio(&self, pin: u8, enable: bool) -> Result<(), Error> {
+        self.send_command(Command::WriteGpio {
+            pin: pin.into(),
+            value: enable,
+        })
+        .await
+    }
+
+    pub async fn set_direct_sampling(&self, mode: Option<DirectSamplingMode>) -> Result<(), Error> {
+        self.send_command(Command::SetDirectSampling { mode }).await
+    }
+
     pub async fn samples(&self) -> Result<Samples<Iq>, Error> {
         self.send_command(Command::SetDirectSampling { mode: None })
             .await?;
-        Ok(Samples {
-            receiver: self.buffer_queue_subscriber.receiver(),
-            sample_type: SampleType::Iq,
-            _phantom: PhantomData,
-        })
+        Ok(Samples::new(
+            self.buffer_queue_subscriber.receiver(),
+            SampleType::Iq,
+        ))
     }
 
     pub async fn direct_samples(&self, mode: DirectSamplingMode) -> Result<Samples<u8>, Error> {
         self.send_command(Command::SetDirectSampling { mode: Some(mode) })
             .await?;
-        Ok(Samples {
-            receiver: self.buffer_queue_subscriber.receiver(),
-            sample_type: mode.into(),
-            _phantom: PhantomData,
-        })
+        Ok(Samples::new(
+            self.buffer_queue_subscriber.receiver(),
+            mode.into(),
+        ))
     }
 }
 
@@ -224,6 +384,10 @@ impl Backend for RtlTcpClient {
         self.dongle_info
     }
 
+    async fn config(&self) -> Result<DeviceConfig, Error> {
+        Ok(RtlTcpClient::config(self))
+    }
+
     async fn set_center_frequency(&self, frequency: u32) -> Result<(), Error> {
         RtlTcpClient::set_center_frequency(self, frequency).await
     }
@@ -236,6 +400,10 @@ impl Backend for RtlTcpClient {
         RtlTcpClient::set_tuner_gain(self, gain).await
     }
 
+    async fn set_tuner_bandwidth(&self, bandwidth: Bandwidth) -> Result<(), Error> {
+        RtlTcpClient::set_tuner_bandwidth(self, bandwidth).await
+    }
+
     async fn set_agc_mode(&self, enable: bool) -> Result<(), Error> {
         RtlTcpClient::set_agc_mode(self, enable).await
     }
@@ -245,7 +413,7 @@ impl Backend for RtlTcpClient {
     }
 
     async fn set_tuner_if_gain(&self, stage: i16, gain: i16) -> Result<(), Error> {
-        RtlTcpClient::set_tuner_if_gain(self, stage.into(), gain.into()).await
+        RtlTcpClient::set_tuner_if_gain(self, stage, gain).await
     }
 
     async fn set_offset_tuning(&self, enable: bool) -> Result<(), Error> {
@@ -264,6 +432,14 @@ impl Backend for RtlTcpClient {
         RtlTcpClient::set_bias_tee(self, enable).await
     }
 
+    async fn set_bias_tee_gpio(&self, pin: u8, enable: bool) -> Result<(), Error> {
+        RtlTcpClient::set_bias_tee_gpio(self, pin, enable).await
+    }
+
+    async fn set_direct_sampling(&self, mode: Option<DirectSamplingMode>) -> Result<(), Error> {
+        RtlTcpClient::set_direct_sampling(self, mode).await
+    }
+
     async fn samples(&self) -> Result<Samples<Iq>, Error> {
         RtlTcpClient::samples(self).await
     }
@@ -279,17 +455,31 @@ struct ControlMessage {
     result_sender: Option<oneshot::Sender<Result<(), Error>>>,
 }
 
-async fn handle_connection(
-    mut tcp: TcpStream,
+async fn handle_connection<S>(
+    stream: S,
     connect_result_sender: oneshot::Sender<Result<DongleInfo, Error>>,
     command_receiver: mpsc::Receiver<ControlMessage>,
     buffer_queue_sender: buffer_queue::Sender,
-) -> Result<(), Error> {
-    let (tcp_read, tcp_write) = tcp.split();
-    let mut tcp_read = BufReader::with_capacity(READ_BUFFER_SIZE, tcp_read);
+    shared: &Shared,
+    buffer_size: usize,
+    config_snapshot: bool,
+) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (tcp_read, tcp_write) = tokio::io::split(stream);
+    let mut tcp_read = BufReader::with_capacity(buffer_size, tcp_read);
     let tcp_write = BufWriter::with_capacity(WRITE_BUFFER_SIZE, tcp_write);
 
-    match read_dongle_info(&mut tcp_read).await {
+    let result = async {
+        let dongle_info = read_dongle_info(&mut tcp_read).await?;
+        if config_snapshot {
+            shared.state.lock().config = read_config_snapshot(&mut tcp_read).await?;
+        }
+        Ok(dongle_info)
+    };
+
+    match result.await {
         Ok(dongle_info) => {
             let _ = connect_result_sender.send(Ok(dongle_info));
         }
@@ -299,20 +489,28 @@ async fn handle_connection(
         }
     }
 
-    let receiver_state = Mutex::new(Default::default());
-
     tokio::select! {
-        result = forward_commands(command_receiver, tcp_write, &receiver_state) => result?,
-        result = forward_samples(tcp_read, buffer_queue_sender, &receiver_state, SAMPLE_BUFFER_SIZE) => result?,
+        result = forward_commands(command_receiver, tcp_write, shared) => result?,
+        result = forward_samples(tcp_read, buffer_queue_sender, &shared.state, buffer_size) => result?,
     }
 
     Ok(())
 }
 
+/// shared between the client, and the task handling the connection.
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<ReceiverState>,
+    config_events: broadcast::Sender<ConfigEvent>,
+}
+
 #[derive(Debug, Default)]
 struct ReceiverState {
-    sample_rate: u32,
-    sample_type: SampleType,
+    /// config tracked from the commands we sent
+    config: DeviceConfig,
+
+    /// incremented for every command, so we can mark the next buffer.
+    config_generation: u64,
 }
 
 async fn read_dongle_info<R: AsyncRead + Unpin>(mut reader: R) -> Result<DongleInfo, Error> {
@@ -335,10 +533,29 @@ async fn read_dongle_info<R: AsyncRead + Unpin>(mut reader: R) -> Result<DongleI
     })
 }
 
+async fn read_config_snapshot<R: AsyncRead + Unpin>(mut reader: R) -> Result<DeviceConfig, Error> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic).await?;
+    if &magic != CONFIG_MAGIC {
+        return Err(Error::InvalidConfigMagic(magic));
+    }
+
+    let num_commands = reader.read_u32().await?;
+
+    let mut config = DeviceConfig::default();
+    for _ in 0..num_commands {
+        let mut buffer = [0; COMMAND_LENGTH];
+        reader.read_exact(&mut buffer).await?;
+        config.apply_command(&Command::decode(&buffer[..])?);
+    }
+
+    Ok(config)
+}
+
 async fn forward_commands<W: AsyncWrite + Unpin>(
     mut command_receiver: mpsc::Receiver<ControlMessage>,
     mut tcp_write: W,
-    receiver_state: &Mutex<ReceiverState>,
+    shared: &Shared,
 ) -> Result<(), Error> {
     //let mut messages = Vec::with_capacity(COMMAND_QUEUE_SIZE);
 
@@ -360,22 +577,27 @@ async fn forward_commands<W: AsyncWrite + Unpin>(
 
         tracing::debug!(?message);
 
-        match &message.command {
-            Command::SetSampleRate { sample_rate } => {
-                let mut receiver_state = receiver_state.lock();
-                receiver_state.sample_rate = *sample_rate;
-            }
-            Command::SetDirectSampling { mode } => {
-                let mut receiver_state = receiver_state.lock();
-                receiver_state.sample_type = (*mode).into();
+        // update this before sending, so that samples for the new config are
+        // labelled correctly. commands that don't change anything, like
+        // disabling direct sampling before every stream, aren't marked or
+        // reported.
+        let changed = {
+            let mut state = shared.state.lock();
+            let changed = state.config.apply_command(&message.command);
+            if changed {
+                state.config_generation += 1;
             }
-            _ => {}
-        }
+            changed
+        };
 
-        let mut buf = [0; 5];
+        let mut buf = [0; COMMAND_LENGTH];
         message.command.encode(&mut buf[..]);
         match tcp_write.write_all(&buf[..]).await {
             Ok(_) => {
+                if changed && let Some(event) = message.command.config_event() {
+                    // this only fails if nobody is subscribed
+                    let _ = shared.config_events.send(event);
+                }
                 if let Some(result_sender) = message.result_sender {
                     let _ = result_sender.send(Ok(()));
                 }
@@ -398,14 +620,24 @@ async fn forward_commands<W: AsyncWrite + Unpin>(
 async fn forward_samples<R: AsyncRead + Unpin>(
     mut tcp_read: R,
     mut buffer_queue_sender: buffer_queue::Sender,
-    receiver_state: &Mutex<ReceiverState>,
+    state: &Mutex<ReceiverState>,
     buffer_size: usize,
 ) -> Result<(), Error> {
     let mut push_buffer = None;
 
+    // we can't know when exactly the server applied a command, so we mark the
+    // first buffer we receive after sending it.
+    let mut config_generation = state.lock().config_generation;
+
+    // running sample index since we connected
+    let mut sample_index = 0;
+
     loop {
-        let Some(mut buffer) =
-            buffer_queue_sender.swap_buffers(push_buffer.take(), buffer_size, false)
+        // this waits until somebody is reading samples. in the meantime we don't
+        // read from the socket, so the server will stop sending.
+        let Some(mut buffer) = buffer_queue_sender
+            .swap_buffers_async(push_buffer.take(), buffer_size)
+            .await
         else {
             // all receivers and subscribers dropped
             tracing::debug!("all readers dropped. exiting");
@@ -418,10 +650,25 @@ async fn forward_samples<R: AsyncRead + Unpin>(
                 assert_eq!(n_read, buffer_mut.len());
 
                 buffer.end = n_read;
-
-                let receiver_state = receiver_state.lock();
-                buffer.sample_rate = receiver_state.sample_rate;
-                buffer.sample_type = receiver_state.sample_type;
+                buffer.timestamp = Instant::now();
+
+                let state = state.lock();
+                buffer.sample_rate = state.config.sample_rate.unwrap_or_default();
+                buffer.sample_type = state.config.direct_sampling.into();
+                buffer.center_frequency = state
+                    .config
+                    .direct_sampling
+                    .is_none()
+                    .then_some(state.config.center_frequency)
+                    .flatten();
+                buffer.frequency_correction = state.config.frequency_correction;
+                buffer.tuner_gain = state.config.tuner_gain;
+                buffer.config_changed =
+                    std::mem::replace(&mut config_generation, state.config_generation)
+                        != state.config_generation;
+
+                buffer.sample_index = sample_index;
+                sample_index += buffer.num_samples() as u64;
 
                 push_buffer = Some(buffer);
             }
@@ -6,6 +6,8 @@
 //! [1]: https://k3xec.com/rtl-tcp/
 //! [2]: https://github.com/rtlsdrblog/rtl-sdr-blog/blob/master/src/rtl_tcp.c
 
+use std::str::FromStr;
+
 use bytes::{
     Buf,
     BufMut,
@@ -13,31 +15,112 @@ use bytes::{
 
 use crate::{
     Backend,
+    Bandwidth,
+    ConfigEvent,
+    DeviceConfig,
     DirectSamplingMode,
     Gain,
     TunerGainMode,
 };
 
+pub mod access_control;
 pub mod client;
 pub mod server;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod transport;
+pub mod virtual_receiver;
 
 /// Commands that can be send to the server.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Command {
-    SetCenterFrequency { frequency: u32 },
-    SetSampleRate { sample_rate: u32 },
-    SetTunerGainMode { mode: TunerGainMode },
-    SetTunerGain { gain: i32 },
-    SetFrequencyCorrection { ppm: i32 },
-    SetTunerIfGain { stage: i16, gain: i16 },
-    SetTestMode { enable: bool },
-    SetAgcMode { enable: bool },
-    SetDirectSampling { mode: Option<DirectSamplingMode> },
-    SetOffsetTuning { enable: bool },
-    SetRtlXtal { frequency: u32 },
-    SetTunerXtal { frequency: u32 },
-    SetTunerGainIndex { index: u32 },
-    SetBiasT { enable: bool },
+    SetCenterFrequency {
+        frequency: u32,
+    },
+    SetSampleRate {
+        sample_rate: u32,
+    },
+    SetTunerGainMode {
+        mode: TunerGainMode,
+    },
+    SetTunerGain {
+        gain: i32,
+    },
+    SetFrequencyCorrection {
+        ppm: i32,
+    },
+    SetTunerIfGain {
+        stage: i16,
+        gain: i16,
+    },
+    SetTestMode {
+        enable: bool,
+    },
+    SetAgcMode {
+        enable: bool,
+    },
+    SetDirectSampling {
+        mode: Option<DirectSamplingMode>,
+    },
+    SetOffsetTuning {
+        enable: bool,
+    },
+    SetRtlXtal {
+        frequency: u32,
+    },
+    SetTunerXtal {
+        frequency: u32,
+    },
+    SetTunerGainIndex {
+        index: u32,
+    },
+    SetBiasT {
+        enable: bool,
+    },
+
+    /// Extension of the librtlsdr fork's `rtl_tcp`.
+    SetTunerBandwidth {
+        bandwidth: Bandwidth,
+    },
+
+    /// Extension of the librtlsdr fork's `rtl_tcp`. Sets the center of the
+    /// R820T's IF filter in Hz, relative to the IF frequency.
+    SetTunerBandwidthIfCenter {
+        if_center: i32,
+    },
+
+    /// Extension of the librtlsdr fork's `rtl_tcp`. Selects how the R820T's
+    /// IF gain is controlled. The meaning of the value is specific to the
+    /// fork.
+    SetTunerIfMode {
+        mode: i32,
+    },
+
+    /// Extension of the librtlsdr fork's `rtl_tcp`.
+    SetSideband {
+        sideband: Sideband,
+    },
+
+    /// Extension of the librtlsdr fork's `rtl_tcp`. Configures a GPIO pin as
+    /// output.
+    SetGpioOutput {
+        pin: u32,
+    },
+
+    /// Extension of the librtlsdr fork's `rtl_tcp`. Configures a GPIO pin as
+    /// input.
+    SetGpioInput {
+        pin: u32,
+    },
+
+    /// Extension of the librtlsdr fork's `rtl_tcp`. Sets the level of a GPIO
+    /// output pin.
+    ///
+    /// This is how bias-tees on other pins than GPIO 0 are switched.
+    WriteGpio {
+        pin: u16,
+        value: bool,
+    },
 }
 
 impl Command {
@@ -123,6 +206,47 @@ impl Command {
                     enable: buffer.get_u32() != 0,
                 })
             }
+            0x40 => {
+                Ok(Self::SetTunerBandwidth {
+                    bandwidth: buffer.get_u32().into(),
+                })
+            }
+            0x45 => {
+                Ok(Self::SetTunerBandwidthIfCenter {
+                    if_center: buffer.get_i32(),
+                })
+            }
+            0x46 => {
+                Ok(Self::SetTunerIfMode {
+                    mode: buffer.get_i32(),
+                })
+            }
+            0x47 => {
+                Ok(Self::SetSideband {
+                    sideband: if buffer.get_u32() == 0 {
+                        Sideband::Lower
+                    }
+                    else {
+                        Sideband::Upper
+                    },
+                })
+            }
+            0x49 => {
+                Ok(Self::SetGpioOutput {
+                    pin: buffer.get_u32(),
+                })
+            }
+            0x50 => {
+                Ok(Self::SetGpioInput {
+                    pin: buffer.get_u32(),
+                })
+            }
+            0x52 => {
+                Ok(Self::WriteGpio {
+                    pin: buffer.get_u16(),
+                    value: buffer.get_u16() != 0,
+                })
+            }
             command => {
                 Err(InvalidCommand {
                     command,
@@ -198,6 +322,117 @@ impl Command {
                 buffer.put_u8(0x0e);
                 buffer.put_u32(*enable as u32);
             }
+            Self::SetTunerBandwidth { bandwidth } => {
+                buffer.put_u8(0x40);
+                buffer.put_u32((*bandwidth).into());
+            }
+            Self::SetTunerBandwidthIfCenter { if_center } => {
+                buffer.put_u8(0x45);
+                buffer.put_i32(*if_center);
+            }
+            Self::SetTunerIfMode { mode } => {
+                buffer.put_u8(0x46);
+                buffer.put_i32(*mode);
+            }
+            Self::SetSideband { sideband } => {
+                buffer.put_u8(0x47);
+                buffer.put_u32(match sideband {
+                    Sideband::Lower => 0,
+                    Sideband::Upper => 1,
+                });
+            }
+            Self::SetGpioOutput { pin } => {
+                buffer.put_u8(0x49);
+                buffer.put_u32(*pin);
+            }
+            Self::SetGpioInput { pin } => {
+                buffer.put_u8(0x50);
+                buffer.put_u32(*pin);
+            }
+            Self::WriteGpio { pin, value } => {
+                buffer.put_u8(0x52);
+                buffer.put_u16(*pin);
+                buffer.put_u16(*value as u16);
+            }
+        }
+    }
+
+    pub fn kind(&self) -> CommandKind {
+        match self {
+            Self::SetCenterFrequency { .. } => CommandKind::SetCenterFrequency,
+            Self::SetSampleRate { .. } => CommandKind::SetSampleRate,
+            Self::SetTunerGainMode { .. } => CommandKind::SetTunerGainMode,
+            Self::SetTunerGain { .. } => CommandKind::SetTunerGain,
+            Self::SetFrequencyCorrection { .. } => CommandKind::SetFrequencyCorrection,
+            Self::SetTunerIfGain { .. } => CommandKind::SetTunerIfGain,
+            Self::SetTestMode { .. } => CommandKind::SetTestMode,
+            Self::SetAgcMode { .. } => CommandKind::SetAgcMode,
+            Self::SetDirectSampling { .. } => CommandKind::SetDirectSampling,
+            Self::SetOffsetTuning { .. } => CommandKind::SetOffsetTuning,
+            Self::SetRtlXtal { .. } => CommandKind::SetRtlXtal,
+            Self::SetTunerXtal { .. } => CommandKind::SetTunerXtal,
+            Self::SetTunerGainIndex { .. } => CommandKind::SetTunerGainIndex,
+            Self::SetBiasT { .. } => CommandKind::SetBiasT,
+            Self::SetTunerBandwidth { .. } => CommandKind::SetTunerBandwidth,
+            Self::SetTunerBandwidthIfCenter { .. } => CommandKind::SetTunerBandwidthIfCenter,
+            Self::SetTunerIfMode { .. } => CommandKind::SetTunerIfMode,
+            Self::SetSideband { .. } => CommandKind::SetSideband,
+            Self::SetGpioOutput { .. } => CommandKind::SetGpioOutput,
+            Self::SetGpioInput { .. } => CommandKind::SetGpioInput,
+            Self::WriteGpio { .. } => CommandKind::WriteGpio,
+        }
+    }
+
+    /// Returns the change this command makes, if any.
+    ///
+    /// Switching to manual gain mode doesn't change anything by itself, since
+    /// the gain is set with the next command.
+    pub fn config_event(&self) -> Option<ConfigEvent> {
+        match *self {
+            Self::SetCenterFrequency { frequency } => {
+                Some(ConfigEvent::CenterFrequency { frequency })
+            }
+            Self::SetSampleRate { sample_rate } => Some(ConfigEvent::SampleRate { sample_rate }),
+            Self::SetTunerGainMode { mode } => {
+                (mode == TunerGainMode::Auto).then_some(ConfigEvent::TunerGain { gain: Gain::Auto })
+            }
+            Self::SetTunerGain { gain } => {
+                Some(ConfigEvent::TunerGain {
+                    gain: Gain::ManualValue(gain),
+                })
+            }
+            Self::SetFrequencyCorrection { ppm } => Some(ConfigEvent::FrequencyCorrection { ppm }),
+            Self::SetTunerIfGain { stage, gain } => Some(ConfigEvent::TunerIfGain { stage, gain }),
+            Self::SetTestMode { enable: _ } => None,
+            Self::SetAgcMode { enable } => Some(ConfigEvent::AgcMode { enable }),
+            Self::SetDirectSampling { mode } => Some(ConfigEvent::DirectSampling { mode }),
+            Self::SetOffsetTuning { enable } => Some(ConfigEvent::OffsetTuning { enable }),
+            Self::SetRtlXtal { frequency } => Some(ConfigEvent::RtlXtal { frequency }),
+            Self::SetTunerXtal { frequency } => Some(ConfigEvent::TunerXtal { frequency }),
+            Self::SetTunerGainIndex { index } => {
+                let index = index.try_into().ok()?;
+                Some(ConfigEvent::TunerGain {
+                    gain: Gain::ManualIndex(index),
+                })
+            }
+            Self::SetBiasT { enable } => Some(ConfigEvent::BiasTee { pin: 0, enable }),
+            Self::SetTunerBandwidth { bandwidth } => {
+                Some(ConfigEvent::TunerBandwidth { bandwidth })
+            }
+            // a GPIO can be used for anything, e.g. an LED or an antenna switch. we only
+            // know that GPIO 0 is the bias-tee, like for `SetBiasT`.
+            Self::WriteGpio { pin: 0, value } => {
+                Some(ConfigEvent::BiasTee {
+                    pin: 0,
+                    enable: value,
+                })
+            }
+            Self::SetTunerBandwidthIfCenter { .. }
+            | Self::SetTunerIfMode { .. }
+            | Self::SetSideband { .. }
+            | Self::SetGpioOutput { .. }
+            | Self::SetGpioInput { .. }
+            | Self::WriteGpio { .. } => None,
         }
     }
 
@@ -236,8 +471,8 @@ impl Command {
             Command::SetAgcMode { enable } => {
                 backend.set_agc_mode(*enable).await?;
             }
-            Command::SetDirectSampling { mode: _ } => {
-                // not supported
+            Command::SetDirectSampling { mode } => {
+                backend.set_direct_sampling(*mode).await?;
             }
             Command::SetOffsetTuning { enable } => {
                 backend.set_offset_tuning(*enable).await?;
@@ -259,15 +494,231 @@ impl Command {
             Command::SetBiasT { enable } => {
                 backend.set_bias_tee(*enable).await?;
             }
+            Command::SetTunerBandwidth { bandwidth } => {
+                backend.set_tuner_bandwidth(*bandwidth).await?;
+            }
+            Command::WriteGpio { pin, value } => {
+                // librtlsdr can only write GPIOs through the bias-tee function. it sets the pin
+                // as output and writes the value, so it works for any GPIO.
+                if let Ok(pin) = (*pin).try_into() {
+                    backend.set_bias_tee_gpio(pin, *value).await?;
+                }
+                else {
+                    tracing::error!(?pin, "GPIO pin doesn't fit into an u8!");
+                }
+            }
+            Command::SetGpioOutput { pin: _ } => {
+                // set_bias_tee_gpio configures the pin as output anyway
+            }
+            Command::SetTunerBandwidthIfCenter { .. }
+            | Command::SetTunerIfMode { .. }
+            | Command::SetSideband { .. }
+            | Command::SetGpioInput { .. } => {
+                // librtlsdr doesn't support these
+                tracing::warn!(command = ?self, "command not supported");
+            }
         }
 
         Ok(())
     }
 }
 
+impl DeviceConfig {
+    /// Updates the config with a command that was sent to the server.
+    /// Returns whether this changed the config.
+    pub(crate) fn apply_command(&mut self, command: &Command) -> bool {
+        let previous = self.clone();
+        if let Some(event) = command.config_event() {
+            self.apply(&event);
+        }
+        else if *command
+            == (Command::SetTunerGainMode {
+                mode: TunerGainMode::Manual,
+            })
+            && self.tuner_gain == Some(Gain::Auto)
+        {
+            // the gain itself is set by the next command
+            self.tuner_gain = None;
+        }
+        *self != previous
+    }
+
+    /// Returns the commands that reproduce this config.
+    ///
+    /// Unknown values are skipped. Applying the commands in order to a
+    /// [`DeviceConfig::default()`] results in this config again.
+    pub fn commands(&self) -> Vec<Command> {
+        let mut commands = vec![];
+
+        if let Some(frequency) = self.rtl_xtal {
+            commands.push(Command::SetRtlXtal { frequency });
+        }
+        if let Some(frequency) = self.tuner_xtal {
+            commands.push(Command::SetTunerXtal { frequency });
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            commands.push(Command::SetSampleRate { sample_rate });
+        }
+        // this goes after the sample rate, since changing it also changes the
+        // bandwidth.
+        if let Some(bandwidth) = self.tuner_bandwidth {
+            commands.push(Command::SetTunerBandwidth { bandwidth });
+        }
+        if let Some(ppm) = self.frequency_correction {
+            commands.push(Command::SetFrequencyCorrection { ppm });
+        }
+        if self.direct_sampling.is_some() {
+            commands.push(Command::SetDirectSampling {
+                mode: self.direct_sampling,
+            });
+        }
+        if let Some(enable) = self.offset_tuning {
+            commands.push(Command::SetOffsetTuning { enable });
+        }
+        if let Some(frequency) = self.center_frequency {
+            commands.push(Command::SetCenterFrequency { frequency });
+        }
+        match self.tuner_gain {
+            Some(Gain::Auto) => {
+                commands.push(Command::SetTunerGainMode {
+                    mode: TunerGainMode::Auto,
+                });
+            }
+            Some(Gain::ManualValue(gain)) => {
+                commands.push(Command::SetTunerGainMode {
+                    mode: TunerGainMode::Manual,
+                });
+                commands.push(Command::SetTunerGain { gain });
+            }
+            Some(Gain::ManualIndex(index)) => {
+                if let Ok(index) = index.try_into() {
+                    commands.push(Command::SetTunerGainMode {
+                        mode: TunerGainMode::Manual,
+                    });
+                    commands.push(Command::SetTunerGainIndex { index });
+                }
+            }
+            None => {}
+        }
+        for &(stage, gain) in &self.tuner_if_gains {
+            commands.push(Command::SetTunerIfGain { stage, gain });
+        }
+        if let Some(enable) = self.agc_mode {
+            commands.push(Command::SetAgcMode { enable });
+        }
+        if let Some(enable) = self.bias_tee {
+            commands.push(Command::SetBiasT { enable });
+        }
+
+        commands
+    }
+}
+
+/// The kind of a [`Command`], without its arguments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CommandKind {
+    SetCenterFrequency,
+    SetSampleRate,
+    SetTunerGainMode,
+    SetTunerGain,
+    SetFrequencyCorrection,
+    SetTunerIfGain,
+    SetTestMode,
+    SetAgcMode,
+    SetDirectSampling,
+    SetOffsetTuning,
+    SetRtlXtal,
+    SetTunerXtal,
+    SetTunerGainIndex,
+    SetBiasT,
+    SetTunerBandwidth,
+    SetTunerBandwidthIfCenter,
+    SetTunerIfMode,
+    SetSideband,
+    SetGpioOutput,
+    SetGpioInput,
+    WriteGpio,
+}
+
+impl CommandKind {
+    pub const ALL: [Self; 21] = [
+        Self::SetCenterFrequency,
+        Self::SetSampleRate,
+        Self::SetTunerGainMode,
+        Self::SetTunerGain,
+        Self::SetFrequencyCorrection,
+        Self::SetTunerIfGain,
+        Self::SetTestMode,
+        Self::SetAgcMode,
+        Self::SetDirectSampling,
+        Self::SetOffsetTuning,
+        Self::SetRtlXtal,
+        Self::SetTunerXtal,
+        Self::SetTunerGainIndex,
+        Self::SetBiasT,
+        Self::SetTunerBandwidth,
+        Self::SetTunerBandwidthIfCenter,
+        Self::SetTunerIfMode,
+        Self::SetSideband,
+        Self::SetGpioOutput,
+        Self::SetGpioInput,
+        Self::WriteGpio,
+    ];
+
+    /// Name of the command in kebab-case, e.g. `set-center-frequency`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::SetCenterFrequency => "set-center-frequency",
+            Self::SetSampleRate => "set-sample-rate",
+            Self::SetTunerGainMode => "set-tuner-gain-mode",
+            Self::SetTunerGain => "set-tuner-gain",
+            Self::SetFrequencyCorrection => "set-frequency-correction",
+            Self::SetTunerIfGain => "set-tuner-if-gain",
+            Self::SetTestMode => "set-test-mode",
+            Self::SetAgcMode => "set-agc-mode",
+            Self::SetDirectSampling => "set-direct-sampling",
+            Self::SetOffsetTuning => "set-offset-tuning",
+            Self::SetRtlXtal => "set-rtl-xtal",
+            Self::SetTunerXtal => "set-tuner-xtal",
+            Self::SetTunerGainIndex => "set-tuner-gain-index",
+            Self::SetBiasT => "set-bias-t",
+            Self::SetTunerBandwidth => "set-tuner-bandwidth",
+            Self::SetTunerBandwidthIfCenter => "set-tuner-bandwidth-if-center",
+            Self::SetTunerIfMode => "set-tuner-if-mode",
+            Self::SetSideband => "set-sideband",
+            Self::SetGpioOutput => "set-gpio-output",
+            Self::SetGpioInput => "set-gpio-input",
+            Self::WriteGpio => "write-gpio",
+        }
+    }
+}
+
+impl FromStr for CommandKind {
+    type Err = InvalidCommandKind;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| InvalidCommandKind(s.to_owned()))
+    }
+}
+
+/// Error for when a [`CommandKind`] is parsed from an unknown name.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[error("Unknown rtl_tcp command: {0}")]
+pub struct InvalidCommandKind(pub String);
+
+/// Sideband selected by [`Command::SetSideband`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Sideband {
+    Lower,
+    Upper,
+}
+
 /// Error for when an invalid command is received.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, thiserror::Error)]
-#[error("Invalid rtl_tcp command: 0x{command:02} (arguments: {arguments:?})")]
+#[error("Invalid rtl_tcp command: 0x{command:02x} (arguments: {arguments:?})")]
 pub struct InvalidCommand {
     pub command: u8,
     pub arguments: [u8; 4],
@@ -284,7 +735,15 @@ pub const HEADER_LENGTH: usize = 12;
 pub const COMMAND_LENGTH: usize = 5;
 
 /// Magic value sent by server to identify the protocol.
-pub const MAGIC: &'static [u8; 4] = b"RTL0";
+pub const MAGIC: &[u8; 4] = b"RTL0";
+
+/// Magic value of the config snapshot a server can send after the header.
+///
+/// This is an extension to the protocol, so it's only sent if both sides
+/// opted in. The magic value is followed by the number of commands as `u32`,
+/// and then by the commands that reproduce the server's config (see
+/// [`DeviceConfig::commands`]).
+pub const CONFIG_MAGIC: &[u8; 4] = b"RTLC";
 
 pub(crate) trait BufReadBytesExt {
     fn get_bytes<const N: usize>(&mut self) -> [u8; N];
@@ -297,3 +756,136 @@ impl<B: Buf> BufReadBytesExt for B {
         data
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Pacing,
+        simulated::{
+            Scene,
+            SimulatedSdr,
+        },
+    };
+
+    /// every command with its encoding
+    #[rustfmt::skip]
+    const COMMANDS: &[(Command, [u8; COMMAND_LENGTH])] = &[
+        (Command::SetCenterFrequency { frequency: 100_000_000 }, [0x01, 0x05, 0xf5, 0xe1, 0x00]),
+        (Command::SetSampleRate { sample_rate: 2_048_000 }, [0x02, 0x00, 0x1f, 0x40, 0x00]),
+        (Command::SetTunerGainMode { mode: TunerGainMode::Auto }, [0x03, 0x00, 0x00, 0x00, 0x00]),
+        (Command::SetTunerGainMode { mode: TunerGainMode::Manual }, [0x03, 0x00, 0x00, 0x00, 0x01]),
+        (Command::SetTunerGain { gain: 197 }, [0x04, 0x00, 0x00, 0x00, 0xc5]),
+        (Command::SetTunerGain { gain: -10 }, [0x04, 0xff, 0xff, 0xff, 0xf6]),
+        (Command::SetFrequencyCorrection { ppm: -3 }, [0x05, 0xff, 0xff, 0xff, 0xfd]),
+        (Command::SetTunerIfGain { stage: 1, gain: -30 }, [0x06, 0x00, 0x01, 0xff, 0xe2]),
+        (Command::SetTestMode { enable: true }, [0x07, 0x00, 0x00, 0x00, 0x01]),
+        (Command::SetAgcMode { enable: true }, [0x08, 0x00, 0x00, 0x00, 0x01]),
+        (Command::SetDirectSampling { mode: None }, [0x09, 0x00, 0x00, 0x00, 0x00]),
+        (Command::SetDirectSampling { mode: Some(DirectSamplingMode::I) }, [0x09, 0x00, 0x00, 0x00, 0x01]),
+        (Command::SetDirectSampling { mode: Some(DirectSamplingMode::Q) }, [0x09, 0x00, 0x00, 0x00, 0x02]),
+        (Command::SetOffsetTuning { enable: true }, [0x0a, 0x00, 0x00, 0x00, 0x01]),
+        (Command::SetRtlXtal { frequency: 28_800_000 }, [0x0b, 0x01, 0xb7, 0x74, 0x00]),
+        (Command::SetTunerXtal { frequency: 28_800_000 }, [0x0c, 0x01, 0xb7, 0x74, 0x00]),
+        (Command::SetTunerGainIndex { index: 3 }, [0x0d, 0x00, 0x00, 0x00, 0x03]),
+        (Command::SetBiasT { enable: true }, [0x0e, 0x00, 0x00, 0x00, 0x01]),
+        (Command::SetTunerBandwidth { bandwidth: Bandwidth::Auto }, [0x40, 0x00, 0x00, 0x00, 0x00]),
+        (Command::SetTunerBandwidth { bandwidth: Bandwidth::Manual(1_500_000) }, [0x40, 0x00, 0x16, 0xe3, 0x60]),
+        (Command::SetTunerBandwidthIfCenter { if_center: -100_000 }, [0x45, 0xff, 0xfe, 0x79, 0x60]),
+        (Command::SetTunerIfMode { mode: 10_000 }, [0x46, 0x00, 0x00, 0x27, 0x10]),
+        (Command::SetSideband { sideband: Sideband::Lower }, [0x47, 0x00, 0x00, 0x00, 0x00]),
+        (Command::SetSideband { sideband: Sideband::Upper }, [0x47, 0x00, 0x00, 0x00, 0x01]),
+        (Command::SetGpioOutput { pin: 5 }, [0x49, 0x00, 0x00, 0x00, 0x05]),
+        (Command::SetGpioInput { pin: 5 }, [0x50, 0x00, 0x00, 0x00, 0x05]),
+        (Command::WriteGpio { pin: 5, value: true }, [0x52, 0x00, 0x05, 0x00, 0x01]),
+    ];
+
+    #[test]
+    fn it_encodes_commands() {
+        for (command, bytes) in COMMANDS {
+            let mut encoded = vec![];
+            command.encode(&mut encoded);
+            assert_eq!(encoded, bytes, "{command:?}");
+        }
+    }
+
+    #[test]
+    fn it_decodes_commands() {
+        for (command, bytes) in COMMANDS {
+            assert_eq!(Command::decode(&bytes[..]).unwrap(), *command);
+        }
+    }
+
+    #[test]
+    fn command_kinds_can_be_parsed_from_their_names() {
+        for kind in CommandKind::ALL {
+            assert_eq!(kind.name().parse::<CommandKind>().unwrap(), kind);
+        }
+        assert!("set-frequency".parse::<CommandKind>().is_err());
+    }
+
+    #[test]
+    fn it_rejects_unknown_opcodes() {
+        let error = Command::decode(&[0xff, 0x01, 0x02, 0x03, 0x04][..]).unwrap_err();
+        assert_eq!(
+            error,
+            InvalidCommand {
+                command: 0xff,
+                arguments: [0x01, 0x02, 0x03, 0x04]
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Invalid rtl_tcp command: 0xff (arguments: [1, 2, 3, 4])"
+        );
+    }
+
+    #[tokio::test]
+    async fn applying_direct_sampling_sets_the_mode() {
+        let sdr = SimulatedSdr::with_pacing(Scene::new(), Pacing::Unpaced);
+
+        Command::SetDirectSampling {
+            mode: Some(DirectSamplingMode::Q),
+        }
+        .apply(&sdr)
+        .await
+        .unwrap();
+        assert_eq!(sdr.get_direct_sampling(), Some(DirectSamplingMode::Q));
+
+        Command::SetDirectSampling { mode: None }
+            .apply(&sdr)
+            .await
+            .unwrap();
+        assert_eq!(sdr.get_direct_sampling(), None);
+    }
+
+    #[test]
+    fn only_gpio_0_is_reported_as_the_bias_tee() {
+        assert_eq!(
+            Command::WriteGpio {
+                pin: 0,
+                value: true
+            }
+            .config_event(),
+            Some(ConfigEvent::BiasTee {
+                pin: 0,
+                enable: true
+            })
+        );
+        assert_eq!(
+            Command::WriteGpio {
+                pin: 5,
+                value: true
+            }
+            .config_event(),
+            None
+        );
+
+        let mut config = DeviceConfig::default();
+        assert!(!config.apply_command(&Command::WriteGpio {
+            pin: 5,
+            value: true,
+        }));
+        assert_eq!(config.bias_tee, None);
+    }
+}
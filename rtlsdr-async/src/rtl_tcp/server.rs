@@ -1,6 +1,9 @@
 use std::{
     fmt::Debug,
     net::SocketAddr,
+    num::NonZeroU32,
+    str::FromStr,
+    time::Duration,
 };
 
 use bytes::{
@@ -10,16 +13,16 @@ use bytes::{
 use futures_util::TryStreamExt;
 use tokio::{
     io::{
+        AsyncRead,
         AsyncReadExt,
         AsyncWrite,
         AsyncWriteExt,
         BufReader,
         BufWriter,
     },
-    net::{
-        TcpListener,
-        TcpStream,
-    },
+    net::TcpListener,
+    sync::broadcast,
+    task::JoinSet,
 };
 use tokio_util::sync::CancellationToken;
 use tracing::Instrument;
@@ -27,17 +30,36 @@ use tracing::Instrument;
 use crate::{
     Backend,
     Chunk,
+    ConfigChanges,
+    ConfigEvent,
+    DeviceConfig,
+    DirectSamplingMode,
     DongleInfo,
     Iq,
     RtlSdr,
+    SampleType,
     Samples,
+    config::CONFIG_EVENT_QUEUE_SIZE,
     rtl_tcp::{
         COMMAND_LENGTH,
+        CONFIG_MAGIC,
         Command,
+        HEADER_LENGTH,
         InvalidCommand,
+        MAGIC,
+        transport::{
+            Incoming,
+            Listener,
+        },
     },
 };
 
+/// Default for [`RtlTcpServer::with_establish_timeout`].
+pub const DEFAULT_ESTABLISH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default for [`RtlTcpServer::with_max_establishing`].
+pub const DEFAULT_MAX_ESTABLISHING: usize = 64;
+
 /// Server errors
 #[derive(Debug, thiserror::Error)]
 #[error("rtl_tcp server error")]
@@ -59,19 +81,30 @@ pub enum Error<H> {
 /// It is usually created from a [`RtlSdr`], but be created from
 /// anything that implements the [`AsyncReadSamples`] and [`Configure`] traits,
 /// e.g. a [`RtlTcpClient`][crate::rtl_tcp::client::RtlTcpClient]
+///
+/// Connections are accepted from a [`TcpListener`], or any other
+/// [`Listener`].
 #[derive(Debug)]
-pub struct RtlTcpServer<H> {
+pub struct RtlTcpServer<H, L = TcpListener> {
     handler: H,
-    tcp_listener: TcpListener,
+    listener: L,
     shutdown: CancellationToken,
+    establish_timeout: Duration,
+    max_establishing: usize,
+    options: ConnectionOptions,
+    config_events: broadcast::Sender<ConfigEvent>,
 }
 
-impl<H> RtlTcpServer<H> {
-    pub fn new(handler: H, tcp_listener: TcpListener) -> Self {
+impl<H, L> RtlTcpServer<H, L> {
+    pub fn new(handler: H, listener: L) -> Self {
         Self {
             handler,
-            tcp_listener,
+            listener,
             shutdown: CancellationToken::new(),
+            establish_timeout: DEFAULT_ESTABLISH_TIMEOUT,
+            max_establishing: DEFAULT_MAX_ESTABLISHING,
+            options: ConnectionOptions::default(),
+            config_events: broadcast::Sender::new(CONFIG_EVENT_QUEUE_SIZE),
         }
     }
 
@@ -81,40 +114,118 @@ impl<H> RtlTcpServer<H> {
         self.shutdown = shutdown;
         self
     }
+
+    /// How long a connection may take to be
+    /// [established][Incoming::establish], e.g. for a TLS handshake, before
+    /// it's closed.
+    ///
+    /// Default is [`DEFAULT_ESTABLISH_TIMEOUT`].
+    pub fn with_establish_timeout(mut self, timeout: Duration) -> Self {
+        self.establish_timeout = timeout;
+        self
+    }
+
+    /// How many connections may be [established][Incoming::establish]
+    /// concurrently. No new connections are accepted while this many are
+    /// still establishing.
+    ///
+    /// Default is [`DEFAULT_MAX_ESTABLISHING`].
+    pub fn with_max_establishing(mut self, max_establishing: usize) -> Self {
+        self.max_establishing = max_establishing.max(1);
+        self
+    }
+
+    /// Send a snapshot of the current config to new clients, right after the
+    /// header.
+    ///
+    /// This is an extension to the protocol (see [`CONFIG_MAGIC`]), so only
+    /// enable this if all clients expect it. Clients made by this crate must be
+    /// configured with
+    /// [`RtlTcpClientBuilder::with_config_snapshot`][super::client::RtlTcpClientBuilder::with_config_snapshot].
+    ///
+    /// Default is `false`.
+    pub fn with_config_snapshot(mut self, enable: bool) -> Self {
+        self.options.config_snapshot = enable;
+        self
+    }
+
+    /// What to do when a client sends a command we don't know.
+    ///
+    /// Default is [`UnknownCommandPolicy::Ignore`].
+    pub fn with_unknown_command_policy(mut self, policy: UnknownCommandPolicy) -> Self {
+        self.options.unknown_commands = policy;
+        self
+    }
+
+    /// Returns a receiver for changes of the configuration.
+    ///
+    /// This receives an event for every command that a client's
+    /// [`ConnectionHandler`] applied to the device.
+    pub fn config_changes(&self) -> ConfigChanges {
+        ConfigChanges::new(self.config_events.subscribe())
+    }
 }
 
-impl<B: Backend> RtlTcpServer<BackendHandler<B>> {
-    pub fn from_backend(backend: B, tcp_listener: TcpListener) -> Self {
-        Self::new(BackendHandler::new(backend), tcp_listener)
+impl<B: Backend, L> RtlTcpServer<BackendHandler<B>, L> {
+    pub fn from_backend(backend: B, listener: L) -> Self {
+        Self::new(BackendHandler::new(backend), listener)
     }
 }
 
-impl RtlTcpServer<BackendHandler<RtlSdr>> {
+impl<L> RtlTcpServer<BackendHandler<RtlSdr>, L> {
     /// This will populate a [`DongleInfo`] and call [`RtlTcpServer::new`] with
     /// it.
-    pub fn from_rtl_sdr(rtl_sdr: RtlSdr, tcp_listener: TcpListener) -> Self {
-        Self::from_backend(rtl_sdr, tcp_listener)
+    pub fn from_rtl_sdr(rtl_sdr: RtlSdr, listener: L) -> Self {
+        Self::from_backend(rtl_sdr, listener)
     }
 }
 
-impl<H> RtlTcpServer<H>
+impl<H, L> RtlTcpServer<H, L>
 where
     H: Handler,
     H::ConnectionHandler: Send + 'static,
+    L: Listener,
 {
     /// Serve incoming connections
     pub async fn serve(mut self) -> Result<(), Error<H::Error>> {
         tracing::debug!("waiting for connections");
 
+        // connections are only passed to the handler once they're established,
+        // so e.g. a client that never finishes its TLS handshake doesn't take
+        // up a slot for the maximum number of clients.
+        let mut establishing = JoinSet::new();
+
         loop {
             tokio::select! {
                 _ = self.shutdown.cancelled() => break,
-                result = self.tcp_listener.accept() => {
-
-
+                result = self.listener.accept(), if establishing.len() < self.max_establishing => {
                     let (connection, address) = result?;
-                    if let Err(error) = self.handle_accept(connection, address).await {
-                        tracing::error!(?error);
+                    tracing::debug!(%address, "new connection");
+                    if !self.handler.is_address_allowed(address) {
+                        // dropping the connection closes it
+                        continue;
+                    }
+                    let timeout = self.establish_timeout;
+                    establishing.spawn(async move {
+                        (tokio::time::timeout(timeout, connection.establish()).await, address)
+                    });
+                }
+                Some(result) = establishing.join_next() => {
+                    match result {
+                        Ok((Ok(Ok(connection)), address)) => {
+                            if let Err(error) = self.handle_accept(connection, address).await {
+                                tracing::error!(?error);
+                            }
+                        }
+                        Ok((Ok(Err(error)), address)) => {
+                            tracing::warn!(%address, ?error, "failed to establish connection");
+                        }
+                        Ok((Err(_), address)) => {
+                            tracing::warn!(%address, "timeout while establishing connection");
+                        }
+                        Err(error) => {
+                            tracing::error!(?error);
+                        }
                     }
                 }
             }
@@ -125,10 +236,12 @@ where
 
     async fn handle_accept(
         &mut self,
-        connection: TcpStream,
+        connection: <L::Incoming as Incoming>::Stream,
         address: SocketAddr,
     ) -> Result<(), Error<H::Error>> {
         let shutdown = self.shutdown.clone();
+        let options = self.options;
+        let config_events = self.config_events.clone();
 
         if let Some(handler) = self
             .handler
@@ -136,12 +249,17 @@ where
             .await
             .map_err(Error::Handler)?
         {
+            let handler = NotifyingConnectionHandler {
+                handler,
+                config_events,
+            };
             let span = tracing::info_span!("connection", %address);
 
             tokio::spawn(
                 async move {
-                    tracing::debug!(%address, "new connection");
-                    if let Err(error) = serve_connection(connection, shutdown, handler).await {
+                    if let Err(error) =
+                        serve_connection(connection, shutdown, handler, options).await
+                    {
                         tracing::error!(?error);
                     }
                     tracing::debug!(%address, "closing connection");
@@ -154,6 +272,106 @@ where
     }
 }
 
+/// Settings for a connection.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConnectionOptions {
+    /// Send a snapshot of the config after the header. See
+    /// [`RtlTcpServer::with_config_snapshot`].
+    pub config_snapshot: bool,
+
+    pub unknown_commands: UnknownCommandPolicy,
+}
+
+/// What to do when a client sends a command we don't know.
+///
+/// This happens e.g. if the client supports extensions of the protocol that
+/// we don't. Commands that the [`ConnectionHandler::unknown_command`] hook
+/// handles don't count as unknown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnknownCommandPolicy {
+    /// Log the command and skip it. This is what `rtl_tcp` does.
+    #[default]
+    Ignore,
+
+    /// Close the connection.
+    Close,
+
+    /// Skip unknown commands, but close the connection on the N-th one.
+    ///
+    /// `CloseAfter(1)` is the same as [`Close`][Self::Close].
+    CloseAfter(NonZeroU32),
+}
+
+impl FromStr for UnknownCommandPolicy {
+    type Err = InvalidUnknownCommandPolicy;
+
+    /// Parses `ignore`, `close`, or the number of the unknown command on which
+    /// the connection is closed.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(Self::Ignore),
+            "close" => Ok(Self::Close),
+            _ => {
+                s.parse()
+                    .map(Self::CloseAfter)
+                    .map_err(|_| InvalidUnknownCommandPolicy(s.to_owned()))
+            }
+        }
+    }
+}
+
+/// Error for when an [`UnknownCommandPolicy`] can't be parsed.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[error("Invalid unknown command policy: {0}")]
+pub struct InvalidUnknownCommandPolicy(pub String);
+
+/// wraps a [`ConnectionHandler`] to send an event for every command it
+/// applied.
+#[derive(Debug)]
+struct NotifyingConnectionHandler<H> {
+    handler: H,
+    config_events: broadcast::Sender<ConfigEvent>,
+}
+
+impl<H> ConnectionHandler for NotifyingConnectionHandler<H>
+where
+    H: ConnectionHandler + Send,
+{
+    type Error = H::Error;
+
+    fn dongle_info(&self) -> DongleInfo {
+        self.handler.dongle_info()
+    }
+
+    fn config(&self) -> impl Future<Output = Result<Option<DeviceConfig>, Self::Error>> + Send {
+        self.handler.config()
+    }
+
+    async fn handle_command(&mut self, command: Command) -> Result<bool, Self::Error> {
+        let applied = self.handler.handle_command(command).await?;
+        if applied && let Some(event) = command.config_event() {
+            // this only fails if nobody is subscribed
+            let _ = self.config_events.send(event);
+        }
+        Ok(applied)
+    }
+
+    fn unknown_command(
+        &mut self,
+        command: u8,
+        arguments: [u8; 4],
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        self.handler.unknown_command(command, arguments)
+    }
+
+    fn read_samples(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> impl Future<Output = Result<usize, Self::Error>> + Send {
+        self.handler.read_samples(buffer)
+    }
+}
+
 #[derive(Debug, Default)]
 struct CommandBuffer {
     data: [u8; COMMAND_LENGTH],
@@ -171,9 +389,11 @@ impl CommandBuffer {
 
     pub fn try_decode(&mut self) -> Result<Option<Command>, InvalidCommand> {
         if self.is_full() {
-            let command = Command::decode(&self.data[..])?;
+            // reset even if the command is invalid, so that we can continue with
+            // the next one.
+            let result = Command::decode(&self.data[..]);
             self.reset();
-            Ok(Some(command))
+            result.map(Some)
         }
         else {
             Ok(None)
@@ -198,17 +418,17 @@ unsafe impl BufMut for CommandBuffer {
 
 #[derive(Debug)]
 struct SampleBuffer {
-    samples: Vec<Iq>,
+    data: Vec<u8>,
     write_pos: usize,
     read_pos: usize,
 }
 
 impl SampleBuffer {
-    const DEFAULT_CAPACITY: usize = 0x4000;
+    const DEFAULT_CAPACITY: usize = 0x8000;
 
     pub fn new(capacity: usize) -> Self {
         Self {
-            samples: vec![Default::default(); capacity],
+            data: vec![0; capacity],
             write_pos: 0,
             read_pos: 0,
         }
@@ -219,12 +439,11 @@ impl SampleBuffer {
     }
 
     pub fn read_buffer(&self) -> &[u8] {
-        bytemuck::cast_slice(&self.samples[self.read_pos..self.write_pos])
+        &self.data[self.read_pos..self.write_pos]
     }
 
     pub fn confirm_read(&mut self, num_bytes: usize) {
-        assert_eq!(num_bytes % 2, 0, "todo: buffer half-read sample");
-        self.read_pos += num_bytes / 2;
+        self.read_pos += num_bytes;
         assert!(self.read_pos <= self.write_pos);
 
         if self.read_pos == self.write_pos {
@@ -237,13 +456,13 @@ impl SampleBuffer {
         self.write_pos == 0
     }
 
-    pub fn write_buffer(&mut self) -> &mut [Iq] {
-        &mut self.samples[self.read_pos..]
+    pub fn write_buffer(&mut self) -> &mut [u8] {
+        &mut self.data[self.write_pos..]
     }
 
-    pub fn confirm_write(&mut self, num_samples: usize) {
-        self.write_pos += num_samples;
-        assert!(self.write_pos <= self.samples.len());
+    pub fn confirm_write(&mut self, num_bytes: usize) {
+        self.write_pos += num_bytes;
+        assert!(self.write_pos <= self.data.len());
     }
 }
 
@@ -253,21 +472,36 @@ impl Default for SampleBuffer {
     }
 }
 
-pub async fn serve_connection<H>(
-    mut connection: TcpStream,
+pub async fn serve_connection<H, S>(
+    connection: S,
     shutdown: CancellationToken,
     mut handler: H,
+    options: ConnectionOptions,
 ) -> Result<(), Error<H::Error>>
 where
     H: ConnectionHandler,
+    S: AsyncRead + AsyncWrite,
 {
     let mut command_buffer = CommandBuffer::default();
     let mut sample_buffer = SampleBuffer::default();
+    let mut num_unknown_commands = 0;
 
-    let (tcp_read, tcp_write) = connection.split();
+    let (tcp_read, tcp_write) = tokio::io::split(connection);
     let mut tcp_read = BufReader::new(tcp_read);
     let mut tcp_write = BufWriter::new(tcp_write);
 
+    // send the header, so the client knows what kind of dongle it's talking to
+    write_header(&mut tcp_write, &handler.dongle_info()).await?;
+
+    if options.config_snapshot {
+        let config = handler
+            .config()
+            .await
+            .map_err(Error::Handler)?
+            .unwrap_or_default();
+        write_config_snapshot(&mut tcp_write, &config).await?;
+    }
+
     loop {
         tokio::select! {
             _ = shutdown.cancelled() => {
@@ -277,8 +511,33 @@ where
                 if result? == 0 {
                     break;
                 }
-                if let Some(command) = command_buffer.try_decode()? {
-                    handler.handle_command(command).await.map_err(Error::Handler)?;
+                match command_buffer.try_decode() {
+                    Ok(Some(command)) => {
+                        handler.handle_command(command).await.map_err(Error::Handler)?;
+                    }
+                    Ok(None) => {}
+                    Err(error) => {
+                        let handled = handler
+                            .unknown_command(error.command, error.arguments)
+                            .await
+                            .map_err(Error::Handler)?;
+                        if !handled {
+                            num_unknown_commands += 1;
+                            match options.unknown_commands {
+                                UnknownCommandPolicy::CloseAfter(max)
+                                    if num_unknown_commands < max.get() =>
+                                {
+                                    tracing::warn!(%error, num_unknown_commands, "ignoring command");
+                                }
+                                UnknownCommandPolicy::Ignore => {
+                                    tracing::warn!(%error, "ignoring command");
+                                }
+                                UnknownCommandPolicy::Close | UnknownCommandPolicy::CloseAfter(_) => {
+                                    return Err(error.into());
+                                }
+                            }
+                        }
+                    }
                 }
             }
             result = forward_samples(&mut sample_buffer, &mut handler, &mut tcp_write) => {
@@ -292,6 +551,41 @@ where
     Ok(())
 }
 
+async fn write_header<W: AsyncWrite + Unpin>(
+    mut tcp_write: W,
+    dongle_info: &DongleInfo,
+) -> Result<(), std::io::Error> {
+    let mut header = [0; HEADER_LENGTH];
+    let mut buffer = &mut header[..];
+    buffer.put_slice(MAGIC);
+    buffer.put_u32(dongle_info.tuner_type.0);
+    buffer.put_u32(dongle_info.tuner_gain_count);
+
+    tcp_write.write_all(&header).await?;
+    tcp_write.flush().await?;
+
+    Ok(())
+}
+
+async fn write_config_snapshot<W: AsyncWrite + Unpin>(
+    mut tcp_write: W,
+    config: &DeviceConfig,
+) -> Result<(), std::io::Error> {
+    let commands = config.commands();
+
+    let mut snapshot = Vec::with_capacity(8 + commands.len() * COMMAND_LENGTH);
+    snapshot.put_slice(CONFIG_MAGIC);
+    snapshot.put_u32(commands.len().try_into().unwrap());
+    for command in &commands {
+        command.encode(&mut snapshot);
+    }
+
+    tcp_write.write_all(&snapshot).await?;
+    tcp_write.flush().await?;
+
+    Ok(())
+}
+
 async fn forward_samples<H, W>(
     sample_buffer: &mut SampleBuffer,
     handler: &mut H,
@@ -302,14 +596,14 @@ where
     W: AsyncWrite + Unpin,
 {
     if sample_buffer.can_write() {
-        let num_samples = handler
+        let num_bytes = handler
             .read_samples(sample_buffer.write_buffer())
             .await
             .map_err(Error::Handler)?;
-        if num_samples == 0 {
+        if num_bytes == 0 {
             return Ok(true);
         }
-        sample_buffer.confirm_write(num_samples);
+        sample_buffer.confirm_write(num_bytes);
     }
     else if sample_buffer.can_read() {
         let num_bytes = tcp_write.write(sample_buffer.read_buffer()).await?;
@@ -331,10 +625,21 @@ pub trait Handler {
     type Error: std::error::Error + Send;
     type ConnectionHandler: ConnectionHandler<Error = Self::Error>;
 
+    /// Called for every incoming connection before it's
+    /// [established][Incoming::establish], e.g. before the TLS handshake.
+    ///
+    /// Return `false` to close the connection right away. Otherwise
+    /// [`accept_connection`][Self::accept_connection] is called once it's
+    /// established. The default accepts every address.
+    fn is_address_allowed(&self, address: SocketAddr) -> bool {
+        let _ = address;
+        true
+    }
+
     fn accept_connection(
         &mut self,
         address: SocketAddr,
-    ) -> impl Future<Output = Result<Option<Self::ConnectionHandler>, Self::Error>>;
+    ) -> impl Future<Output = Result<Option<Self::ConnectionHandler>, Self::Error>> + Send;
 }
 
 pub trait ConnectionHandler {
@@ -342,14 +647,50 @@ pub trait ConnectionHandler {
 
     fn dongle_info(&self) -> DongleInfo;
 
+    /// Returns the current config, which is sent to the client if config
+    /// snapshots are enabled.
+    ///
+    /// `None` sends an empty snapshot. This is the default.
+    fn config(&self) -> impl Future<Output = Result<Option<DeviceConfig>, Self::Error>> + Send {
+        async { Ok(None) }
+    }
+
+    /// Handles a command from the client.
+    ///
+    /// Returns whether the command was applied to the device. Only then an
+    /// event is sent to the server's
+    /// [config changes][RtlTcpServer::config_changes]. Commands that were
+    /// ignored or rejected, that didn't change anything, or that only affect
+    /// this connection, return `false`.
     fn handle_command(
         &mut self,
         command: Command,
-    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send;
 
+    /// Called for commands that [`Command`] doesn't know, with their opcode
+    /// and raw arguments.
+    ///
+    /// This can be used to implement extensions of the protocol. Return
+    /// `true` if the command was handled, or `false` to let the server's
+    /// [`UnknownCommandPolicy`] decide what to do with it. The default
+    /// returns `false`.
+    fn unknown_command(
+        &mut self,
+        command: u8,
+        arguments: [u8; 4],
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        let _ = (command, arguments);
+        async { Ok(false) }
+    }
+
+    /// Reads samples into `buffer` and returns the number of bytes read.
+    ///
+    /// The bytes are sent to the client as-is, so they must be interleaved IQ
+    /// samples, or, in direct sampling mode, one byte per sample. Returning 0
+    /// closes the connection.
     fn read_samples(
         &mut self,
-        buffer: &mut [Iq],
+        buffer: &mut [u8],
     ) -> impl Future<Output = Result<usize, Self::Error>> + Send;
 }
 
@@ -384,8 +725,8 @@ where
 #[derive(Clone, Debug)]
 pub struct BackendConnectionHandler<B> {
     backend: B,
-    samples: Samples<Iq>,
-    chunk: Option<Chunk<Iq>>,
+    samples: SampleStream,
+    chunk: Option<Chunk<u8>>,
 }
 
 impl<B> BackendConnectionHandler<B>
@@ -393,13 +734,33 @@ where
     B: Backend,
 {
     pub async fn new(backend: B) -> Result<Self, B::Error> {
-        let samples = backend.samples().await?;
+        let samples = SampleStream::Iq(backend.samples().await?);
         Ok(Self {
             backend,
             samples,
             chunk: None,
         })
     }
+
+    /// Returns whether the backend's direct sampling mode changed.
+    async fn set_direct_sampling(
+        &mut self,
+        mode: Option<DirectSamplingMode>,
+    ) -> Result<bool, B::Error> {
+        let previous = self.backend.config().await?.direct_sampling;
+
+        self.samples = if let Some(mode) = mode {
+            SampleStream::Direct(self.backend.direct_samples(mode).await?)
+        }
+        else {
+            SampleStream::Iq(self.backend.samples().await?)
+        };
+
+        // whatever is left from the old stream has the wrong sample type.
+        self.chunk = None;
+
+        Ok(previous != mode)
+    }
 }
 
 impl<B> ConnectionHandler for BackendConnectionHandler<B>
@@ -412,22 +773,25 @@ where
         self.backend.dongle_info()
     }
 
-    async fn handle_command(&mut self, command: Command) -> Result<(), Self::Error> {
-        match command {
-            Command::SetDirectSampling { mode: _ } => {
-                // ignored
-            }
-            _ => {
-                if let Err(error) = command.apply(&self.backend).await {
-                    // log, but don't end connection
-                    tracing::error!(?error);
-                }
-            }
+    async fn config(&self) -> Result<Option<DeviceConfig>, Self::Error> {
+        Ok(Some(self.backend.config().await?))
+    }
+
+    async fn handle_command(&mut self, command: Command) -> Result<bool, Self::Error> {
+        let result = match command {
+            Command::SetDirectSampling { mode } => self.set_direct_sampling(mode).await,
+            _ => command.apply(&self.backend).await.map(|()| true),
+        };
+
+        if let Err(error) = &result {
+            // log, but don't end connection
+            tracing::error!(?error);
         }
-        Ok(())
+
+        Ok(result.unwrap_or(false))
     }
 
-    async fn read_samples(&mut self, buffer: &mut [Iq]) -> Result<usize, Self::Error> {
+    async fn read_samples(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
         loop {
             if let Some(chunk) = &mut self.chunk {
                 let n = buffer.len().min(chunk.len());
@@ -438,24 +802,811 @@ where
                 }
                 return Ok(n);
             }
+            else if let Some(chunk) = self.samples.next_chunk().await {
+                self.chunk = Some(chunk);
+            }
             else {
-                if let Some(chunk) = self.samples.try_next().await.map_err(|_| todo!())? {
-                    self.chunk = Some(chunk);
-                }
-                else {
-                    return Ok(0);
-                }
+                return Ok(0);
+            }
+        }
+    }
+}
+
+/// The sample stream of a connection, depending on the sampling mode.
+#[derive(Clone, Debug)]
+enum SampleStream {
+    Iq(Samples<Iq>),
+    Direct(Samples<u8>),
+}
+
+impl SampleStream {
+    /// Returns the next chunk as raw bytes, or `None` if the backend stopped
+    /// sending samples.
+    ///
+    /// If the sampling mode was changed, e.g. by another client, this follows
+    /// the new mode.
+    async fn next_chunk(&mut self) -> Option<Chunk<u8>> {
+        loop {
+            let chunk = match self {
+                SampleStream::Iq(samples) => next_chunk(samples).await.map(Chunk::into_bytes),
+                SampleStream::Direct(samples) => next_chunk(samples).await,
+            };
+            if chunk.is_some() {
+                return chunk;
+            }
+
+            // the stream either ended because the backend stopped, or because
+            // the sampling mode was changed. in the latter case we continue
+            // with a stream for the new sample type.
+            let sample_type = self.switched_sample_type()?;
+            tracing::debug!(?sample_type, "sampling mode changed");
+            *self = match sample_type {
+                SampleType::Iq => SampleStream::Iq(self.switch()?),
+                SampleType::I | SampleType::Q => SampleStream::Direct(self.switch()?),
+            };
+        }
+    }
+
+    fn switched_sample_type(&self) -> Option<SampleType> {
+        match self {
+            SampleStream::Iq(samples) => samples.switched_sample_type(),
+            SampleStream::Direct(samples) => samples.switched_sample_type(),
+        }
+    }
+
+    fn switch<U>(&mut self) -> Option<Samples<U>> {
+        match self {
+            SampleStream::Iq(samples) => samples.switch(),
+            SampleStream::Direct(samples) => samples.switch(),
+        }
+    }
+}
+
+/// Returns the next chunk, or `None` if the stream ended. This is also the
+/// case if the connection fell behind with [`Backpressure::Error`], so that
+/// the connection is closed.
+///
+/// [`Backpressure::Error`]: crate::Backpressure::Error
+pub(super) async fn next_chunk<T>(samples: &mut Samples<T>) -> Option<Chunk<T>> {
+    match samples.try_next().await {
+        Ok(chunk) => {
+            if let Some(chunk) = &chunk
+                && chunk.dropped() > 0
+            {
+                tracing::warn!(dropped = chunk.dropped(), "connection is lagging behind");
             }
+            chunk
+        }
+        Err(crate::Error::Lagged { chunks }) => {
+            tracing::warn!(chunks, "connection fell behind. closing it");
+            None
+        }
+        Err(error) => {
+            tracing::error!(?error);
+            None
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::Iq;
+    use std::{
+        net::SocketAddr,
+        sync::{
+            Arc,
+            atomic::{
+                AtomicUsize,
+                Ordering,
+            },
+        },
+        time::Duration,
+    };
+
+    use futures_util::TryStreamExt;
+    use tokio::{
+        net::{
+            TcpListener,
+            TcpStream,
+        },
+        sync::mpsc,
+    };
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+    use crate::{
+        Bandwidth,
+        DirectSamplingMode,
+        Gain,
+        Pacing,
+        TunerType,
+        rtl_tcp::client::{
+            Error as ClientError,
+            RtlTcpClient,
+        },
+        simulated::{
+            Scene,
+            SimulatedSdr,
+        },
+    };
+
+    const DONGLE_INFO: DongleInfo = DongleInfo {
+        tuner_type: TunerType::R820T,
+        tuner_gain_count: 29,
+    };
+
+    /// handler that records commands and sends a counting sample pattern
+    struct TestHandler {
+        commands: mpsc::UnboundedSender<Command>,
+        produced: Arc<AtomicUsize>,
+    }
+
+    impl Handler for TestHandler {
+        type Error = std::io::Error;
+        type ConnectionHandler = TestConnectionHandler;
+
+        async fn accept_connection(
+            &mut self,
+            _address: SocketAddr,
+        ) -> Result<Option<Self::ConnectionHandler>, Self::Error> {
+            Ok(Some(TestConnectionHandler {
+                commands: self.commands.clone(),
+                produced: self.produced.clone(),
+                counter: 0,
+            }))
+        }
+    }
+
+    struct TestConnectionHandler {
+        commands: mpsc::UnboundedSender<Command>,
+        produced: Arc<AtomicUsize>,
+        counter: u8,
+    }
+
+    impl ConnectionHandler for TestConnectionHandler {
+        type Error = std::io::Error;
+
+        fn dongle_info(&self) -> DongleInfo {
+            DONGLE_INFO
+        }
+
+        async fn handle_command(&mut self, command: Command) -> Result<bool, Self::Error> {
+            let _ = self.commands.send(command);
+            Ok(true)
+        }
+
+        /// implements a vendor command `0xf0`, which is an alias for
+        /// [`Command::SetTestMode`].
+        async fn unknown_command(
+            &mut self,
+            command: u8,
+            arguments: [u8; 4],
+        ) -> Result<bool, Self::Error> {
+            if command == 0xf0 {
+                let _ = self.commands.send(Command::SetTestMode {
+                    enable: u32::from_be_bytes(arguments) != 0,
+                });
+                Ok(true)
+            }
+            else {
+                Ok(false)
+            }
+        }
+
+        async fn read_samples(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+            // yield so that we don't starve the command reader
+            tokio::task::yield_now().await;
+            let samples: &mut [Iq] = bytemuck::cast_slice_mut(buffer);
+            for sample in samples.iter_mut() {
+                *sample = Iq {
+                    i: self.counter,
+                    q: !self.counter,
+                };
+                self.counter = self.counter.wrapping_add(1);
+            }
+            self.produced
+                .fetch_add(2 * samples.len(), Ordering::Relaxed);
+            Ok(2 * samples.len())
+        }
+    }
+
+    async fn spawn_server<H>(handler: H) -> (SocketAddr, CancellationToken)
+    where
+        H: Handler + Send + 'static,
+        H::ConnectionHandler: Send + 'static,
+    {
+        spawn_server_with(handler, |server| server).await
+    }
+
+    async fn spawn_server_with<H>(
+        handler: H,
+        configure: impl FnOnce(RtlTcpServer<H>) -> RtlTcpServer<H>,
+    ) -> (SocketAddr, CancellationToken)
+    where
+        H: Handler + Send + 'static,
+        H::ConnectionHandler: Send + 'static,
+    {
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = tcp_listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let server =
+            configure(RtlTcpServer::new(handler, tcp_listener).with_shutdown(shutdown.clone()));
+        tokio::spawn(async move {
+            server.serve().await.unwrap();
+        });
+        (address, shutdown)
+    }
+
+    async fn spawn_test_server() -> (
+        SocketAddr,
+        CancellationToken,
+        mpsc::UnboundedReceiver<Command>,
+    ) {
+        let (commands, command_receiver) = mpsc::unbounded_channel();
+        let (address, shutdown) = spawn_server(TestHandler {
+            commands,
+            produced: Default::default(),
+        })
+        .await;
+        (address, shutdown, command_receiver)
+    }
 
     #[test]
     fn size_of_iq_is_what_we_expect() {
         assert_eq!(std::mem::size_of::<Iq>(), 2);
     }
+
+    #[tokio::test]
+    async fn it_sends_the_header() {
+        let (address, shutdown, _commands) = spawn_test_server().await;
+
+        let mut tcp = TcpStream::connect(address).await.unwrap();
+        let mut header = [0; HEADER_LENGTH];
+        tcp.read_exact(&mut header).await.unwrap();
+        assert_eq!(&header[..4], MAGIC);
+        assert_eq!(&header[4..8], &TunerType::R820T.0.to_be_bytes());
+        assert_eq!(&header[8..], &29u32.to_be_bytes());
+
+        let client = RtlTcpClient::connect(address).await.unwrap();
+        assert_eq!(client.dongle_info(), &DONGLE_INFO);
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn it_forwards_commands() {
+        let (address, shutdown, mut commands) = spawn_test_server().await;
+        let client = RtlTcpClient::connect(address).await.unwrap();
+
+        client.set_center_frequency(100_000_000).await.unwrap();
+        client.set_sample_rate(1_024_000).await.unwrap();
+        client.set_tuner_if_gain(1, -30).await.unwrap();
+        client.set_bias_tee(true).await.unwrap();
+        client
+            .set_tuner_bandwidth(Bandwidth::Manual(1_500_000))
+            .await
+            .unwrap();
+        client.set_tuner_bandwidth(Bandwidth::Auto).await.unwrap();
+
+        let mut received = vec![];
+        for _ in 0..6 {
+            received.push(commands.recv().await.unwrap());
+        }
+        assert_eq!(
+            received,
+            vec![
+                Command::SetCenterFrequency {
+                    frequency: 100_000_000
+                },
+                Command::SetSampleRate {
+                    sample_rate: 1_024_000
+                },
+                Command::SetTunerIfGain {
+                    stage: 1,
+                    gain: -30
+                },
+                Command::SetBiasT { enable: true },
+                Command::SetTunerBandwidth {
+                    bandwidth: Bandwidth::Manual(1_500_000)
+                },
+                Command::SetTunerBandwidth {
+                    bandwidth: Bandwidth::Auto
+                },
+            ]
+        );
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn it_skips_unknown_commands() {
+        let (address, shutdown, mut commands) = spawn_test_server().await;
+
+        let mut tcp = TcpStream::connect(address).await.unwrap();
+        tcp.write_all(&[0xff, 0x01, 0x02, 0x03, 0x04])
+            .await
+            .unwrap();
+        tcp.write_all(&[0x0e, 0x00, 0x00, 0x00, 0x01])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            commands.recv().await.unwrap(),
+            Command::SetBiasT { enable: true }
+        );
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn it_closes_connections_on_unknown_commands() {
+        let (command_sender, mut commands) = mpsc::unbounded_channel();
+        let handler = TestHandler {
+            commands: command_sender,
+            produced: Default::default(),
+        };
+        let (address, shutdown) = spawn_server_with(handler, |server| {
+            server.with_unknown_command_policy(UnknownCommandPolicy::Close)
+        })
+        .await;
+
+        let mut tcp = TcpStream::connect(address).await.unwrap();
+        tcp.write_all(&[0xff, 0x01, 0x02, 0x03, 0x04])
+            .await
+            .unwrap();
+        tcp.write_all(&[0x0e, 0x00, 0x00, 0x00, 0x01])
+            .await
+            .unwrap();
+
+        // the server closes the connection without handling the second command
+        wait_for_close(&mut tcp).await;
+        assert!(commands.try_recv().is_err());
+
+        shutdown.cancel();
+    }
+
+    #[test]
+    fn it_parses_unknown_command_policies() {
+        assert_eq!(
+            "ignore".parse::<UnknownCommandPolicy>().unwrap(),
+            UnknownCommandPolicy::Ignore
+        );
+        assert_eq!(
+            "close".parse::<UnknownCommandPolicy>().unwrap(),
+            UnknownCommandPolicy::Close
+        );
+        assert_eq!(
+            "3".parse::<UnknownCommandPolicy>().unwrap(),
+            UnknownCommandPolicy::CloseAfter(NonZeroU32::new(3).unwrap())
+        );
+        assert!("0".parse::<UnknownCommandPolicy>().is_err());
+        assert!("never".parse::<UnknownCommandPolicy>().is_err());
+    }
+
+    #[tokio::test]
+    async fn it_closes_connections_after_too_many_unknown_commands() {
+        let (command_sender, mut commands) = mpsc::unbounded_channel();
+        let handler = TestHandler {
+            commands: command_sender,
+            produced: Default::default(),
+        };
+        let (address, shutdown) = spawn_server_with(handler, |server| {
+            server.with_unknown_command_policy(UnknownCommandPolicy::CloseAfter(
+                NonZeroU32::new(2).unwrap(),
+            ))
+        })
+        .await;
+
+        let mut tcp = TcpStream::connect(address).await.unwrap();
+        tcp.write_all(&[0xff, 0x01, 0x02, 0x03, 0x04])
+            .await
+            .unwrap();
+        tcp.write_all(&[0x0e, 0x00, 0x00, 0x00, 0x01])
+            .await
+            .unwrap();
+        assert_eq!(
+            commands.recv().await.unwrap(),
+            Command::SetBiasT { enable: true }
+        );
+
+        tcp.write_all(&[0xff, 0x01, 0x02, 0x03, 0x04])
+            .await
+            .unwrap();
+        tcp.write_all(&[0x0e, 0x00, 0x00, 0x00, 0x00])
+            .await
+            .unwrap();
+        wait_for_close(&mut tcp).await;
+        assert!(commands.try_recv().is_err());
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn connection_handler_can_handle_unknown_commands() {
+        let (command_sender, mut commands) = mpsc::unbounded_channel();
+        let handler = TestHandler {
+            commands: command_sender,
+            produced: Default::default(),
+        };
+        let (address, shutdown) = spawn_server_with(handler, |server| {
+            server.with_unknown_command_policy(UnknownCommandPolicy::Close)
+        })
+        .await;
+
+        let mut tcp = TcpStream::connect(address).await.unwrap();
+        tcp.write_all(&[0xf0, 0x00, 0x00, 0x00, 0x01])
+            .await
+            .unwrap();
+        tcp.write_all(&[0x0e, 0x00, 0x00, 0x00, 0x01])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            commands.recv().await.unwrap(),
+            Command::SetTestMode { enable: true }
+        );
+        assert_eq!(
+            commands.recv().await.unwrap(),
+            Command::SetBiasT { enable: true }
+        );
+
+        shutdown.cancel();
+    }
+
+    /// reads from the connection until the server closes it
+    async fn wait_for_close(tcp: &mut TcpStream) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            let mut buffer = [0; 0x1000];
+            while tcp.read(&mut buffer).await.unwrap_or_default() > 0 {}
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn client_tracks_sent_commands() {
+        let (address, shutdown, _commands) = spawn_test_server().await;
+        let client = RtlTcpClient::connect(address).await.unwrap();
+        assert_eq!(client.config(), DeviceConfig::default());
+
+        client.set_center_frequency(100_000_000).await.unwrap();
+        client.set_tuner_gain(Gain::ManualIndex(3)).await.unwrap();
+        client.set_tuner_if_gain(1, -30).await.unwrap();
+        client.set_tuner_if_gain(1, 20).await.unwrap();
+        client.set_bias_tee(true).await.unwrap();
+
+        assert_eq!(
+            client.config(),
+            DeviceConfig {
+                center_frequency: Some(100_000_000),
+                tuner_gain: Some(Gain::ManualIndex(3)),
+                tuner_if_gains: vec![(1, 20)],
+                bias_tee: Some(true),
+                ..Default::default()
+            }
+        );
+
+        client.set_tuner_gain(Gain::Auto).await.unwrap();
+        assert_eq!(client.config().tuner_gain, Some(Gain::Auto));
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn it_sends_a_config_snapshot() {
+        let sdr = SimulatedSdr::with_pacing(Scene::new(), Pacing::Unpaced);
+        sdr.set_center_frequency(433_920_000).await.unwrap();
+        sdr.set_tuner_gain(Gain::ManualValue(197)).await.unwrap();
+
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = tcp_listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let server = RtlTcpServer::from_backend(sdr.clone(), tcp_listener)
+            .with_shutdown(shutdown.clone())
+            .with_config_snapshot(true);
+        tokio::spawn(async move {
+            server.serve().await.unwrap();
+        });
+
+        let client = RtlTcpClient::builder()
+            .with_config_snapshot(true)
+            .connect(address)
+            .await
+            .unwrap();
+        assert_eq!(client.config(), sdr.config().await.unwrap());
+        assert_eq!(client.config().center_frequency, Some(433_920_000));
+
+        // the connection still works after the snapshot
+        let mut samples = client.samples().await.unwrap();
+        let chunk = samples.try_next().await.unwrap().unwrap();
+        assert!(!chunk.is_empty());
+
+        shutdown.cancel();
+    }
+
+    async fn next_marked_chunk(samples: &mut Samples<Iq>) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !samples.try_next().await.unwrap().unwrap().config_changed() {}
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_reports_config_changes() {
+        let sdr = SimulatedSdr::with_pacing(Scene::new(), Pacing::Unpaced);
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = tcp_listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let server = RtlTcpServer::new(BackendHandler::new(sdr), tcp_listener)
+            .with_shutdown(shutdown.clone());
+        let mut server_changes = server.config_changes();
+        tokio::spawn(async move {
+            server.serve().await.unwrap();
+        });
+
+        let client = RtlTcpClient::connect(address).await.unwrap();
+        let mut client_changes = client.config_changes();
+
+        client.set_tuner_gain(Gain::ManualValue(197)).await.unwrap();
+        let expected = ConfigEvent::TunerGain {
+            gain: Gain::ManualValue(197),
+        };
+        assert_eq!(client_changes.recv().await, Some(expected));
+        assert_eq!(server_changes.recv().await, Some(expected));
+
+        // direct sampling was already disabled, so disabling it for the stream
+        // isn't reported. the client only starts receiving once we read
+        // samples, so the gain is marked on the first chunk.
+        let mut samples = client.samples().await.unwrap();
+        next_marked_chunk(&mut samples).await;
+
+        client.set_center_frequency(100_000_000).await.unwrap();
+        let expected = ConfigEvent::CenterFrequency {
+            frequency: 100_000_000,
+        };
+        assert_eq!(client_changes.recv().await, Some(expected));
+        assert_eq!(server_changes.recv().await, Some(expected));
+        next_marked_chunk(&mut samples).await;
+
+        // the retune is marked only once
+        for _ in 0..64 {
+            let chunk = samples.try_next().await.unwrap().unwrap();
+            assert!(!chunk.config_changed());
+        }
+
+        // neither is a command that doesn't change anything
+        client.set_direct_sampling(None).await.unwrap();
+        for _ in 0..64 {
+            let chunk = samples.try_next().await.unwrap().unwrap();
+            assert!(!chunk.config_changed());
+        }
+
+        // and it isn't reported either, so the next event is the next change
+        client.set_center_frequency(101_000_000).await.unwrap();
+        let expected = ConfigEvent::CenterFrequency {
+            frequency: 101_000_000,
+        };
+        assert_eq!(client_changes.recv().await, Some(expected));
+        assert_eq!(server_changes.recv().await, Some(expected));
+        assert_eq!(client_changes.missed(), 0);
+
+        shutdown.cancel();
+    }
+
+    #[test]
+    fn config_commands_round_trip() {
+        let config = DeviceConfig {
+            center_frequency: Some(100_000_000),
+            sample_rate: Some(2_048_000),
+            tuner_gain: Some(Gain::ManualValue(197)),
+            tuner_if_gains: vec![(1, -30), (2, 20)],
+            tuner_bandwidth: Some(Bandwidth::Auto),
+            agc_mode: Some(true),
+            frequency_correction: Some(-3),
+            offset_tuning: Some(false),
+            rtl_xtal: Some(28_800_000),
+            tuner_xtal: None,
+            bias_tee: Some(true),
+            direct_sampling: Some(DirectSamplingMode::Q),
+        };
+
+        let mut applied = DeviceConfig::default();
+        for command in config.commands() {
+            applied.apply_command(&command);
+        }
+        assert_eq!(applied, config);
+    }
+
+    #[test]
+    fn automatic_bandwidth_is_encoded_as_zero() {
+        let mut buffer = [0xff; COMMAND_LENGTH];
+        Command::SetTunerBandwidth {
+            bandwidth: Bandwidth::Auto,
+        }
+        .encode(&mut buffer[..]);
+        assert_eq!(buffer, [0x40, 0, 0, 0, 0]);
+        assert_eq!(
+            Command::decode(&buffer[..]).unwrap(),
+            Command::SetTunerBandwidth {
+                bandwidth: Bandwidth::Auto
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn it_forwards_samples() {
+        let (address, shutdown, _commands) = spawn_test_server().await;
+        let client = RtlTcpClient::connect(address).await.unwrap();
+
+        let mut samples = client.samples().await.unwrap();
+        for _ in 0..4 {
+            let chunk = samples.try_next().await.unwrap().unwrap();
+            assert!(!chunk.is_empty());
+
+            // within a chunk the samples must be contiguous and aligned
+            let first = chunk.samples()[0].i;
+            for (n, sample) in chunk.samples().iter().enumerate() {
+                let expected = first.wrapping_add(n as u8);
+                assert_eq!(
+                    *sample,
+                    Iq {
+                        i: expected,
+                        q: !expected
+                    }
+                );
+            }
+        }
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn client_only_reads_when_there_are_receivers() {
+        let (commands, _command_receiver) = mpsc::unbounded_channel();
+        let produced = Arc::new(AtomicUsize::new(0));
+        let (address, shutdown) = spawn_server(TestHandler {
+            commands,
+            produced: produced.clone(),
+        })
+        .await;
+        let client = RtlTcpClient::connect(address).await.unwrap();
+
+        // once the socket buffers are full, the server can't send anymore
+        let mut last = usize::MAX;
+        loop {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let now = produced.load(Ordering::Relaxed);
+            if now == last {
+                break;
+            }
+            last = now;
+        }
+
+        // once we read the buffered samples, the server continues
+        let mut samples = client.samples().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while produced.load(Ordering::Relaxed) == last {
+                samples.try_next().await.unwrap().unwrap();
+            }
+        })
+        .await
+        .unwrap();
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn client_uses_configured_buffer_size() {
+        let (address, shutdown, _commands) = spawn_test_server().await;
+
+        assert!(matches!(
+            RtlTcpClient::builder()
+                .with_buffer_size(0x401)
+                .connect(address)
+                .await,
+            Err(ClientError::InvalidBufferSize { buffer_size: 0x401 })
+        ));
+        assert!(matches!(
+            RtlTcpClient::builder()
+                .with_queue_size(1)
+                .connect(address)
+                .await,
+            Err(ClientError::InvalidQueueSize { queue_size: 1 })
+        ));
+
+        let client = RtlTcpClient::builder()
+            .with_buffer_size(0x400)
+            .with_queue_size(4)
+            .connect(address)
+            .await
+            .unwrap();
+        let mut samples = client.samples().await.unwrap();
+        let chunk = samples.try_next().await.unwrap().unwrap();
+        assert_eq!(chunk.as_bytes().len(), 0x400);
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn it_serves_a_backend() {
+        let sdr = SimulatedSdr::with_pacing(Scene::new(), Pacing::Unpaced);
+        let (address, shutdown) = spawn_server(BackendHandler::new(sdr.clone())).await;
+        let client = RtlTcpClient::connect(address).await.unwrap();
+        assert_eq!(client.dongle_info(), &sdr.dongle_info());
+
+        client.set_center_frequency(433_920_000).await.unwrap();
+        client.set_tuner_gain(Gain::ManualValue(197)).await.unwrap();
+        client
+            .set_tuner_bandwidth(Bandwidth::Manual(1_000_000))
+            .await
+            .unwrap();
+
+        // the client only waits for the command to be sent, so wait until the
+        // server applied it.
+        let mut samples = client.samples().await.unwrap();
+        while sdr.config().await.unwrap().tuner_bandwidth != Some(Bandwidth::Manual(1_000_000)) {
+            samples.try_next().await.unwrap().unwrap();
+        }
+        assert_eq!(sdr.get_center_frequency(), 433_920_000);
+        assert_eq!(sdr.get_tuner_gain(), 197);
+
+        let chunk = samples.try_next().await.unwrap().unwrap();
+        assert!(!chunk.is_empty());
+        assert_eq!(chunk.center_frequency(), Some(433_920_000));
+        assert_eq!(chunk.tuner_gain(), Some(Gain::ManualValue(197)));
+        assert_eq!(chunk.frequency_correction(), None);
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn it_switches_sampling_mode() {
+        let sdr = SimulatedSdr::with_pacing(Scene::new(), Pacing::Unpaced);
+        let (address, shutdown) = spawn_server(BackendHandler::new(sdr.clone())).await;
+        let client = RtlTcpClient::connect(address).await.unwrap();
+
+        let mut samples = client.direct_samples(DirectSamplingMode::Q).await.unwrap();
+        while sdr.get_direct_sampling() != Some(DirectSamplingMode::Q) {
+            samples.try_next().await.unwrap().unwrap();
+        }
+
+        // switching back must not close the connection
+        let mut samples = client.samples().await.unwrap();
+        while sdr.get_direct_sampling().is_some() {
+            samples.try_next().await.unwrap().unwrap();
+        }
+        let chunk = samples.try_next().await.unwrap().unwrap();
+        assert!(!chunk.is_empty());
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn it_follows_sampling_mode_changes() {
+        let sdr = SimulatedSdr::with_pacing(Scene::new(), Pacing::Unpaced);
+        let mut handler = BackendConnectionHandler::new(sdr.clone()).await.unwrap();
+        let mut buffer = vec![0; 0x100];
+
+        // e.g. another client switches to direct sampling
+        let mut direct_samples = sdr.direct_samples(DirectSamplingMode::I).await.unwrap();
+        let direct_chunk = direct_samples.try_next().await.unwrap().unwrap();
+
+        let mut num_bytes = 0;
+        while !matches!(handler.samples, SampleStream::Direct(_)) {
+            num_bytes = handler.read_samples(&mut buffer).await.unwrap();
+            assert!(num_bytes > 0);
+        }
+
+        // one byte per sample
+        let chunk = handler.chunk.as_ref().unwrap();
+        assert_eq!(num_bytes + chunk.as_bytes().len(), direct_chunk.len());
+
+        handler
+            .handle_command(Command::SetDirectSampling { mode: None })
+            .await
+            .unwrap();
+        assert!(matches!(handler.samples, SampleStream::Iq(_)));
+        assert_eq!(sdr.get_direct_sampling(), None);
+        assert!(handler.read_samples(&mut buffer).await.unwrap() > 0);
+    }
 }
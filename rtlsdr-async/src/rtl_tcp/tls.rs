@@ -0,0 +1,444 @@
+//! `rtl_tcp` over TLS.
+//!
+//! A server is set up by passing a [`TlsListener`] to
+//! [`RtlTcpServer::new`][super::server::RtlTcpServer::new]. If the server
+//! config is created with a client CA, clients must authenticate with a
+//! certificate signed by it.
+//!
+//! A client connects with [`connect`] and then talks to the server with
+//! [`RtlTcpClientBuilder::connect_stream`][super::client::RtlTcpClientBuilder::connect_stream].
+//!
+//! The handshake is done with `rustls` using the `ring` crypto provider.
+
+use std::{
+    net::SocketAddr,
+    path::Path,
+    sync::Arc,
+};
+
+use tokio::net::{
+    TcpListener,
+    TcpStream,
+    ToSocketAddrs,
+};
+pub use tokio_rustls::rustls;
+use tokio_rustls::{
+    TlsAcceptor,
+    TlsConnector,
+    client,
+    rustls::{
+        ClientConfig,
+        RootCertStore,
+        ServerConfig,
+        crypto::CryptoProvider,
+        pki_types::{
+            CertificateDer,
+            PrivateKeyDer,
+            ServerName,
+            pem::PemObject,
+        },
+        server::WebPkiClientVerifier,
+    },
+    server,
+};
+
+use crate::rtl_tcp::transport::{
+    Incoming,
+    Listener,
+};
+
+#[derive(Debug, thiserror::Error)]
+#[error("rtl_tcp TLS error")]
+pub enum Error {
+    Pem(#[from] rustls::pki_types::pem::Error),
+    Rustls(#[from] rustls::Error),
+    Verifier(#[from] rustls::server::VerifierBuilderError),
+}
+
+/// [`Listener`] that accepts TLS connections.
+pub struct TlsListener {
+    tcp_listener: TcpListener,
+    acceptor: TlsAcceptor,
+}
+
+impl TlsListener {
+    pub fn new(tcp_listener: TcpListener, config: Arc<ServerConfig>) -> Self {
+        Self {
+            tcp_listener,
+            acceptor: config.into(),
+        }
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, std::io::Error> {
+        self.tcp_listener.local_addr()
+    }
+}
+
+impl Listener for TlsListener {
+    type Incoming = TlsIncoming;
+
+    async fn accept(&mut self) -> Result<(Self::Incoming, SocketAddr), std::io::Error> {
+        let (stream, address) = self.tcp_listener.accept().await?;
+        Ok((
+            TlsIncoming {
+                stream,
+                acceptor: self.acceptor.clone(),
+            },
+            address,
+        ))
+    }
+}
+
+/// A TLS connection that still needs to do its handshake.
+pub struct TlsIncoming {
+    stream: TcpStream,
+    acceptor: TlsAcceptor,
+}
+
+impl Incoming for TlsIncoming {
+    type Stream = server::TlsStream<TcpStream>;
+
+    async fn establish(self) -> Result<Self::Stream, std::io::Error> {
+        self.acceptor.accept(self.stream).await
+    }
+}
+
+/// Creates the config for a server with the certificate chain `cert_chain`
+/// and its private key.
+///
+/// If `client_ca` is given, clients must present a certificate signed by one
+/// of these certificates.
+pub fn server_config(
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    client_ca: Option<Vec<CertificateDer<'static>>>,
+) -> Result<ServerConfig, Error> {
+    let provider = crypto_provider();
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = if let Some(client_ca) = client_ca {
+        let verifier = WebPkiClientVerifier::builder_with_provider(
+            root_cert_store(client_ca)?.into(),
+            provider,
+        )
+        .build()?;
+        builder.with_client_cert_verifier(verifier)
+    }
+    else {
+        builder.with_no_client_auth()
+    };
+
+    Ok(builder.with_single_cert(cert_chain, key)?)
+}
+
+/// Creates the config for a client that trusts the certificates in `roots`.
+///
+/// If the server requires client authentication, `client_auth` is the
+/// client's certificate chain and private key.
+pub fn client_config(
+    roots: Vec<CertificateDer<'static>>,
+    client_auth: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+) -> Result<ClientConfig, Error> {
+    let builder = ClientConfig::builder_with_provider(crypto_provider())
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(root_cert_store(roots)?);
+
+    if let Some((cert_chain, key)) = client_auth {
+        Ok(builder.with_client_auth_cert(cert_chain, key)?)
+    }
+    else {
+        Ok(builder.with_no_client_auth())
+    }
+}
+
+/// Connects to a server at `address` and does the TLS handshake.
+///
+/// `server_name` is the name that the server's certificate must be valid
+/// for.
+pub async fn connect<A: ToSocketAddrs>(
+    address: A,
+    server_name: ServerName<'static>,
+    config: Arc<ClientConfig>,
+) -> Result<client::TlsStream<TcpStream>, std::io::Error> {
+    let tcp = TcpStream::connect(address).await?;
+    TlsConnector::from(config).connect(server_name, tcp).await
+}
+
+/// Reads all certificates from a PEM file.
+pub fn load_certs(path: impl AsRef<Path>) -> Result<Vec<CertificateDer<'static>>, Error> {
+    Ok(CertificateDer::pem_file_iter(path)?.collect::<Result<_, _>>()?)
+}
+
+/// Reads the first private key from a PEM file.
+pub fn load_private_key(path: impl AsRef<Path>) -> Result<PrivateKeyDer<'static>, Error> {
+    Ok(PrivateKeyDer::from_pem_file(path)?)
+}
+
+fn crypto_provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn root_cert_store(certs: Vec<CertificateDer<'static>>) -> Result<RootCertStore, Error> {
+    let mut store = RootCertStore::empty();
+    for cert in certs {
+        store.add(cert)?;
+    }
+    Ok(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::TryStreamExt;
+    use rcgen::{
+        BasicConstraints,
+        CertificateParams,
+        CertifiedIssuer,
+        IsCa,
+        KeyPair,
+    };
+    use rustls::pki_types::PrivatePkcs8KeyDer;
+    use tokio::io::AsyncReadExt;
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+    use crate::{
+        Pacing,
+        rtl_tcp::{
+            access_control::{
+                AccessControlHandler,
+                AccessPolicy,
+            },
+            client::{
+                Error as ClientError,
+                RtlTcpClient,
+            },
+            server::{
+                BackendHandler,
+                DEFAULT_ESTABLISH_TIMEOUT,
+                DEFAULT_MAX_ESTABLISHING,
+                Handler,
+                RtlTcpServer,
+            },
+        },
+        simulated::{
+            Scene,
+            SimulatedSdr,
+        },
+    };
+
+    struct Certs {
+        ca: CertificateDer<'static>,
+        server: (CertificateDer<'static>, PrivateKeyDer<'static>),
+        client: (CertificateDer<'static>, PrivateKeyDer<'static>),
+    }
+
+    impl Certs {
+        fn generate() -> Self {
+            let mut params = CertificateParams::new(vec![]).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap();
+
+            let leaf = |name: &str| {
+                let key = KeyPair::generate().unwrap();
+                let cert = CertificateParams::new(vec![name.to_owned()])
+                    .unwrap()
+                    .signed_by(&key, &ca)
+                    .unwrap();
+                (
+                    cert.der().clone(),
+                    PrivatePkcs8KeyDer::from(key.serialize_der()).into(),
+                )
+            };
+
+            Self {
+                ca: ca.der().clone(),
+                server: leaf("localhost"),
+                client: leaf("client"),
+            }
+        }
+
+        fn server_config(&self, client_auth: bool) -> ServerConfig {
+            server_config(
+                vec![self.server.0.clone()],
+                self.server.1.clone_key(),
+                client_auth.then(|| vec![self.ca.clone()]),
+            )
+            .unwrap()
+        }
+
+        fn client_config(&self, client_auth: bool) -> ClientConfig {
+            client_config(
+                vec![self.ca.clone()],
+                client_auth.then(|| (vec![self.client.0.clone()], self.client.1.clone_key())),
+            )
+            .unwrap()
+        }
+    }
+
+    async fn spawn_server(config: ServerConfig) -> (SocketAddr, CancellationToken) {
+        spawn_server_with(
+            BackendHandler::new(simulated_sdr()),
+            config,
+            DEFAULT_ESTABLISH_TIMEOUT,
+            DEFAULT_MAX_ESTABLISHING,
+        )
+        .await
+    }
+
+    async fn spawn_server_with<H>(
+        handler: H,
+        config: ServerConfig,
+        establish_timeout: Duration,
+        max_establishing: usize,
+    ) -> (SocketAddr, CancellationToken)
+    where
+        H: Handler + Send + 'static,
+        H::ConnectionHandler: Send + 'static,
+    {
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listener = TlsListener::new(tcp_listener, Arc::new(config));
+        let address = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let server = RtlTcpServer::new(handler, listener)
+            .with_shutdown(shutdown.clone())
+            .with_establish_timeout(establish_timeout)
+            .with_max_establishing(max_establishing);
+        tokio::spawn(async move {
+            server.serve().await.unwrap();
+        });
+        (address, shutdown)
+    }
+
+    fn simulated_sdr() -> SimulatedSdr {
+        SimulatedSdr::with_pacing(Scene::new(), Pacing::Unpaced)
+    }
+
+    async fn connect(
+        address: SocketAddr,
+        config: ClientConfig,
+    ) -> Result<RtlTcpClient, ClientError> {
+        let stream = super::connect(
+            address,
+            ServerName::try_from("localhost").unwrap(),
+            Arc::new(config),
+        )
+        .await?;
+        RtlTcpClient::builder().connect_stream(stream).await
+    }
+
+    #[tokio::test]
+    async fn it_serves_over_tls() {
+        let certs = Certs::generate();
+        let (address, shutdown) = spawn_server(certs.server_config(false)).await;
+
+        let client = connect(address, certs.client_config(false)).await.unwrap();
+        client.set_center_frequency(433_920_000).await.unwrap();
+        let mut samples = client.samples().await.unwrap();
+        let chunk = samples.try_next().await.unwrap().unwrap();
+        assert!(!chunk.is_empty());
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn it_authenticates_clients() {
+        let certs = Certs::generate();
+        let (address, shutdown) = spawn_server(certs.server_config(true)).await;
+
+        assert!(connect(address, certs.client_config(false)).await.is_err());
+
+        let client = connect(address, certs.client_config(true)).await.unwrap();
+        let mut samples = client.samples().await.unwrap();
+        assert!(samples.try_next().await.unwrap().is_some());
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn clients_only_count_after_the_handshake() {
+        let certs = Certs::generate();
+        let handler = AccessControlHandler::new(
+            BackendHandler::new(simulated_sdr()),
+            AccessPolicy::default()
+                .with_max_clients(1)
+                .with_first_client_controls(true),
+        );
+        let (address, shutdown) = spawn_server_with(
+            handler,
+            certs.server_config(false),
+            Duration::from_millis(500),
+            DEFAULT_MAX_ESTABLISHING,
+        )
+        .await;
+
+        // never starts the handshake
+        let mut idle = TcpStream::connect(address).await.unwrap();
+
+        let client = connect(address, certs.client_config(false)).await.unwrap();
+        let mut samples = client.samples().await.unwrap();
+        assert!(samples.try_next().await.unwrap().is_some());
+
+        // the idle connection is closed once the handshake times out
+        let n = tokio::time::timeout(Duration::from_secs(5), idle.read(&mut [0; 1]))
+            .await
+            .unwrap()
+            .unwrap_or_default();
+        assert_eq!(n, 0);
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn denied_addresses_are_closed_before_the_handshake() {
+        let certs = Certs::generate();
+        let handler = AccessControlHandler::new(
+            BackendHandler::new(simulated_sdr()),
+            AccessPolicy::default().with_deny("127.0.0.1".parse().unwrap()),
+        );
+        let (address, shutdown) = spawn_server_with(
+            handler,
+            certs.server_config(false),
+            DEFAULT_ESTABLISH_TIMEOUT,
+            DEFAULT_MAX_ESTABLISHING,
+        )
+        .await;
+
+        // closed right away, not after the handshake timed out
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let n = tokio::time::timeout(Duration::from_secs(1), stream.read(&mut [0; 1]))
+            .await
+            .unwrap()
+            .unwrap_or_default();
+        assert_eq!(n, 0);
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn concurrent_handshakes_are_limited() {
+        let certs = Certs::generate();
+        let establish_timeout = Duration::from_millis(500);
+        let (address, shutdown) = spawn_server_with(
+            BackendHandler::new(simulated_sdr()),
+            certs.server_config(false),
+            establish_timeout,
+            1,
+        )
+        .await;
+
+        // takes up the only slot until it times out
+        let _idle = TcpStream::connect(address).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let start = tokio::time::Instant::now();
+        let client = connect(address, certs.client_config(false)).await.unwrap();
+        assert!(start.elapsed() >= establish_timeout / 2);
+        let mut samples = client.samples().await.unwrap();
+        assert!(samples.try_next().await.unwrap().is_some());
+
+        shutdown.cancel();
+    }
+}
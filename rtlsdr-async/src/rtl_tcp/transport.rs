@@ -0,0 +1,57 @@
+//! Transports that the `rtl_tcp` protocol can be spoken over.
+//!
+//! [`RtlTcpServer`][super::server::RtlTcpServer] accepts connections from a
+//! [`Listener`], which is a [`TcpListener`] by default. Accepted connections
+//! are [`establish`][Incoming::establish]ed in their own task, so e.g. a slow
+//! TLS handshake doesn't hold up other clients.
+//!
+//! [`RtlTcpClient`][super::client::RtlTcpClient] can talk over any stream, see
+//! [`RtlTcpClientBuilder::connect_stream`][super::client::RtlTcpClientBuilder::connect_stream].
+
+use std::net::SocketAddr;
+
+use tokio::{
+    io::{
+        AsyncRead,
+        AsyncWrite,
+    },
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+};
+
+/// Accepts incoming connections for a server.
+pub trait Listener {
+    type Incoming: Incoming;
+
+    fn accept(
+        &mut self,
+    ) -> impl Future<Output = Result<(Self::Incoming, SocketAddr), std::io::Error>> + Send;
+}
+
+/// A connection that was accepted, but might not be ready yet.
+pub trait Incoming: Send + 'static {
+    type Stream: AsyncRead + AsyncWrite + Send + Unpin + 'static;
+
+    /// Finishes setting up the connection, e.g. with a handshake.
+    fn establish(self) -> impl Future<Output = Result<Self::Stream, std::io::Error>> + Send;
+}
+
+impl Listener for TcpListener {
+    type Incoming = TcpStream;
+
+    fn accept(
+        &mut self,
+    ) -> impl Future<Output = Result<(Self::Incoming, SocketAddr), std::io::Error>> + Send {
+        TcpListener::accept(self)
+    }
+}
+
+impl Incoming for TcpStream {
+    type Stream = TcpStream;
+
+    async fn establish(self) -> Result<Self::Stream, std::io::Error> {
+        Ok(self)
+    }
+}
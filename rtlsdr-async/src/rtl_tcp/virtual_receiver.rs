@@ -0,0 +1,556 @@
+//! Virtual receivers for [`RtlTcpServer`][super::server::RtlTcpServer].
+//!
+//! Normally all clients of a server share one tuner, so a client that retunes
+//! also retunes everyone else. With [`VirtualReceiverHandler`] the dongle runs
+//! at a fixed center frequency and a wide sample rate instead, and every
+//! client gets its own channel inside that band through a [`Ddc`].
+//!
+//! [`Command::SetCenterFrequency`] and [`Command::SetSampleRate`] select the
+//! channel. The sample rate must be the band's sample rate divided by an
+//! integer. Channels that don't fit into the band are handled according to
+//! the [`OutOfBandPolicy`]. All other commands would change the dongle for
+//! all clients, so they're ignored.
+
+use std::net::SocketAddr;
+
+use crate::{
+    Backend,
+    DeviceConfig,
+    DongleInfo,
+    Iq,
+    Samples,
+    ddc::Ddc,
+    rtl_tcp::{
+        Command,
+        server::{
+            ConnectionHandler,
+            Handler,
+            next_chunk,
+        },
+    },
+};
+
+/// The largest factor by which the band's sample rate can be divided to get
+/// a channel's sample rate.
+pub const MAX_DECIMATION: u32 = 1024;
+
+/// What to do when a client selects a channel that doesn't fit into the
+/// band.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutOfBandPolicy {
+    /// Ignore the command, so the client keeps its current channel.
+    #[default]
+    Reject,
+
+    /// Move the channel to the nearest one that fits.
+    Clamp,
+}
+
+/// The band that the dongle captures, and in which the virtual receivers are
+/// placed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Band {
+    /// Center frequency in Hz
+    pub center_frequency: u32,
+
+    /// Sample rate in Hz
+    pub sample_rate: u32,
+}
+
+/// [`Handler`] that gives every connection its own virtual receiver.
+#[derive(Clone, Debug)]
+pub struct VirtualReceiverHandler<B> {
+    backend: B,
+    band: Band,
+    out_of_band: OutOfBandPolicy,
+}
+
+impl<B> VirtualReceiverHandler<B>
+where
+    B: Backend,
+{
+    /// Tunes `backend` to `band`.
+    ///
+    /// The backend should not be retuned afterwards, since the virtual
+    /// receivers wouldn't notice.
+    pub async fn new(backend: B, band: Band) -> Result<Self, B::Error> {
+        backend.set_sample_rate(band.sample_rate).await?;
+        backend.set_center_frequency(band.center_frequency).await?;
+
+        Ok(Self {
+            backend,
+            band,
+            out_of_band: OutOfBandPolicy::default(),
+        })
+    }
+
+    /// What to do when a client selects a channel outside of the band.
+    ///
+    /// Default is [`OutOfBandPolicy::Reject`].
+    pub fn with_out_of_band_policy(mut self, policy: OutOfBandPolicy) -> Self {
+        self.out_of_band = policy;
+        self
+    }
+
+    pub fn band(&self) -> Band {
+        self.band
+    }
+}
+
+impl<B> Handler for VirtualReceiverHandler<B>
+where
+    B: Backend + Clone + Send + Sync + Unpin + 'static,
+{
+    type Error = B::Error;
+    type ConnectionHandler = VirtualReceiverConnectionHandler<B>;
+
+    async fn accept_connection(
+        &mut self,
+        _address: SocketAddr,
+    ) -> Result<Option<Self::ConnectionHandler>, Self::Error> {
+        let samples = self.backend.samples().await?;
+
+        Ok(Some(VirtualReceiverConnectionHandler {
+            backend: self.backend.clone(),
+            samples,
+            band: self.band,
+            out_of_band: self.out_of_band,
+            // new clients get the whole band
+            ddc: Ddc::new(self.band.sample_rate, 0, 1),
+            output: vec![],
+            read_pos: 0,
+        }))
+    }
+}
+
+/// The virtual receiver of a single connection.
+#[derive(Debug)]
+pub struct VirtualReceiverConnectionHandler<B> {
+    backend: B,
+    samples: Samples<Iq>,
+    band: Band,
+    out_of_band: OutOfBandPolicy,
+    ddc: Ddc,
+
+    /// down-converted samples that weren't sent yet
+    output: Vec<Iq>,
+    read_pos: usize,
+}
+
+impl<B> VirtualReceiverConnectionHandler<B> {
+    /// Center frequency of the channel in Hz.
+    pub fn center_frequency(&self) -> u32 {
+        self.band
+            .center_frequency
+            .saturating_add_signed(self.ddc.offset())
+    }
+
+    /// Sample rate of the channel in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.ddc.output_sample_rate()
+    }
+
+    fn set_center_frequency(&mut self, frequency: u32) {
+        let offset = i64::from(frequency) - i64::from(self.band.center_frequency);
+        if let Some(offset) = self.fit(offset, self.ddc.decimation()) {
+            self.ddc.set_offset(offset);
+        }
+        else {
+            tracing::warn!(frequency, band = ?self.band, "rejecting center frequency outside of band");
+        }
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        if sample_rate == 0 {
+            tracing::warn!("rejecting sample rate of 0 Hz");
+            return;
+        }
+
+        let mut decimation = (f64::from(self.band.sample_rate) / f64::from(sample_rate))
+            .round()
+            .max(1.0) as u32;
+
+        if decimation > MAX_DECIMATION {
+            if self.out_of_band == OutOfBandPolicy::Reject {
+                tracing::warn!(sample_rate, band = ?self.band, "rejecting sample rate that is too low");
+                return;
+            }
+            decimation = MAX_DECIMATION;
+        }
+        else if self.band.sample_rate / decimation != sample_rate
+            && self.out_of_band == OutOfBandPolicy::Reject
+        {
+            tracing::warn!(
+                sample_rate,
+                band = ?self.band,
+                "rejecting sample rate that the band's sample rate isn't a multiple of"
+            );
+            return;
+        }
+
+        // a wider channel might not fit at the current offset anymore
+        if let Some(offset) = self.fit(self.ddc.offset().into(), decimation) {
+            self.ddc.set_decimation(decimation);
+            self.ddc.set_offset(offset);
+            self.output.clear();
+            self.read_pos = 0;
+        }
+        else {
+            tracing::warn!(sample_rate, band = ?self.band, "rejecting sample rate, since the channel wouldn't fit into the band");
+        }
+    }
+
+    /// returns the offset for a channel with `offset` and `decimation`, if it
+    /// fits into the band, or the nearest one if the policy allows it.
+    fn fit(&self, offset: i64, decimation: u32) -> Option<i32> {
+        let band_width = i64::from(self.band.sample_rate);
+        let channel_width = band_width / i64::from(decimation);
+        let max_offset = (band_width - channel_width) / 2;
+
+        if (-max_offset..=max_offset).contains(&offset) {
+            offset.try_into().ok()
+        }
+        else if self.out_of_band == OutOfBandPolicy::Clamp {
+            offset.clamp(-max_offset, max_offset).try_into().ok()
+        }
+        else {
+            None
+        }
+    }
+}
+
+impl<B> ConnectionHandler for VirtualReceiverConnectionHandler<B>
+where
+    B: Backend + Send + Sync + 'static,
+{
+    type Error = B::Error;
+
+    fn dongle_info(&self) -> DongleInfo {
+        self.backend.dongle_info()
+    }
+
+    async fn config(&self) -> Result<Option<DeviceConfig>, Self::Error> {
+        let mut config = self.backend.config().await?;
+        config.center_frequency = Some(self.center_frequency());
+        config.sample_rate = Some(self.sample_rate());
+        Ok(Some(config))
+    }
+
+    async fn handle_command(&mut self, command: Command) -> Result<bool, Self::Error> {
+        match command {
+            Command::SetCenterFrequency { frequency } => self.set_center_frequency(frequency),
+            Command::SetSampleRate { sample_rate } => self.set_sample_rate(sample_rate),
+            _ => {
+                tracing::debug!(?command, "ignoring command for virtual receiver");
+            }
+        }
+
+        // the channel belongs to this connection only, so the device never
+        // changes.
+        Ok(false)
+    }
+
+    async fn read_samples(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+        while self.read_pos == self.output.len() {
+            let Some(chunk) = next_chunk(&mut self.samples).await
+            else {
+                return Ok(0);
+            };
+            self.output.clear();
+            self.read_pos = 0;
+            self.ddc.process(chunk.samples(), &mut self.output);
+
+            // down-converting is expensive, and if we're lagging behind the next
+            // chunk is ready immediately. so we give other connections a chance
+            // to run.
+            tokio::task::yield_now().await;
+        }
+
+        let output: &[u8] = bytemuck::cast_slice(&self.output[self.read_pos..]);
+        // only send whole samples
+        let n = buffer.len().min(output.len()) & !1;
+        buffer[..n].copy_from_slice(&output[..n]);
+        self.read_pos += n / 2;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::TryStreamExt;
+    use tokio::net::TcpListener;
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+    use crate::{
+        Pacing,
+        rtl_tcp::{
+            client::RtlTcpClient,
+            server::RtlTcpServer,
+        },
+        simulated::{
+            Scene,
+            Signal,
+            SimulatedSdr,
+        },
+        u8_to_f32,
+    };
+
+    const BAND: Band = Band {
+        center_frequency: 100_000_000,
+        sample_rate: 2_048_000,
+    };
+
+    fn simulated_sdr(pacing: Pacing) -> SimulatedSdr {
+        let scene = Scene::new()
+            .with_noise_floor(f32::NEG_INFINITY)
+            .with_signal(Signal::tone(100_300_000, -6.0))
+            .with_signal(Signal::tone(99_600_000, -6.0));
+        SimulatedSdr::with_pacing(scene, pacing)
+    }
+
+    async fn connection_handler(
+        policy: OutOfBandPolicy,
+    ) -> VirtualReceiverConnectionHandler<SimulatedSdr> {
+        let mut handler = VirtualReceiverHandler::new(simulated_sdr(Pacing::Unpaced), BAND)
+            .await
+            .unwrap()
+            .with_out_of_band_policy(policy);
+        handler
+            .accept_connection("127.0.0.1:1234".parse().unwrap())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    /// returns the power at 0 Hz
+    fn dc_power(samples: &[Iq]) -> f32 {
+        let n = samples.len() as f32;
+        let i = samples
+            .iter()
+            .map(|sample| u8_to_f32(sample.i))
+            .sum::<f32>()
+            / n;
+        let q = samples
+            .iter()
+            .map(|sample| u8_to_f32(sample.q))
+            .sum::<f32>()
+            / n;
+        i * i + q * q
+    }
+
+    /// waits until the connection handler receives a tone at its center
+    /// frequency
+    async fn wait_for_tone(handler: &mut VirtualReceiverConnectionHandler<SimulatedSdr>) {
+        let mut buffer = vec![0; 0x2000];
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let n = handler.read_samples(&mut buffer).await.unwrap();
+                let samples: &[Iq] = bytemuck::cast_slice(&buffer[..n]);
+                if samples.len() >= 0x100 && dc_power(samples) > 0.1 {
+                    break;
+                }
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn clients_get_their_own_channel() {
+        let sdr = simulated_sdr(Pacing::Unpaced);
+        let mut handler = VirtualReceiverHandler::new(sdr.clone(), BAND)
+            .await
+            .unwrap();
+        let address = "127.0.0.1:1234".parse().unwrap();
+        let mut first = handler.accept_connection(address).await.unwrap().unwrap();
+        let mut second = handler.accept_connection(address).await.unwrap().unwrap();
+
+        first
+            .handle_command(Command::SetSampleRate {
+                sample_rate: 256_000,
+            })
+            .await
+            .unwrap();
+        first
+            .handle_command(Command::SetCenterFrequency {
+                frequency: 100_300_000,
+            })
+            .await
+            .unwrap();
+        second
+            .handle_command(Command::SetSampleRate {
+                sample_rate: 128_000,
+            })
+            .await
+            .unwrap();
+        second
+            .handle_command(Command::SetCenterFrequency {
+                frequency: 99_600_000,
+            })
+            .await
+            .unwrap();
+
+        let config = first.config().await.unwrap().unwrap();
+        assert_eq!(config.center_frequency, Some(100_300_000));
+        assert_eq!(config.sample_rate, Some(256_000));
+        let config = second.config().await.unwrap().unwrap();
+        assert_eq!(config.center_frequency, Some(99_600_000));
+        assert_eq!(config.sample_rate, Some(128_000));
+
+        wait_for_tone(&mut first).await;
+        wait_for_tone(&mut second).await;
+
+        // the dongle wasn't retuned
+        assert_eq!(sdr.get_center_frequency(), BAND.center_frequency);
+        assert_eq!(sdr.get_sample_rate(), BAND.sample_rate);
+    }
+
+    #[tokio::test]
+    async fn it_serves_virtual_receivers() {
+        let handler = VirtualReceiverHandler::new(simulated_sdr(Pacing::RealTime), BAND)
+            .await
+            .unwrap();
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = tcp_listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let server = RtlTcpServer::new(handler, tcp_listener).with_shutdown(shutdown.clone());
+        let mut config_changes = server.config_changes();
+        tokio::spawn(async move {
+            server.serve().await.unwrap();
+        });
+
+        let client = RtlTcpClient::connect(address).await.unwrap();
+        client.set_sample_rate(256_000).await.unwrap();
+        client.set_center_frequency(100_300_000).await.unwrap();
+        let mut samples = client.samples().await.unwrap();
+
+        // samples of the whole band might still be on their way.
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let chunk = samples.try_next().await.unwrap().unwrap();
+                if dc_power(chunk.samples()) > 0.1 {
+                    break;
+                }
+            }
+        })
+        .await
+        .unwrap();
+
+        // retuning the virtual receiver isn't a change of the device
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), config_changes.recv())
+                .await
+                .is_err()
+        );
+
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn it_rejects_channels_outside_of_the_band() {
+        let mut handler = connection_handler(OutOfBandPolicy::Reject).await;
+
+        // the whole band is selected, so it can't be moved.
+        handler
+            .handle_command(Command::SetCenterFrequency {
+                frequency: 100_100_000,
+            })
+            .await
+            .unwrap();
+        assert_eq!(handler.center_frequency(), 100_000_000);
+
+        handler
+            .handle_command(Command::SetSampleRate {
+                sample_rate: 256_000,
+            })
+            .await
+            .unwrap();
+        handler
+            .handle_command(Command::SetCenterFrequency {
+                frequency: 100_700_000,
+            })
+            .await
+            .unwrap();
+        assert_eq!(handler.sample_rate(), 256_000);
+        assert_eq!(handler.center_frequency(), 100_700_000);
+
+        handler
+            .handle_command(Command::SetCenterFrequency {
+                frequency: 101_000_000,
+            })
+            .await
+            .unwrap();
+        assert_eq!(handler.center_frequency(), 100_700_000);
+
+        // 2048 kHz isn't a multiple of 250 kHz
+        handler
+            .handle_command(Command::SetSampleRate {
+                sample_rate: 250_000,
+            })
+            .await
+            .unwrap();
+        assert_eq!(handler.sample_rate(), 256_000);
+
+        // a wider channel doesn't fit at this frequency
+        handler
+            .handle_command(Command::SetSampleRate {
+                sample_rate: 1_024_000,
+            })
+            .await
+            .unwrap();
+        assert_eq!(handler.sample_rate(), 256_000);
+
+        for sample_rate in [0, 1_000] {
+            handler
+                .handle_command(Command::SetSampleRate { sample_rate })
+                .await
+                .unwrap();
+            assert_eq!(handler.sample_rate(), 256_000);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_clamps_channels_outside_of_the_band() {
+        let mut handler = connection_handler(OutOfBandPolicy::Clamp).await;
+
+        handler
+            .handle_command(Command::SetSampleRate {
+                sample_rate: 250_000,
+            })
+            .await
+            .unwrap();
+        assert_eq!(handler.sample_rate(), 256_000);
+
+        handler
+            .handle_command(Command::SetCenterFrequency {
+                frequency: 101_000_000,
+            })
+            .await
+            .unwrap();
+        assert_eq!(handler.center_frequency(), 100_896_000);
+
+        handler
+            .handle_command(Command::SetSampleRate {
+                sample_rate: 1_024_000,
+            })
+            .await
+            .unwrap();
+        assert_eq!(handler.sample_rate(), 1_024_000);
+        assert_eq!(handler.center_frequency(), 100_512_000);
+
+        handler
+            .handle_command(Command::SetSampleRate { sample_rate: 1 })
+            .await
+            .unwrap();
+        assert_eq!(handler.sample_rate(), BAND.sample_rate / MAX_DECIMATION);
+
+        handler
+            .handle_command(Command::SetSampleRate { sample_rate: 0 })
+            .await
+            .unwrap();
+        assert_eq!(handler.sample_rate(), BAND.sample_rate / MAX_DECIMATION);
+    }
+}
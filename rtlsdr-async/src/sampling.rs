@@ -1,80 +1,373 @@
 use std::{
     sync::Arc,
     thread,
+    time::Instant,
+};
+
+use tokio::sync::broadcast::{
+    self,
+    error::TryRecvError,
 };
 
 use crate::{
+    ConfigEvent,
     Error,
+    Gain,
+    SampleType,
     buffer_queue::{
         self,
         Buffer,
     },
-    handle::Handle,
+    handle::{
+        Handle,
+        LockedHandle,
+    },
+    supervisor::Supervisor,
 };
 
+/// How the reader thread reads from the device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReaderMode {
+    /// Read with `rtlsdr_read_sync`.
+    ///
+    /// The handle is locked while reading, so control commands have to wait
+    /// until a buffer is filled. The sample rate and sampling mode are checked
+    /// for every buffer.
+    #[default]
+    Sync,
+
+    /// Read with `rtlsdr_read_async`.
+    ///
+    /// librtlsdr keeps `buffer_count` USB transfers in flight, and the samples
+    /// are copied into the buffer queue as they arrive. The length of these
+    /// transfers is the [buffer size][crate::RtlSdrBuilder::with_buffer_size].
+    /// If `buffer_count` is 0, librtlsdr's default of 15 is used.
+    ///
+    /// Control commands don't have to wait for reads. Changing the sample rate
+    /// or sampling mode cancels the read, and it's restarted with the new
+    /// settings, so that every chunk reports the right ones. The transfers in
+    /// flight are dropped, so the next chunk is marked as a
+    /// [discontinuity][crate::Chunk::discontinuity].
+    ///
+    /// The reader never waits for receivers in this mode. While there are
+    /// none, or a receiver with [`Backpressure::Block`] lags behind, transfers
+    /// are dropped, and the next chunk is marked as a
+    /// [discontinuity][crate::Chunk::discontinuity].
+    ///
+    /// [`Backpressure::Block`]: crate::Backpressure::Block
+    Async { buffer_count: u32 },
+}
+
 pub(crate) fn spawn_reader_thread(
     handle: Arc<Handle>,
     buffer_size: usize,
     queue_size: usize,
+    mode: ReaderMode,
+    supervisor: Option<Supervisor>,
 ) -> buffer_queue::Subscriber {
     let (buffer_queue_sender, buffer_queue_subscriber) = buffer_queue::channel(queue_size);
 
     thread::spawn({
         let handle = handle.clone();
         move || {
-            reader_thread(buffer_queue_sender, handle, buffer_size);
+            let _guard = tracing::debug_span!("reader thread", index = handle.index()).entered();
+            tracing::debug!(?mode, "reader thread spawned");
+
+            let mut reader = Reader {
+                sender: buffer_queue_sender,
+                config_generation: handle.config_generation(),
+                handle,
+                buffer_size,
+                sample_index: 0,
+                discontinuity: false,
+            };
+            reader.run(mode, supervisor);
         }
     });
 
     buffer_queue_subscriber
 }
 
-fn reader_thread(
-    mut buffer_queue_sender: buffer_queue::Sender,
+/// why a reader stopped reading
+enum Stopped {
+    /// all receivers and subscribers dropped
+    Closed,
+
+    /// the device stopped delivering samples. most likely it was unplugged.
+    DeviceLost,
+}
+
+struct Reader {
+    sender: buffer_queue::Sender,
     handle: Arc<Handle>,
     buffer_size: usize,
-) {
-    let _guard = tracing::debug_span!("reader thread", index = handle.index).entered();
 
-    // when we are reading to the buffer we don't hold the queue lock, so once we're
-    // done we need to acquire the lock to add the buffer to the queue.
-    // but we also need the queue lock to get a new free buffer. we can combine both
-    // steps into one lock-holding code section at the start of the loop. All we
-    // need to do is remember the buffer we want to push.
-    let mut push_buffer = None;
+    /// running sample index since the reader thread was started
+    sample_index: u64,
 
-    tracing::debug!("reader thread spawned");
+    /// set when samples were dropped, e.g. after the device was reopened, so
+    /// the next buffer is marked.
+    discontinuity: bool,
 
-    loop {
-        let Some(mut buffer) =
-            buffer_queue_sender.swap_buffers(push_buffer.take(), buffer_size, true)
-        else {
-            // all receivers and subscribers dropped
-            tracing::debug!("all readers dropped. exiting");
-            break;
-        };
+    /// the [config generation][Handle::config_generation] of the last
+    /// buffer, so we can mark buffers after a change.
+    config_generation: u64,
+}
 
-        match read_to_buffer(&handle, &mut buffer, buffer_size) {
-            Ok(true) => {
-                push_buffer = Some(buffer);
+impl Reader {
+    fn run(&mut self, mode: ReaderMode, supervisor: Option<Supervisor>) {
+        loop {
+            let stopped = match mode {
+                ReaderMode::Sync => self.read_sync(),
+                ReaderMode::Async { buffer_count } => self.read_async(buffer_count),
+            };
+
+            match stopped {
+                Stopped::Closed => {
+                    tracing::debug!("all readers dropped. exiting");
+                    break;
+                }
+                Stopped::DeviceLost => {}
             }
-            Ok(false) => {
-                tracing::debug!("rtlsdr_read_sync returned 0. exiting");
+
+            // the device is most likely gone. if we have a supervisor, we wait for it to
+            // come back.
+            let Some(supervisor) = &supervisor
+            else {
                 break;
-            }
-            Err(error) => {
-                tracing::error!(?error, "rtlsdr reader thread error");
+            };
+            if !supervisor.reconnect(self.handle.as_ref(), &self.sender) {
+                tracing::debug!("not reconnected. exiting");
                 break;
             }
+            tracing::Span::current().record("index", self.handle.index());
+            self.discontinuity = true;
+        }
+    }
+
+    fn read_sync(&mut self) -> Stopped {
+        // when we are reading to the buffer we don't hold the queue lock, so once
+        // we're done we need to acquire the lock to add the buffer to the queue.
+        // but we also need the queue lock to get a new free buffer. we can combine
+        // both steps into one lock-holding code section at the start of the loop.
+        // All we need to do is remember the buffer we want to push.
+        let mut push_buffer = None;
+
+        loop {
+            let Some(mut buffer) =
+                self.sender
+                    .swap_buffers(push_buffer.take(), self.buffer_size, true)
+            else {
+                return Stopped::Closed;
+            };
+
+            // if this fails, the buffer we got is just dropped.
+            match read_to_buffer(
+                &self.handle,
+                &mut buffer,
+                self.buffer_size,
+                self.sample_index,
+                &mut self.config_generation,
+            ) {
+                Ok(true) => {
+                    self.sample_index += buffer.num_samples() as u64;
+                    buffer.discontinuity = std::mem::take(&mut self.discontinuity);
+                    push_buffer = Some(buffer);
+                }
+                Ok(false) => {
+                    tracing::debug!("rtlsdr_read_sync returned 0");
+                    return Stopped::DeviceLost;
+                }
+                Err(error) => {
+                    tracing::error!(?error, "rtlsdr reader thread error");
+                    return Stopped::DeviceLost;
+                }
+            }
+        }
+    }
+
+    fn read_async(&mut self, buffer_count: u32) -> Stopped {
+        loop {
+            let mut read = match self.start_async_read() {
+                Ok(read) => read,
+                Err(error) => {
+                    tracing::error!(?error, "rtlsdr reader thread error");
+                    return Stopped::DeviceLost;
+                }
+            };
+            let mut transfer = Transfer::Continue;
+
+            let handle = self.handle.clone();
+            let result = handle.read_async(buffer_count, self.buffer_size, |data| {
+                if transfer != Transfer::Continue {
+                    // the read is cancelled, but librtlsdr still delivers the transfers that
+                    // already completed. we drop them, and keep the outcome.
+                    return false;
+                }
+                transfer = self.on_transfer(&mut read, data);
+                transfer == Transfer::Continue
+            });
+
+            match transfer {
+                Transfer::Closed => return Stopped::Closed,
+                Transfer::Restart => continue,
+                Transfer::Continue => {}
+            }
+            if self.handle.stream_generation() != read.stream_generation {
+                continue;
+            }
+
+            // we didn't cancel the read, so something went wrong
+            match result {
+                Ok(()) => tracing::debug!("rtlsdr_read_async returned"),
+                Err(error) => tracing::error!(?error, "rtlsdr reader thread error"),
+            }
+            return Stopped::DeviceLost;
+        }
+    }
+
+    fn start_async_read(&self) -> Result<AsyncRead, Error> {
+        // we can't lock the handle in the callback, because the control thread
+        // might wait for the USB event handling we'd block. so we get the settings
+        // now, and follow changes to them with the config events. the sample rate
+        // and sampling mode can only change when the read is cancelled.
+        let config_events = self.handle.subscribe_config_changes();
+        let (stream_generation, stream_info) = snapshot(
+            || self.handle.stream_generation(),
+            || StreamInfo::get(&mut self.handle.lock()),
+        );
+
+        Ok(AsyncRead {
+            stream_generation,
+            stream_info: stream_info?,
+            config_events,
+            free_buffer: None,
+        })
+    }
+
+    /// the body of the `rtlsdr_read_async` callback. this copies `data` into
+    /// the buffer queue.
+    ///
+    /// this must never block: the callback runs while librtlsdr holds the
+    /// libusb event lock, so control transfers from other threads would wait
+    /// for us.
+    fn on_transfer(&mut self, read: &mut AsyncRead, data: &[u8]) -> Transfer {
+        if self.handle.stream_generation() != read.stream_generation {
+            // the samples might already be from the new settings, so we drop them.
+            tracing::debug!("stream settings changed. restarting read");
+            self.discontinuity = true;
+            return Transfer::Restart;
+        }
+
+        loop {
+            match read.config_events.try_recv() {
+                Ok(event) => read.stream_info.apply(&event),
+                Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+                Err(TryRecvError::Lagged(_)) => {
+                    // we don't know the settings anymore, so we restart.
+                    tracing::debug!("config events lagged. restarting read");
+                    self.discontinuity = true;
+                    return Transfer::Restart;
+                }
+            }
         }
+
+        if self.sender.is_closed() {
+            return Transfer::Closed;
+        }
+        if self.sender.would_block() {
+            // nobody is ready for the samples, so we drop them and mark the gap.
+            tracing::trace!("no receiver ready. dropping transfer");
+            self.discontinuity = true;
+            return Transfer::Continue;
+        }
+
+        let Some(mut buffer) = read
+            .free_buffer
+            .take()
+            .or_else(|| self.sender.swap_buffers(None, self.buffer_size, false))
+        else {
+            return Transfer::Closed;
+        };
+
+        buffer.reclaim_or_allocate(self.buffer_size)[..data.len()].copy_from_slice(data);
+        buffer.start = 0;
+        buffer.end = data.len();
+        read.stream_info.write_to(&mut buffer);
+        buffer.timestamp = Instant::now();
+        buffer.sample_index = self.sample_index;
+        buffer.discontinuity = std::mem::take(&mut self.discontinuity);
+        let config_generation = self.handle.config_generation();
+        buffer.config_changed =
+            std::mem::replace(&mut self.config_generation, config_generation) != config_generation;
+        self.sample_index += buffer.num_samples() as u64;
+
+        read.free_buffer = self
+            .sender
+            .swap_buffers(Some(buffer), self.buffer_size, false);
+        if read.free_buffer.is_none() {
+            return Transfer::Closed;
+        }
+
+        Transfer::Continue
     }
 }
 
-fn read_to_buffer(handle: &Handle, buffer: &mut Buffer, buffer_size: usize) -> Result<bool, Error> {
-    let mut handle = handle.lock();
+/// the state of an async read, from its start until it's cancelled.
+struct AsyncRead {
+    /// the [stream generation][Handle::stream_generation] the read was
+    /// started with.
+    stream_generation: u64,
+    stream_info: StreamInfo,
+    config_events: broadcast::Receiver<ConfigEvent>,
+
+    /// the buffer we fill next
+    free_buffer: Option<Buffer>,
+}
+
+/// what an async read does after a transfer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Transfer {
+    /// keep reading.
+    Continue,
+
+    /// cancel the read and start a new one, because the settings changed or
+    /// we lost track of them.
+    Restart,
+
+    /// cancel the read and stop, because all receivers and subscribers
+    /// dropped.
+    Closed,
+}
+
+/// reads the stream settings with `get`, together with the
+/// [stream generation][Handle::stream_generation] they belong to.
+///
+/// the generation must be loaded first: if the settings change while we read
+/// them, the generation is already outdated and the read is restarted right
+/// away. the other way around, old settings would be tagged with the new
+/// generation, and every buffer would report them until the next restart.
+fn snapshot<T>(stream_generation: impl FnOnce() -> u64, get: impl FnOnce() -> T) -> (u64, T) {
+    let stream_generation = stream_generation();
+    (stream_generation, get())
+}
+
+fn read_to_buffer(
+    handle: &Handle,
+    buffer: &mut Buffer,
+    buffer_size: usize,
+    sample_index: u64,
+    config_generation: &mut u64,
+) -> Result<bool, Error> {
+    let mut locked = handle.lock();
+
+    StreamInfo::get(&mut locked)?.write_to(buffer);
 
-    buffer.sample_rate = handle.get_sample_rate()?;
-    buffer.sample_type = handle.get_direct_sampling()?.into();
+    // settings are only changed while holding the lock, so this tells us exactly
+    // whether this buffer is the first one after a change.
+    let current_generation = handle.config_generation();
+    buffer.config_changed =
+        std::mem::replace(config_generation, current_generation) != current_generation;
 
     // this will try to reclaim the buffer. if it can't, it'll create a new one.
     let buffer_mut = buffer.reclaim_or_allocate(buffer_size);
@@ -83,7 +376,8 @@ fn read_to_buffer(handle: &Handle, buffer: &mut Buffer, buffer_size: usize) -> R
     // buffer, but testing shows that it usually fills the buffer.
     // not sure how it will behave with larger buffer sizes, but you should then
     // probably choose a better buffer size.
-    let n_read = handle.read_sync(buffer_mut)?;
+    let n_read = locked.read_sync(buffer_mut)?;
+    let timestamp = Instant::now();
 
     if n_read > 0 {
         assert!(
@@ -92,6 +386,8 @@ fn read_to_buffer(handle: &Handle, buffer: &mut Buffer, buffer_size: usize) -> R
         );
         buffer.start = 0;
         buffer.end = n_read;
+        buffer.timestamp = timestamp;
+        buffer.sample_index = sample_index;
         Ok(true)
     }
     else {
@@ -99,3 +395,262 @@ fn read_to_buffer(handle: &Handle, buffer: &mut Buffer, buffer_size: usize) -> R
         Ok(false)
     }
 }
+
+/// the settings that are recorded in every buffer.
+#[derive(Clone, Copy, Debug)]
+struct StreamInfo {
+    sample_rate: u32,
+    sample_type: SampleType,
+    center_frequency: Option<u32>,
+    frequency_correction: i32,
+    tuner_gain: Option<Gain>,
+}
+
+impl StreamInfo {
+    fn get(handle: &mut LockedHandle) -> Result<Self, Error> {
+        Ok(Self {
+            sample_rate: handle.get_sample_rate()?,
+            sample_type: handle.get_direct_sampling()?.into(),
+            // this fails if the device wasn't tuned yet
+            center_frequency: handle.get_center_frequency().ok(),
+            frequency_correction: handle.get_frequency_correction()?,
+            tuner_gain: handle.tuner_gain(),
+        })
+    }
+
+    /// follows changes that don't restart an async read.
+    fn apply(&mut self, event: &ConfigEvent) {
+        match *event {
+            ConfigEvent::CenterFrequency { frequency } => self.center_frequency = Some(frequency),
+            ConfigEvent::FrequencyCorrection { ppm } => self.frequency_correction = ppm,
+            ConfigEvent::TunerGain { gain } => self.tuner_gain = Some(gain),
+            _ => {}
+        }
+    }
+
+    fn write_to(&self, buffer: &mut Buffer) {
+        buffer.sample_rate = self.sample_rate;
+        buffer.sample_type = self.sample_type;
+        // in direct sampling mode the tuner is bypassed
+        buffer.center_frequency = self
+            .center_frequency
+            .filter(|_| self.sample_type == SampleType::Iq);
+        buffer.frequency_correction = Some(self.frequency_correction);
+        buffer.tuner_gain = self.tuner_gain;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+
+    use super::*;
+    use crate::{
+        buffer_queue::Backpressure,
+        config::CONFIG_EVENT_QUEUE_SIZE,
+    };
+
+    const BUFFER_SIZE: usize = 16;
+
+    /// an async read that is started at 1 MHz.
+    fn async_read(handle: &Handle) -> AsyncRead {
+        AsyncRead {
+            stream_generation: handle.stream_generation(),
+            stream_info: StreamInfo {
+                sample_rate: 1_024_000,
+                sample_type: SampleType::Iq,
+                center_frequency: Some(1_000_000),
+                frequency_correction: 0,
+                tuner_gain: None,
+            },
+            config_events: handle.subscribe_config_changes(),
+            free_buffer: None,
+        }
+    }
+
+    /// a reader for a device-less handle, and an async read that was started
+    /// at 1 MHz.
+    fn async_reader() -> (Reader, AsyncRead, buffer_queue::Subscriber) {
+        let handle = Arc::new(Handle::closed());
+        let (sender, subscriber) = buffer_queue::channel(4);
+        let read = async_read(&handle);
+        let reader = Reader {
+            sender,
+            config_generation: handle.config_generation(),
+            handle,
+            buffer_size: BUFFER_SIZE,
+            sample_index: 0,
+            discontinuity: false,
+        };
+        (reader, read, subscriber)
+    }
+
+    #[tokio::test]
+    async fn async_reads_follow_config_events() {
+        let (mut reader, mut read, subscriber) = async_reader();
+        let mut receiver = subscriber.receiver();
+
+        assert_eq!(
+            reader.on_transfer(&mut read, &[0; BUFFER_SIZE]),
+            Transfer::Continue
+        );
+        reader.handle.notify(ConfigEvent::CenterFrequency {
+            frequency: 2_000_000,
+        });
+        assert_eq!(
+            reader.on_transfer(&mut read, &[0; BUFFER_SIZE]),
+            Transfer::Continue
+        );
+
+        let first = receiver.next().await.unwrap().unwrap();
+        assert_eq!(first.center_frequency, Some(1_000_000));
+        assert_eq!(first.sample_index, 0);
+        assert!(!first.config_changed);
+
+        let second = receiver.next().await.unwrap().unwrap();
+        assert_eq!(second.center_frequency, Some(2_000_000));
+        assert_eq!(second.sample_index, BUFFER_SIZE as u64 / 2);
+        assert!(second.config_changed);
+    }
+
+    #[test]
+    fn async_reads_restart_when_the_stream_changes() {
+        let (mut reader, mut read, subscriber) = async_reader();
+        let _receiver = subscriber.receiver();
+
+        reader.handle.change_stream();
+        assert_eq!(
+            reader.on_transfer(&mut read, &[0; BUFFER_SIZE]),
+            Transfer::Restart
+        );
+        // the samples are dropped
+        assert_eq!(reader.sample_index, 0);
+    }
+
+    #[test]
+    fn async_reads_restart_after_lagging_behind_config_events() {
+        let (mut reader, mut read, subscriber) = async_reader();
+        let _receiver = subscriber.receiver();
+
+        for ppm in 0..=CONFIG_EVENT_QUEUE_SIZE as i32 {
+            reader
+                .handle
+                .notify(ConfigEvent::FrequencyCorrection { ppm });
+        }
+        assert_eq!(
+            reader.on_transfer(&mut read, &[0; BUFFER_SIZE]),
+            Transfer::Restart
+        );
+        assert_eq!(reader.sample_index, 0);
+    }
+
+    #[test]
+    fn async_reads_stop_when_all_receivers_are_dropped() {
+        let (mut reader, mut read, subscriber) = async_reader();
+        let receiver = subscriber.receiver();
+
+        assert_eq!(
+            reader.on_transfer(&mut read, &[0; BUFFER_SIZE]),
+            Transfer::Continue
+        );
+
+        drop(subscriber);
+        drop(receiver);
+        assert_eq!(
+            reader.on_transfer(&mut read, &[0; BUFFER_SIZE]),
+            Transfer::Closed
+        );
+    }
+
+    #[tokio::test]
+    async fn async_reads_drop_transfers_without_receivers() {
+        let (mut reader, mut read, subscriber) = async_reader();
+
+        // this must not wait for a receiver, or the setter would never finish on a
+        // real device.
+        assert_eq!(
+            reader.on_transfer(&mut read, &[0; BUFFER_SIZE]),
+            Transfer::Continue
+        );
+        reader.handle.notify(ConfigEvent::CenterFrequency {
+            frequency: 2_000_000,
+        });
+        assert_eq!(
+            reader.on_transfer(&mut read, &[0; BUFFER_SIZE]),
+            Transfer::Continue
+        );
+
+        let mut receiver = subscriber.receiver();
+        assert_eq!(
+            reader.on_transfer(&mut read, &[0; BUFFER_SIZE]),
+            Transfer::Continue
+        );
+
+        let chunk = receiver.next().await.unwrap().unwrap();
+        assert_eq!(chunk.center_frequency, Some(2_000_000));
+        assert!(chunk.discontinuity);
+        assert!(chunk.config_changed);
+    }
+
+    #[tokio::test]
+    async fn async_reads_dont_wait_for_blocking_receivers() {
+        let (mut reader, mut read, subscriber) = async_reader();
+        subscriber.set_default_backpressure(Backpressure::Block);
+        let mut receiver = subscriber.receiver();
+
+        for _ in 0..8 {
+            assert_eq!(
+                reader.on_transfer(&mut read, &[0; BUFFER_SIZE]),
+                Transfer::Continue
+            );
+        }
+
+        // the queue filled up, and the rest was dropped. the reader holds on to
+        // one buffer.
+        let mut discontinuities = vec![];
+        for _ in 0..3 {
+            let chunk = receiver.next().await.unwrap().unwrap();
+            discontinuities.push(chunk.discontinuity);
+        }
+        assert_eq!(discontinuities, [false, false, false]);
+
+        assert_eq!(
+            reader.on_transfer(&mut read, &[0; BUFFER_SIZE]),
+            Transfer::Continue
+        );
+        let chunk = receiver.next().await.unwrap().unwrap();
+        assert!(chunk.discontinuity);
+    }
+
+    #[tokio::test]
+    async fn async_reads_mark_samples_dropped_by_a_restart() {
+        let (mut reader, mut read, subscriber) = async_reader();
+        let mut receiver = subscriber.receiver();
+
+        assert_eq!(
+            reader.on_transfer(&mut read, &[0; BUFFER_SIZE]),
+            Transfer::Continue
+        );
+
+        // e.g. the sample rate is changed while we're reading
+        reader.handle.change_stream();
+        assert_eq!(
+            reader.on_transfer(&mut read, &[0; BUFFER_SIZE]),
+            Transfer::Restart
+        );
+
+        let mut read = async_read(&reader.handle);
+        assert_eq!(
+            reader.on_transfer(&mut read, &[0; BUFFER_SIZE]),
+            Transfer::Continue
+        );
+
+        let first = receiver.next().await.unwrap().unwrap();
+        assert!(!first.discontinuity);
+
+        // the dropped transfer isn't counted, but the gap is marked
+        let second = receiver.next().await.unwrap().unwrap();
+        assert_eq!(second.sample_index, BUFFER_SIZE as u64 / 2);
+        assert!(second.discontinuity);
+    }
+}
@@ -0,0 +1,278 @@
+//! Reading and writing [SigMF][1] recordings.
+//!
+//! A SigMF recording consists of a `.sigmf-data` file with the raw samples,
+//! and a `.sigmf-meta` file with JSON metadata. The metadata types in this
+//! module only cover the parts of the [specification][2] that we need.
+//!
+//! Since the core namespace only has a global sample rate, we record the sample
+//! rate of every capture segment in the `rtlsdr:sample_rate` field. The global
+//! `core:sample_rate` is only written if it's the same for all segments. The
+//! tuner type is recorded in `rtlsdr:tuner_type`.
+//!
+//! [1]: https://sigmf.org
+//! [2]: https://github.com/sigmf/SigMF/blob/main/sigmf-spec.md
+
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use bytemuck::Pod;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::Iq;
+
+pub mod reader;
+pub mod writer;
+
+/// SigMF specification version we write.
+pub const SIGMF_VERSION: &str = "1.0.0";
+
+/// Name of our extension namespace.
+pub const EXTENSION_NAME: &str = "rtlsdr";
+
+/// Version of our extension namespace.
+pub const EXTENSION_VERSION: &str = "0.1.0";
+
+/// Errors returned when reading or writing SigMF recordings
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[error("invalid metadata")]
+    Json(#[from] serde_json::Error),
+    #[error("unsupported datatype: {datatype}")]
+    UnsupportedDatatype { datatype: String },
+    #[error("recording doesn't specify a sample rate")]
+    MissingSampleRate,
+    #[error("sample offset {offset} is beyond the end of the recording ({num_samples} samples)")]
+    InvalidOffset { offset: u64, num_samples: u64 },
+    #[error("operation not supported by SigMF reader")]
+    Unsupported,
+    #[error("error while reading samples")]
+    Samples(#[from] crate::Error),
+}
+
+/// Sample types that can be stored in a SigMF recording.
+pub trait Datatype: Pod {
+    /// The SigMF datatype string, e.g. `cu8`.
+    const DATATYPE: &'static str;
+}
+
+impl Datatype for Iq {
+    const DATATYPE: &'static str = "cu8";
+}
+
+impl Datatype for u8 {
+    const DATATYPE: &'static str = "ru8";
+}
+
+/// Returns the paths of the data and metadata files for a recording.
+///
+/// `path` is the path of the recording without the `.sigmf-data` or
+/// `.sigmf-meta` extension. If it has one of those extensions, it will be
+/// replaced.
+pub fn recording_paths(path: impl AsRef<Path>) -> (PathBuf, PathBuf) {
+    let path = path.as_ref();
+    let base = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("sigmf-data" | "sigmf-meta") => path.with_extension(""),
+        _ => path.to_owned(),
+    };
+
+    let with_extension = |extension: &str| {
+        let mut path = base.as_os_str().to_owned();
+        path.push(".");
+        path.push(extension);
+        PathBuf::from(path)
+    };
+
+    (with_extension("sigmf-data"), with_extension("sigmf-meta"))
+}
+
+/// Contents of a `.sigmf-meta` file.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub global: Global,
+
+    #[serde(default)]
+    pub captures: Vec<Capture>,
+
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+}
+
+/// Global metadata of a recording.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Global {
+    /// Format of the samples, e.g. `cu8`.
+    #[serde(rename = "core:datatype")]
+    pub datatype: String,
+
+    /// Sample rate in Hz.
+    #[serde(
+        rename = "core:sample_rate",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub sample_rate: Option<f64>,
+
+    #[serde(rename = "core:version")]
+    pub version: String,
+
+    /// Description of the hardware used to make the recording.
+    #[serde(rename = "core:hw", default, skip_serializing_if = "Option::is_none")]
+    pub hw: Option<String>,
+
+    /// Name of the software used to make the recording.
+    #[serde(
+        rename = "core:recorder",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub recorder: Option<String>,
+
+    #[serde(
+        rename = "core:description",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub description: Option<String>,
+
+    #[serde(
+        rename = "core:extensions",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub extensions: Vec<Extension>,
+
+    /// Tuner type as reported by librtlsdr, e.g. `R820T`.
+    #[serde(
+        rename = "rtlsdr:tuner_type",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub tuner_type: Option<String>,
+}
+
+/// Extension namespace used in a recording.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Extension {
+    pub name: String,
+    pub version: String,
+    pub optional: bool,
+}
+
+/// A capture segment.
+///
+/// A new capture segment starts whenever the receiver was retuned, its
+/// sample rate changed, or samples are missing.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Capture {
+    /// Index of the first sample of this segment.
+    #[serde(rename = "core:sample_start")]
+    pub sample_start: u64,
+
+    /// Index of the first sample of this segment in the stream it was
+    /// recorded from, if it differs from `sample_start`, e.g. because chunks
+    /// were dropped.
+    #[serde(
+        rename = "core:global_index",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub global_index: Option<u64>,
+
+    /// Center frequency in Hz.
+    #[serde(
+        rename = "core:frequency",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub frequency: Option<f64>,
+
+    /// Sample rate of this segment in Hz.
+    #[serde(
+        rename = "rtlsdr:sample_rate",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub sample_rate: Option<f64>,
+}
+
+/// An annotation of a range of samples.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    /// Index of the first annotated sample.
+    #[serde(rename = "core:sample_start")]
+    pub sample_start: u64,
+
+    /// Number of annotated samples.
+    #[serde(
+        rename = "core:sample_count",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub sample_count: Option<u64>,
+
+    /// Lower edge of the annotated signal in Hz.
+    #[serde(
+        rename = "core:freq_lower_edge",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub freq_lower_edge: Option<f64>,
+
+    /// Upper edge of the annotated signal in Hz.
+    #[serde(
+        rename = "core:freq_upper_edge",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub freq_upper_edge: Option<f64>,
+
+    /// Short label, e.g. for display in a waterfall.
+    #[serde(
+        rename = "core:label",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub label: Option<String>,
+
+    #[serde(
+        rename = "core:comment",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub comment: Option<String>,
+}
+
+impl Annotation {
+    /// Annotate `sample_count` samples starting at `sample_start`.
+    pub fn new(sample_start: u64, sample_count: u64) -> Self {
+        Self {
+            sample_start,
+            sample_count: Some(sample_count),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    /// Set the frequency range of the annotated signal in Hz.
+    pub fn with_frequency_range(mut self, lower_edge: f64, upper_edge: f64) -> Self {
+        self.freq_lower_edge = Some(lower_edge);
+        self.freq_upper_edge = Some(upper_edge);
+        self
+    }
+}
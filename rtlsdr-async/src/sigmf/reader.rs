@@ -0,0 +1,399 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::Arc,
+};
+
+use crate::{
+    Backend,
+    Bandwidth,
+    DeviceConfig,
+    DirectSamplingMode,
+    DongleInfo,
+    Gain,
+    Iq,
+    Pacing,
+    Samples,
+    TunerType,
+    file::{
+        Playback,
+        SampleFormat,
+        Segment,
+    },
+    sigmf::{
+        Error,
+        Metadata,
+        recording_paths,
+    },
+};
+
+/// Options for opening a SigMF recording with [`SigMfReader`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReaderOptions {
+    /// Whether to replay in real-time, or as fast as possible.
+    pub pacing: Pacing,
+
+    /// Convert `ci8`, `ci16_le` and `cf32_le` recordings to `cu8`.
+    ///
+    /// If this is `false`, only `cu8` recordings can be opened.
+    pub convert: bool,
+}
+
+/// Replays a SigMF recording.
+///
+/// This works like the [`FileBackend`][crate::file::FileBackend], but reads
+/// the sample rate and center frequency from the capture segments in the
+/// metadata. Each [`Chunk`][crate::Chunk] reports the sample rate and center
+/// frequency of the capture segment it came from.
+///
+/// A recording can't be retuned, so setting the center frequency or sample
+/// rate only succeeds if it matches the current capture segment.
+///
+/// The current capture segment is the one the playback thread is reading
+/// from. Since it reads ahead of the streams, this can already be the next
+/// segment near a segment boundary. This applies to
+/// [`config`][Backend::config] as well. Use the
+/// [center frequency][crate::Chunk::center_frequency] and
+/// [sample rate][crate::Chunk::sample_rate] of the chunks to know which
+/// segment samples came from.
+#[derive(Clone, Debug)]
+pub struct SigMfReader {
+    metadata: Arc<Metadata>,
+    segments: Arc<[Segment]>,
+    playback: Playback,
+}
+
+impl SigMfReader {
+    /// Open the recording at `path` and replay it in real-time.
+    ///
+    /// See [`recording_paths`] for how `path` is mapped to the data and
+    /// metadata files.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::open_with_options(path, Default::default())
+    }
+
+    pub fn open_with_options(
+        path: impl AsRef<Path>,
+        options: ReaderOptions,
+    ) -> Result<Self, Error> {
+        let (data_path, meta_path) = recording_paths(path);
+
+        let metadata: Metadata = serde_json::from_reader(BufReader::new(File::open(meta_path)?))?;
+
+        let format = match (metadata.global.datatype.as_str(), options.convert) {
+            ("cu8", _) => SampleFormat::Cu8,
+            ("ci8", true) => SampleFormat::Ci8,
+            ("ci16_le", true) => SampleFormat::Ci16Le,
+            ("cf32_le", true) => SampleFormat::Cf32Le,
+            (datatype, _) => {
+                return Err(Error::UnsupportedDatatype {
+                    datatype: datatype.to_owned(),
+                });
+            }
+        };
+
+        let segments = segments(&metadata)?;
+        let playback = Playback::open(data_path, format, segments.clone(), options.pacing)?;
+
+        Ok(Self {
+            metadata: Arc::new(metadata),
+            segments: segments.into(),
+            playback,
+        })
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Number of IQ samples in the recording.
+    pub fn num_samples(&self) -> u64 {
+        self.playback.num_samples()
+    }
+
+    /// Restart from the beginning when the end of the recording is reached.
+    pub fn set_looping(&self, looping: bool) {
+        self.playback.set_looping(looping);
+    }
+
+    /// Continue playback at the given sample offset.
+    ///
+    /// Samples that were already read into the buffer queue will still be
+    /// delivered.
+    pub fn seek(&self, offset: u64) -> Result<(), Error> {
+        if offset > self.num_samples() {
+            return Err(Error::InvalidOffset {
+                offset,
+                num_samples: self.num_samples(),
+            });
+        }
+        self.playback.seek(offset);
+        Ok(())
+    }
+
+    pub async fn samples(&self) -> Result<Samples<Iq>, Error> {
+        Ok(self.playback.samples())
+    }
+
+    /// the capture segment that the playback thread is currently reading. this
+    /// is ahead of what the streams have received.
+    fn current_segment(&self) -> &Segment {
+        let position = self.playback.position();
+        let index = self
+            .segments
+            .partition_point(|segment| segment.sample_start <= position)
+            .saturating_sub(1);
+        &self.segments[index]
+    }
+}
+
+impl Backend for SigMfReader {
+    type Error = Error;
+
+    fn dongle_info(&self) -> DongleInfo {
+        DongleInfo {
+            tuner_type: self
+                .metadata
+                .global
+                .tuner_type
+                .as_deref()
+                .and_then(TunerType::from_name)
+                .unwrap_or(TunerType::UNKNOWN),
+            tuner_gain_count: 0,
+        }
+    }
+
+    async fn config(&self) -> Result<DeviceConfig, Error> {
+        let segment = self.current_segment();
+        Ok(DeviceConfig {
+            center_frequency: segment.center_frequency,
+            sample_rate: Some(segment.sample_rate),
+            ..Default::default()
+        })
+    }
+
+    async fn set_center_frequency(&self, frequency: u32) -> Result<(), Error> {
+        if self.current_segment().center_frequency == Some(frequency) {
+            Ok(())
+        }
+        else {
+            Err(Error::Unsupported)
+        }
+    }
+
+    async fn set_sample_rate(&self, sample_rate: u32) -> Result<(), Error> {
+        if self.current_segment().sample_rate == sample_rate {
+            Ok(())
+        }
+        else {
+            Err(Error::Unsupported)
+        }
+    }
+
+    async fn set_tuner_gain(&self, _gain: Gain) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_tuner_bandwidth(&self, _bandwidth: Bandwidth) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_agc_mode(&self, _enable: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_frequency_correction(&self, _ppm: i32) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_tuner_if_gain(&self, _stage: i16, _gain: i16) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_offset_tuning(&self, _enable: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_rtl_xtal(&self, _frequency: u32) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_tuner_xtal(&self, _frequency: u32) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_bias_tee(&self, _enable: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_bias_tee_gpio(&self, _pin: u8, _enable: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_direct_sampling(&self, mode: Option<DirectSamplingMode>) -> Result<(), Error> {
+        // recordings only have IQ samples
+        if mode.is_some() {
+            Err(Error::Unsupported)
+        }
+        else {
+            Ok(())
+        }
+    }
+
+    async fn samples(&self) -> Result<Samples<Iq>, Error> {
+        SigMfReader::samples(self).await
+    }
+
+    async fn direct_samples(&self, _mode: DirectSamplingMode) -> Result<Samples<u8>, Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+/// builds the playback segments from the capture segments in the metadata.
+fn segments(metadata: &Metadata) -> Result<Vec<Segment>, Error> {
+    let to_u32 =
+        |value: f64| (value >= 0.0 && value <= f64::from(u32::MAX)).then(|| value.round() as u32);
+
+    let global_sample_rate = metadata.global.sample_rate.and_then(to_u32);
+
+    let mut segments = metadata
+        .captures
+        .iter()
+        .map(|capture| {
+            Ok(Segment {
+                sample_start: capture.sample_start,
+                sample_rate: capture
+                    .sample_rate
+                    .and_then(to_u32)
+                    .or(global_sample_rate)
+                    .ok_or(Error::MissingSampleRate)?,
+                center_frequency: capture.frequency.and_then(to_u32),
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    segments.sort_by_key(|segment| segment.sample_start);
+
+    // make sure there's a segment for the start of the recording
+    if segments.first().is_none_or(|first| first.sample_start > 0) {
+        segments.insert(
+            0,
+            Segment {
+                sample_start: 0,
+                sample_rate: global_sample_rate.ok_or(Error::MissingSampleRate)?,
+                center_frequency: None,
+            },
+        );
+    }
+
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::TryStreamExt;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::sigmf::{
+        Capture,
+        Global,
+        SIGMF_VERSION,
+    };
+
+    /// the recording is deleted when the returned directory is dropped.
+    fn write_recording(datatype: &str, data: &[u8], captures: Vec<Capture>) -> (TempDir, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording").to_str().unwrap().to_owned();
+        let (data_path, meta_path) = recording_paths(&path);
+        let metadata = Metadata {
+            global: Global {
+                datatype: datatype.to_owned(),
+                sample_rate: Some(1_024_000.0),
+                version: SIGMF_VERSION.to_owned(),
+                tuner_type: Some("R820T".to_owned()),
+                ..Default::default()
+            },
+            captures,
+            annotations: vec![],
+        };
+        std::fs::write(data_path, data).unwrap();
+        std::fs::write(meta_path, serde_json::to_vec(&metadata).unwrap()).unwrap();
+        (dir, path)
+    }
+
+    fn unpaced(convert: bool) -> ReaderOptions {
+        ReaderOptions {
+            pacing: Pacing::Unpaced,
+            convert,
+        }
+    }
+
+    #[tokio::test]
+    async fn chunks_report_their_capture_segment() {
+        let (_dir, path) = write_recording(
+            "cu8",
+            &[128; 2 * 1000],
+            vec![
+                Capture {
+                    sample_start: 0,
+                    frequency: Some(100e6),
+                    ..Default::default()
+                },
+                Capture {
+                    sample_start: 600,
+                    frequency: Some(101e6),
+                    sample_rate: Some(2_048_000.0),
+                    ..Default::default()
+                },
+            ],
+        );
+
+        let reader = SigMfReader::open_with_options(&path, unpaced(false)).unwrap();
+        assert_eq!(reader.dongle_info().tuner_type, TunerType::R820T);
+
+        let mut samples = reader.samples().await.unwrap();
+        let mut chunks = vec![];
+        while let Some(chunk) = samples.try_next().await.unwrap() {
+            chunks.push((chunk.len(), chunk.sample_rate(), chunk.center_frequency()));
+        }
+
+        assert_eq!(
+            chunks,
+            vec![
+                (600, 1_024_000, Some(100_000_000)),
+                (400, 2_048_000, Some(101_000_000)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_converts_on_request() {
+        let data = [-128i8, 127, 0, -1]
+            .iter()
+            .flat_map(|x| i16::from(*x).wrapping_mul(256).to_le_bytes())
+            .collect::<Vec<u8>>();
+        let (_dir, path) = write_recording("ci16_le", &data, vec![]);
+
+        assert!(matches!(
+            SigMfReader::open_with_options(&path, unpaced(false)),
+            Err(Error::UnsupportedDatatype { .. })
+        ));
+
+        let reader = SigMfReader::open_with_options(&path, unpaced(true)).unwrap();
+        let mut samples = reader.samples().await.unwrap();
+        let chunk = samples.try_next().await.unwrap().unwrap();
+        assert_eq!(chunk.as_bytes(), &[0, 255, 128, 127]);
+        assert_eq!(chunk.center_frequency(), None);
+    }
+
+    #[test]
+    fn it_rejects_unknown_datatypes() {
+        let (_dir, path) = write_recording("cu16_le", &[], vec![]);
+        assert!(matches!(
+            SigMfReader::open_with_options(&path, unpaced(true)),
+            Err(Error::UnsupportedDatatype { .. })
+        ));
+    }
+}
@@ -0,0 +1,445 @@
+use std::{
+    fs::File,
+    io::{
+        BufWriter,
+        Write,
+    },
+    marker::PhantomData,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+use futures_util::TryStreamExt;
+
+use crate::{
+    Chunk,
+    DongleInfo,
+    Samples,
+    sigmf::{
+        Annotation,
+        Capture,
+        Datatype,
+        EXTENSION_NAME,
+        EXTENSION_VERSION,
+        Error,
+        Extension,
+        Global,
+        Metadata,
+        SIGMF_VERSION,
+        recording_paths,
+    },
+};
+
+/// Information about the recording that isn't contained in the samples.
+#[derive(Clone, Debug, Default)]
+pub struct RecordingInfo {
+    /// Center frequency in Hz at the start of the recording.
+    pub center_frequency: Option<u32>,
+
+    /// Information about the dongle that is recorded from.
+    pub dongle_info: Option<DongleInfo>,
+
+    /// Description of the recording.
+    pub description: Option<String>,
+}
+
+/// Writes a SigMF recording.
+///
+/// Chunks are written with [`write_chunk`][Self::write_chunk], or a whole
+/// stream with [`record`][Self::record]. The metadata is only written once
+/// [`finish`][Self::finish] is called.
+///
+/// A new capture segment is started whenever the sample rate or the
+/// [center frequency][Chunk::center_frequency] of the chunks changes, or when
+/// [`set_center_frequency`][Self::set_center_frequency] is called.
+///
+/// Samples that are missing from the stream, e.g. because chunks were
+/// dropped, also start a new capture segment, and are marked with an
+/// annotation. The segment's `core:global_index` is the
+/// [sample index][Chunk::sample_index] in the stream.
+///
+/// Writes to the files are blocking, but buffered.
+#[derive(Debug)]
+pub struct SigMfWriter<T> {
+    data: BufWriter<File>,
+    meta_path: PathBuf,
+    metadata: Metadata,
+    num_samples: u64,
+    center_frequency: Option<u32>,
+    sample_rate: Option<u32>,
+
+    /// center frequency reported by the last chunk
+    chunk_frequency: Option<u32>,
+
+    /// [sample index][Chunk::sample_index] in the stream of the next sample
+    /// we write.
+    stream_index: Option<u64>,
+
+    _phantom: PhantomData<fn(T)>,
+}
+
+impl<T: Datatype> SigMfWriter<T> {
+    /// Create a recording at `path`.
+    ///
+    /// See [`recording_paths`] for how `path` is mapped to the data and
+    /// metadata files.
+    pub fn create(path: impl AsRef<Path>, info: RecordingInfo) -> Result<Self, Error> {
+        let (data_path, meta_path) = recording_paths(path);
+        let data = BufWriter::new(File::create(data_path)?);
+
+        let tuner_type = info.dongle_info.map(|dongle_info| {
+            dongle_info
+                .tuner_type
+                .name()
+                .map(ToOwned::to_owned)
+                .unwrap_or_else(|| dongle_info.tuner_type.0.to_string())
+        });
+
+        let metadata = Metadata {
+            global: Global {
+                datatype: T::DATATYPE.to_owned(),
+                sample_rate: None,
+                version: SIGMF_VERSION.to_owned(),
+                hw: tuner_type
+                    .as_ref()
+                    .map(|tuner_type| format!("RTL-SDR with {tuner_type} tuner")),
+                recorder: Some(concat!("rtlsdr-async ", env!("CARGO_PKG_VERSION")).to_owned()),
+                description: info.description,
+                extensions: vec![Extension {
+                    name: EXTENSION_NAME.to_owned(),
+                    version: EXTENSION_VERSION.to_owned(),
+                    optional: true,
+                }],
+                tuner_type,
+            },
+            captures: vec![],
+            annotations: vec![],
+        };
+
+        Ok(Self {
+            data,
+            meta_path,
+            metadata,
+            num_samples: 0,
+            center_frequency: info.center_frequency,
+            sample_rate: None,
+            chunk_frequency: None,
+            stream_index: None,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Number of samples written so far.
+    pub fn num_samples(&self) -> u64 {
+        self.num_samples
+    }
+
+    /// Notify the writer that the receiver was retuned.
+    ///
+    /// This starts a new capture segment with the next sample that is
+    /// written. This is only needed if the chunks don't report their center
+    /// frequency.
+    pub fn set_center_frequency(&mut self, frequency: u32) {
+        if self.center_frequency != Some(frequency) {
+            self.center_frequency = Some(frequency);
+            if self.sample_rate.is_some() {
+                self.start_capture();
+            }
+        }
+    }
+
+    /// Add an annotation.
+    pub fn annotate(&mut self, annotation: Annotation) {
+        self.metadata.annotations.push(annotation);
+    }
+
+    /// Write a chunk of samples.
+    pub fn write_chunk(&mut self, chunk: &Chunk<T>) -> Result<(), Error> {
+        if chunk.is_empty() {
+            return Ok(());
+        }
+
+        let gap = self.num_samples > 0
+            && (self.stream_index != Some(chunk.sample_index()) || chunk.discontinuity());
+        if gap {
+            self.annotate_gap(chunk);
+        }
+        self.stream_index = Some(chunk.sample_index());
+
+        // only follow changes, so that set_center_frequency isn't undone by the
+        // next chunk.
+        if let Some(frequency) = chunk.center_frequency()
+            && self.chunk_frequency != Some(frequency)
+        {
+            self.chunk_frequency = Some(frequency);
+            self.set_center_frequency(frequency);
+        }
+
+        if self.sample_rate != Some(chunk.sample_rate()) {
+            self.sample_rate = Some(chunk.sample_rate());
+            self.start_capture();
+        }
+        else if gap {
+            self.start_capture();
+        }
+
+        self.data.write_all(chunk.as_bytes())?;
+        self.num_samples += chunk.len() as u64;
+        self.stream_index = Some(chunk.sample_index() + chunk.len() as u64);
+
+        Ok(())
+    }
+
+    /// Write all chunks from `samples` until the stream ends.
+    ///
+    /// To make sure no samples are lost, set the stream's backpressure policy
+    /// to [`Backpressure::Block`][crate::Backpressure::Block] or
+    /// [`Backpressure::Error`][crate::Backpressure::Error]. Otherwise dropped
+    /// chunks are marked as gaps.
+    pub async fn record(&mut self, mut samples: Samples<T>) -> Result<(), Error> {
+        while let Some(chunk) = samples.try_next().await? {
+            self.write_chunk(&chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Flush the data file and write the metadata file.
+    pub fn finish(mut self) -> Result<Metadata, Error> {
+        self.data.flush()?;
+
+        // the global sample rate is only valid if it's the same for the whole
+        // recording. otherwise only the captures have it.
+        let mut sample_rates = self
+            .metadata
+            .captures
+            .iter()
+            .map(|capture| capture.sample_rate);
+        let first = sample_rates.next().flatten();
+        self.metadata.global.sample_rate = sample_rates
+            .all(|sample_rate| sample_rate == first)
+            .then_some(first)
+            .flatten();
+        self.metadata
+            .annotations
+            .sort_by_key(|annotation| annotation.sample_start);
+
+        let meta_file = BufWriter::new(File::create(&self.meta_path)?);
+        serde_json::to_writer_pretty(meta_file, &self.metadata)?;
+
+        Ok(self.metadata)
+    }
+
+    fn start_capture(&mut self) {
+        let capture = Capture {
+            sample_start: self.num_samples,
+            global_index: self
+                .stream_index
+                .filter(|stream_index| *stream_index != self.num_samples),
+            frequency: self.center_frequency.map(f64::from),
+            sample_rate: self.sample_rate.map(f64::from),
+        };
+
+        // replace the last capture segment if it's empty.
+        match self.metadata.captures.last_mut() {
+            Some(last) if last.sample_start == self.num_samples => *last = capture,
+            _ => self.metadata.captures.push(capture),
+        }
+    }
+
+    fn annotate_gap(&mut self, chunk: &Chunk<T>) {
+        let comment = match self.stream_index {
+            Some(stream_index) if chunk.sample_index() > stream_index => {
+                format!("{} samples missing", chunk.sample_index() - stream_index)
+            }
+            _ => "samples missing".to_owned(),
+        };
+        self.annotate(
+            Annotation::new(self.num_samples, 0)
+                .with_label("gap")
+                .with_comment(comment),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Backend,
+        Iq,
+        Pacing,
+        TunerType,
+        simulated::{
+            Scene,
+            SimulatedSdr,
+        },
+    };
+
+    #[tokio::test]
+    async fn it_writes_a_capture_for_each_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording");
+        let sdr = SimulatedSdr::with_pacing(Scene::new(), Pacing::Unpaced);
+
+        let mut writer = SigMfWriter::<Iq>::create(
+            &path,
+            RecordingInfo {
+                center_frequency: Some(100_000_000),
+                dongle_info: Some(sdr.dongle_info()),
+                description: None,
+            },
+        )
+        .unwrap();
+
+        let mut samples = sdr.samples().await.unwrap();
+        let chunk = samples.try_next().await.unwrap().unwrap();
+        writer.write_chunk(&chunk).unwrap();
+        let retuned_at = writer.num_samples();
+
+        writer.set_center_frequency(101_000_000);
+        let chunk = samples.try_next().await.unwrap().unwrap();
+        writer.write_chunk(&chunk).unwrap();
+
+        sdr.set_sample_rate(1_024_000).await.unwrap();
+        let sample_rate_changed_at = loop {
+            let chunk = samples.try_next().await.unwrap().unwrap();
+            let num_samples = writer.num_samples();
+            writer.write_chunk(&chunk).unwrap();
+            if chunk.sample_rate() == 1_024_000 {
+                break num_samples;
+            }
+        };
+        let num_samples = writer.num_samples();
+
+        writer.annotate(Annotation::new(10, 20).with_label("test"));
+        let metadata = writer.finish().unwrap();
+
+        assert_eq!(metadata.global.datatype, "cu8");
+        // the sample rate isn't the same for the whole recording
+        assert_eq!(metadata.global.sample_rate, None);
+        assert_eq!(
+            metadata.global.tuner_type.as_deref(),
+            TunerType::R820T.name()
+        );
+        assert_eq!(
+            metadata.captures,
+            vec![
+                Capture {
+                    sample_start: 0,
+                    frequency: Some(100_000_000.0),
+                    sample_rate: Some(2_048_000.0),
+                    ..Default::default()
+                },
+                Capture {
+                    sample_start: retuned_at,
+                    frequency: Some(101_000_000.0),
+                    sample_rate: Some(2_048_000.0),
+                    ..Default::default()
+                },
+                Capture {
+                    sample_start: sample_rate_changed_at,
+                    frequency: Some(101_000_000.0),
+                    sample_rate: Some(1_024_000.0),
+                    ..Default::default()
+                },
+            ]
+        );
+
+        let (data_path, meta_path) = recording_paths(&path);
+        assert_eq!(std::fs::metadata(data_path).unwrap().len(), 2 * num_samples);
+        let read_back: Metadata = serde_json::from_reader(File::open(meta_path).unwrap()).unwrap();
+        assert_eq!(read_back, metadata);
+    }
+
+    #[tokio::test]
+    async fn it_follows_retunes_reported_by_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording");
+        let sdr = SimulatedSdr::with_pacing(Scene::new(), Pacing::Unpaced);
+
+        let mut writer = SigMfWriter::<Iq>::create(&path, RecordingInfo::default()).unwrap();
+
+        let mut samples = sdr.samples().await.unwrap();
+        let chunk = samples.try_next().await.unwrap().unwrap();
+        assert_eq!(chunk.center_frequency(), Some(100_000_000));
+        writer.write_chunk(&chunk).unwrap();
+
+        sdr.set_center_frequency(433_920_000).await.unwrap();
+        let retuned_at = loop {
+            let chunk = samples.try_next().await.unwrap().unwrap();
+            let num_samples = writer.num_samples();
+            writer.write_chunk(&chunk).unwrap();
+            if chunk.center_frequency() == Some(433_920_000) {
+                break num_samples;
+            }
+        };
+
+        let metadata = writer.finish().unwrap();
+        assert_eq!(metadata.global.sample_rate, Some(2_048_000.0));
+        assert_eq!(
+            metadata.captures,
+            vec![
+                Capture {
+                    sample_start: 0,
+                    frequency: Some(100_000_000.0),
+                    sample_rate: Some(2_048_000.0),
+                    ..Default::default()
+                },
+                Capture {
+                    sample_start: retuned_at,
+                    frequency: Some(433_920_000.0),
+                    sample_rate: Some(2_048_000.0),
+                    ..Default::default()
+                },
+            ]
+        );
+        assert!(metadata.annotations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_marks_gaps() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording");
+        let sdr = SimulatedSdr::with_pacing(Scene::new(), Pacing::Unpaced);
+
+        let mut writer = SigMfWriter::<Iq>::create(&path, RecordingInfo::default()).unwrap();
+
+        let mut samples = sdr.samples().await.unwrap();
+        let first = samples.try_next().await.unwrap().unwrap();
+        writer.write_chunk(&first).unwrap();
+        // not written, as if it was dropped
+        let missing = samples.try_next().await.unwrap().unwrap();
+        let third = samples.try_next().await.unwrap().unwrap();
+        writer.write_chunk(&third).unwrap();
+
+        let metadata = writer.finish().unwrap();
+        let gap_at = first.len() as u64;
+        assert_eq!(
+            metadata.captures,
+            vec![
+                Capture {
+                    sample_start: 0,
+                    global_index: (first.sample_index() != 0).then_some(first.sample_index()),
+                    frequency: Some(100_000_000.0),
+                    sample_rate: Some(2_048_000.0),
+                },
+                Capture {
+                    sample_start: gap_at,
+                    global_index: Some(third.sample_index()),
+                    frequency: Some(100_000_000.0),
+                    sample_rate: Some(2_048_000.0),
+                },
+            ]
+        );
+        assert_eq!(
+            metadata.annotations,
+            vec![
+                Annotation::new(gap_at, 0)
+                    .with_label("gap")
+                    .with_comment(format!("{} samples missing", missing.len()))
+            ]
+        );
+    }
+}
@@ -0,0 +1,796 @@
+//! A simulated RTL-SDR for testing without hardware.
+//!
+//! [`SimulatedSdr`] implements [`Backend`] and synthesizes samples from a
+//! [`Scene`]. The signals in the scene are placed at absolute frequencies, so
+//! retuning the simulated device shifts them around in the baseband, just like
+//! it would with a real dongle.
+
+use std::{
+    f64::consts::TAU,
+    sync::Arc,
+    thread,
+    time::Instant,
+};
+
+use parking_lot::Mutex;
+
+use crate::{
+    Backend,
+    Bandwidth,
+    DEFAULT_BUFFER_SIZE,
+    DEFAULT_QUEUE_SIZE,
+    DeviceConfig,
+    DirectSamplingMode,
+    DongleInfo,
+    Error,
+    Gain,
+    Iq,
+    SampleType,
+    Samples,
+    TunerType,
+    buffer_queue::{
+        self,
+        Buffer,
+    },
+    pacing::{
+        Pacer,
+        Pacing,
+    },
+};
+
+/// Gain values of the R820T in tenths of a dB, as reported by librtlsdr.
+const TUNER_GAINS: &[i32] = &[
+    0, 9, 14, 27, 37, 77, 87, 125, 144, 157, 166, 197, 207, 229, 254, 280, 297, 328, 338, 364, 372,
+    386, 402, 421, 434, 439, 445, 480, 496,
+];
+
+const DEFAULT_CENTER_FREQUENCY: u32 = 100_000_000;
+const DEFAULT_SAMPLE_RATE: u32 = 2_048_000;
+
+/// librtlsdr returns `-EINVAL` for invalid arguments
+const EINVAL: i32 = -22;
+
+/// A scene of signals that a [`SimulatedSdr`] receives.
+#[derive(Clone, Debug)]
+pub struct Scene {
+    /// Noise floor in dBFS at 0 dB tuner gain.
+    pub noise_floor: f32,
+
+    /// Signals present in the scene.
+    pub signals: Vec<Signal>,
+}
+
+impl Scene {
+    /// Default noise floor in dBFS.
+    pub const DEFAULT_NOISE_FLOOR: f32 = -60.0;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the noise floor in dBFS at 0 dB tuner gain.
+    ///
+    /// Use [`f32::NEG_INFINITY`] for a noiseless scene.
+    pub fn with_noise_floor(mut self, noise_floor: f32) -> Self {
+        self.noise_floor = noise_floor;
+        self
+    }
+
+    /// Add a signal to the scene.
+    pub fn with_signal(mut self, signal: Signal) -> Self {
+        self.signals.push(signal);
+        self
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            noise_floor: Self::DEFAULT_NOISE_FLOOR,
+            signals: vec![],
+        }
+    }
+}
+
+/// A signal in a [`Scene`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Signal {
+    /// Absolute carrier frequency in Hz.
+    pub frequency: u32,
+
+    /// Carrier level in dBFS at 0 dB tuner gain.
+    pub level: f32,
+
+    /// How the carrier is modulated.
+    pub modulation: Modulation,
+}
+
+impl Signal {
+    /// An unmodulated carrier.
+    pub fn tone(frequency: u32, level: f32) -> Self {
+        Self {
+            frequency,
+            level,
+            modulation: Modulation::None,
+        }
+    }
+
+    /// An amplitude modulated carrier with a sine tone of `frequency` Hz as
+    /// message.
+    pub fn am(carrier: u32, level: f32, frequency: f32, depth: f32) -> Self {
+        Self {
+            frequency: carrier,
+            level,
+            modulation: Modulation::Am { frequency, depth },
+        }
+    }
+
+    /// A frequency modulated carrier with a sine tone of `frequency` Hz as
+    /// message.
+    pub fn fm(carrier: u32, level: f32, frequency: f32, deviation: f32) -> Self {
+        Self {
+            frequency: carrier,
+            level,
+            modulation: Modulation::Fm {
+                frequency,
+                deviation,
+            },
+        }
+    }
+}
+
+/// Modulation of a [`Signal`].
+///
+/// The message is always a sine tone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Modulation {
+    /// Plain carrier
+    None,
+    /// Amplitude modulation
+    Am {
+        /// Frequency of the message tone in Hz
+        frequency: f32,
+        /// Modulation depth, from 0 to 1
+        depth: f32,
+    },
+    /// Frequency modulation
+    Fm {
+        /// Frequency of the message tone in Hz
+        frequency: f32,
+        /// Peak frequency deviation in Hz
+        deviation: f32,
+    },
+}
+
+/// A simulated RTL-SDR.
+///
+/// This behaves like an [`RtlSdr`][crate::RtlSdr] with an R820T tuner, but
+/// synthesizes its samples from a [`Scene`]:
+///
+/// - Signals are placed at absolute frequencies, so
+///   [`set_center_frequency`][Backend::set_center_frequency] shifts them in the
+///   baseband. Signals outside of the sampled bandwidth are not received.
+/// - [`set_sample_rate`][Backend::set_sample_rate] accepts the same sample
+///   rates as librtlsdr does.
+/// - [`set_tuner_gain`][Backend::set_tuner_gain] scales the signal before it's
+///   quantized, so too much gain will clip the 8 bit output. [`Gain::Auto`] is
+///   simulated as 0 dB gain.
+///
+/// Like [`RtlSdr`][crate::RtlSdr] this is cheaply cloneable, and all clones
+/// share the same simulated device.
+#[derive(Clone, Debug)]
+pub struct SimulatedSdr {
+    state: Arc<Mutex<SimulatorState>>,
+    buffer_queue_subscriber: buffer_queue::Subscriber,
+}
+
+impl SimulatedSdr {
+    /// Create a simulated RTL-SDR that produces samples in real-time.
+    pub fn new(scene: Scene) -> Self {
+        Self::with_pacing(scene, Pacing::RealTime)
+    }
+
+    /// Create a simulated RTL-SDR with the given pacing.
+    ///
+    /// [`Pacing::Unpaced`] is useful in tests, where you don't want to wait
+    /// for the samples.
+    pub fn with_pacing(scene: Scene, pacing: Pacing) -> Self {
+        let state = Arc::new(Mutex::new(SimulatorState::new(scene)));
+        let buffer_queue_subscriber = spawn_generator_thread(
+            state.clone(),
+            pacing,
+            DEFAULT_BUFFER_SIZE,
+            DEFAULT_QUEUE_SIZE,
+        );
+
+        Self {
+            state,
+            buffer_queue_subscriber,
+        }
+    }
+
+    /// Replace the scene.
+    pub fn set_scene(&self, scene: Scene) {
+        self.state.lock().set_scene(scene);
+    }
+
+    pub fn get_center_frequency(&self) -> u32 {
+        self.state.lock().center_frequency
+    }
+
+    pub fn get_sample_rate(&self) -> u32 {
+        self.state.lock().sample_rate
+    }
+
+    pub fn get_tuner_type(&self) -> TunerType {
+        TunerType::R820T
+    }
+
+    pub fn get_tuner_gains(&self) -> &[i32] {
+        TUNER_GAINS
+    }
+
+    /// Returns the tuner gain in tenths of a dB.
+    pub fn get_tuner_gain(&self) -> i32 {
+        self.state.lock().tuner_gain
+    }
+
+    pub fn get_frequency_correction(&self) -> i32 {
+        self.state.lock().ppm
+    }
+
+    pub fn get_direct_sampling(&self) -> Option<DirectSamplingMode> {
+        self.state.lock().direct_sampling
+    }
+}
+
+impl Backend for SimulatedSdr {
+    type Error = Error;
+
+    fn dongle_info(&self) -> DongleInfo {
+        DongleInfo {
+            tuner_type: self.get_tuner_type(),
+            tuner_gain_count: TUNER_GAINS.len() as u32,
+        }
+    }
+
+    async fn config(&self) -> Result<DeviceConfig, Error> {
+        let state = self.state.lock();
+        Ok(DeviceConfig {
+            center_frequency: Some(state.center_frequency),
+            sample_rate: Some(state.sample_rate),
+            tuner_gain: Some(if state.auto_gain {
+                Gain::Auto
+            }
+            else {
+                Gain::ManualValue(state.tuner_gain)
+            }),
+            tuner_bandwidth: state.tuner_bandwidth,
+            frequency_correction: Some(state.ppm),
+            offset_tuning: Some(false),
+            direct_sampling: state.direct_sampling,
+            ..Default::default()
+        })
+    }
+
+    async fn set_center_frequency(&self, frequency: u32) -> Result<(), Error> {
+        self.state.lock().center_frequency = frequency;
+        Ok(())
+    }
+
+    async fn set_sample_rate(&self, sample_rate: u32) -> Result<(), Error> {
+        // same check as rtlsdr_set_sample_rate
+        if !((225_001..=300_000).contains(&sample_rate)
+            || (900_001..=3_200_000).contains(&sample_rate))
+        {
+            return Err(Error::from_lib("rtlsdr_set_sample_rate", EINVAL));
+        }
+        self.state.lock().sample_rate = sample_rate;
+        Ok(())
+    }
+
+    async fn set_tuner_gain(&self, gain: Gain) -> Result<(), Error> {
+        let (tuner_gain, auto_gain) = match gain {
+            Gain::ManualValue(gain) => {
+                let gain = TUNER_GAINS
+                    .iter()
+                    .min_by_key(|supported| (**supported - gain).abs())
+                    .ok_or(Error::NoSupportedGains)?;
+                (*gain, false)
+            }
+            Gain::ManualIndex(index) => {
+                let gain = TUNER_GAINS
+                    .get(index)
+                    .ok_or(Error::InvalidGainIndex { index })?;
+                (*gain, false)
+            }
+            Gain::Auto => (0, true),
+        };
+        let mut state = self.state.lock();
+        state.tuner_gain = tuner_gain;
+        state.auto_gain = auto_gain;
+        Ok(())
+    }
+
+    async fn set_tuner_bandwidth(&self, bandwidth: Bandwidth) -> Result<(), Error> {
+        // we don't simulate the IF filter
+        self.state.lock().tuner_bandwidth = Some(bandwidth);
+        Ok(())
+    }
+
+    async fn set_agc_mode(&self, _enable: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_frequency_correction(&self, ppm: i32) -> Result<(), Error> {
+        self.state.lock().ppm = ppm;
+        Ok(())
+    }
+
+    async fn set_tuner_if_gain(&self, _stage: i16, _gain: i16) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_offset_tuning(&self, _enable: bool) -> Result<(), Error> {
+        // same as `Control::set_offset_tuning` for R82xx tuners
+        Err(Error::Unsupported)
+    }
+
+    async fn set_rtl_xtal(&self, _frequency: u32) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_tuner_xtal(&self, _frequency: u32) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_bias_tee(&self, _enable: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_bias_tee_gpio(&self, _pin: u8, _enable: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn set_direct_sampling(&self, mode: Option<DirectSamplingMode>) -> Result<(), Error> {
+        self.state.lock().direct_sampling = mode;
+        Ok(())
+    }
+
+    async fn samples(&self) -> Result<Samples<Iq>, Error> {
+        self.state.lock().direct_sampling = None;
+        Ok(Samples::new(
+            self.buffer_queue_subscriber.receiver(),
+            SampleType::Iq,
+        ))
+    }
+
+    async fn direct_samples(&self, mode: DirectSamplingMode) -> Result<Samples<u8>, Error> {
+        self.state.lock().direct_sampling = Some(mode);
+        Ok(Samples::new(
+            self.buffer_queue_subscriber.receiver(),
+            mode.into(),
+        ))
+    }
+}
+
+#[derive(Debug)]
+struct SimulatorState {
+    scene: Scene,
+
+    /// phases of the carriers and messages of the signals in the scene. these
+    /// are kept across buffers (and retunes), so the signals are continuous.
+    phases: Vec<SignalPhase>,
+
+    rng: XorShift,
+
+    center_frequency: u32,
+    sample_rate: u32,
+    tuner_gain: i32,
+    auto_gain: bool,
+    tuner_bandwidth: Option<Bandwidth>,
+    ppm: i32,
+    direct_sampling: Option<DirectSamplingMode>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct SignalPhase {
+    carrier: f64,
+    message: f64,
+}
+
+impl SimulatorState {
+    fn new(scene: Scene) -> Self {
+        Self {
+            phases: vec![Default::default(); scene.signals.len()],
+            scene,
+            rng: XorShift::new(0x5eed),
+            center_frequency: DEFAULT_CENTER_FREQUENCY,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            tuner_gain: 0,
+            auto_gain: false,
+            tuner_bandwidth: None,
+            ppm: 0,
+            direct_sampling: None,
+        }
+    }
+
+    fn set_scene(&mut self, scene: Scene) {
+        self.phases = vec![Default::default(); scene.signals.len()];
+        self.scene = scene;
+    }
+
+    /// fills `buffer` with samples and returns the number of bytes written.
+    fn generate(&mut self, buffer: &mut [u8]) -> usize {
+        let sample_rate = f64::from(self.sample_rate);
+        let gain = 10f64.powf(f64::from(self.tuner_gain) / 200.0);
+        let noise_amplitude = 10f64.powf(f64::from(self.scene.noise_floor) / 20.0);
+
+        match self.direct_sampling {
+            None => {
+                // only signals within the sampled bandwidth reach the ADC
+                let center_frequency = f64::from(self.center_frequency);
+                let offsets = self
+                    .scene
+                    .signals
+                    .iter()
+                    .map(|signal| {
+                        let offset = f64::from(signal.frequency) - center_frequency;
+                        (offset.abs() < sample_rate / 2.0).then_some(offset)
+                    })
+                    .collect::<Vec<_>>();
+
+                let noise_amplitude = noise_amplitude * std::f64::consts::FRAC_1_SQRT_2;
+
+                let samples: &mut [Iq] = bytemuck::cast_slice_mut(buffer);
+                for sample in samples.iter_mut() {
+                    let mut i = noise_amplitude * self.rng.next_gaussian();
+                    let mut q = noise_amplitude * self.rng.next_gaussian();
+
+                    for ((signal, phase), offset) in self
+                        .scene
+                        .signals
+                        .iter()
+                        .zip(&mut self.phases)
+                        .zip(&offsets)
+                    {
+                        let Some(offset) = offset
+                        else {
+                            continue;
+                        };
+                        let amplitude = phase.advance(signal, *offset, sample_rate);
+                        i += amplitude * phase.carrier.cos();
+                        q += amplitude * phase.carrier.sin();
+                    }
+
+                    sample.i = quantize(i * gain);
+                    sample.q = quantize(q * gain);
+                }
+
+                2 * samples.len()
+            }
+            Some(_) => {
+                // the ADC samples the antenna directly, so signals above the
+                // nyquist frequency alias, just like they would with a real
+                // dongle.
+                for sample in buffer.iter_mut() {
+                    let mut x = noise_amplitude * self.rng.next_gaussian();
+
+                    for (signal, phase) in self.scene.signals.iter().zip(&mut self.phases) {
+                        let amplitude =
+                            phase.advance(signal, f64::from(signal.frequency), sample_rate);
+                        x += amplitude * phase.carrier.cos();
+                    }
+
+                    *sample = quantize(x * gain);
+                }
+
+                buffer.len()
+            }
+        }
+    }
+}
+
+impl SignalPhase {
+    /// advances the phases by one sample and returns the instantaneous
+    /// amplitude.
+    fn advance(&mut self, signal: &Signal, frequency: f64, sample_rate: f64) -> f64 {
+        let level = 10f64.powf(f64::from(signal.level) / 20.0);
+
+        let (amplitude, frequency) = match signal.modulation {
+            Modulation::None => (level, frequency),
+            Modulation::Am {
+                frequency: message_frequency,
+                depth,
+            } => {
+                self.message = (self.message + TAU * f64::from(message_frequency) / sample_rate)
+                    .rem_euclid(TAU);
+                let envelope = 1.0 + f64::from(depth) * self.message.sin();
+                (level * envelope / (1.0 + f64::from(depth)), frequency)
+            }
+            Modulation::Fm {
+                frequency: message_frequency,
+                deviation,
+            } => {
+                self.message = (self.message + TAU * f64::from(message_frequency) / sample_rate)
+                    .rem_euclid(TAU);
+                (level, frequency + f64::from(deviation) * self.message.sin())
+            }
+        };
+
+        self.carrier = (self.carrier + TAU * frequency / sample_rate).rem_euclid(TAU);
+
+        amplitude
+    }
+}
+
+/// maps [-1, 1] to [0, 255], clipping anything outside of that range.
+fn quantize(x: f64) -> u8 {
+    ((x + 1.0) * 127.5).round().clamp(0.0, 255.0) as u8
+}
+
+/// xorshift64* PRNG for the noise. we don't need anything fancy here, and this
+/// keeps the simulation reproducible.
+#[derive(Debug)]
+struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        Self { state: seed.max(1) }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    /// uniform in (0, 1]
+    fn next_f64(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    /// standard normal distribution (box-muller)
+    fn next_gaussian(&mut self) -> f64 {
+        let u1 = self.next_f64();
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (TAU * u2).cos()
+    }
+}
+
+fn spawn_generator_thread(
+    state: Arc<Mutex<SimulatorState>>,
+    pacing: Pacing,
+    buffer_size: usize,
+    queue_size: usize,
+) -> buffer_queue::Subscriber {
+    let (buffer_queue_sender, buffer_queue_subscriber) = buffer_queue::channel(queue_size);
+
+    thread::spawn(move || {
+        generator_thread(buffer_queue_sender, state, pacing, buffer_size);
+    });
+
+    buffer_queue_subscriber
+}
+
+/// this does the same as the reader thread in `sampling.rs`, but instead of
+/// reading from a device, we generate the samples.
+fn generator_thread(
+    mut buffer_queue_sender: buffer_queue::Sender,
+    state: Arc<Mutex<SimulatorState>>,
+    pacing: Pacing,
+    buffer_size: usize,
+) {
+    let _guard = tracing::debug_span!("generator thread").entered();
+
+    let mut pacer = Pacer::new(pacing);
+    let mut push_buffer: Option<Buffer> = None;
+    let mut sample_index = 0;
+
+    tracing::debug!("generator thread spawned");
+
+    loop {
+        let Some(mut buffer) =
+            buffer_queue_sender.swap_buffers(push_buffer.take(), buffer_size, true)
+        else {
+            // all receivers and subscribers dropped
+            tracing::debug!("all readers dropped. exiting");
+            break;
+        };
+
+        let (num_samples, sample_rate) = {
+            let mut state = state.lock();
+
+            buffer.sample_rate = state.sample_rate;
+            buffer.sample_type = state.direct_sampling.into();
+            buffer.center_frequency = state
+                .direct_sampling
+                .is_none()
+                .then_some(state.center_frequency);
+            buffer.frequency_correction = Some(state.ppm);
+            buffer.tuner_gain = Some(if state.auto_gain {
+                Gain::Auto
+            }
+            else {
+                Gain::ManualValue(state.tuner_gain)
+            });
+
+            let buffer_mut = buffer.reclaim_or_allocate(buffer_size);
+            let n_written = state.generate(buffer_mut);
+
+            buffer.start = 0;
+            buffer.end = n_written;
+            buffer.sample_index = sample_index;
+
+            (buffer.num_samples(), state.sample_rate)
+        };
+        sample_index += num_samples as u64;
+
+        // the samples are only "captured" once the pacer lets them through
+        pacer.wait(num_samples, sample_rate);
+        buffer.timestamp = Instant::now();
+
+        push_buffer = Some(buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::TryStreamExt;
+
+    use super::*;
+    use crate::u8_to_f32;
+
+    /// returns the power at `frequency` relative to the center frequency
+    fn power_at(samples: &[Iq], frequency: f64, sample_rate: u32) -> f64 {
+        let (mut re, mut im) = (0.0, 0.0);
+        for (n, sample) in samples.iter().enumerate() {
+            let i = f64::from(u8_to_f32(sample.i));
+            let q = f64::from(u8_to_f32(sample.q));
+            let (sin, cos) = (-TAU * frequency * n as f64 / f64::from(sample_rate)).sin_cos();
+            re += i * cos - q * sin;
+            im += i * sin + q * cos;
+        }
+        let n = samples.len() as f64;
+        (re / n).powi(2) + (im / n).powi(2)
+    }
+
+    /// returns samples that were generated after this was called.
+    async fn next_chunk(sdr: &SimulatedSdr) -> Vec<Iq> {
+        let mut samples = sdr.samples().await.unwrap();
+        // the generator might have a buffer in flight that was generated before
+        // the last configuration change, so we skip it.
+        samples.try_next().await.unwrap().unwrap();
+        samples
+            .try_next()
+            .await
+            .unwrap()
+            .unwrap()
+            .samples()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn tone_is_placed_relative_to_center_frequency() {
+        let scene = Scene::new()
+            .with_noise_floor(f32::NEG_INFINITY)
+            .with_signal(Signal::tone(100_200_000, -6.0));
+        let sdr = SimulatedSdr::with_pacing(scene, Pacing::Unpaced);
+        sdr.set_sample_rate(2_048_000).await.unwrap();
+
+        sdr.set_center_frequency(100_000_000).await.unwrap();
+        let samples = next_chunk(&sdr).await;
+        assert!(power_at(&samples, 200_000.0, 2_048_000) > 0.1);
+        assert!(power_at(&samples, -200_000.0, 2_048_000) < 0.001);
+
+        // retuning shifts the tone
+        sdr.set_center_frequency(100_300_000).await.unwrap();
+        let samples = next_chunk(&sdr).await;
+        assert!(power_at(&samples, -100_000.0, 2_048_000) > 0.1);
+        assert!(power_at(&samples, 200_000.0, 2_048_000) < 0.001);
+    }
+
+    #[tokio::test]
+    async fn signals_outside_of_bandwidth_are_not_received() {
+        let scene = Scene::new()
+            .with_noise_floor(f32::NEG_INFINITY)
+            .with_signal(Signal::tone(110_000_000, 0.0));
+        let sdr = SimulatedSdr::with_pacing(scene, Pacing::Unpaced);
+
+        let samples = next_chunk(&sdr).await;
+        assert!(
+            samples
+                .iter()
+                .all(|sample| sample.i == 128 && sample.q == 128)
+        );
+    }
+
+    #[tokio::test]
+    async fn gain_clips_output() {
+        let scene = Scene::new()
+            .with_noise_floor(f32::NEG_INFINITY)
+            .with_signal(Signal::tone(100_100_000, -20.0));
+        let sdr = SimulatedSdr::with_pacing(scene, Pacing::Unpaced);
+
+        let samples = next_chunk(&sdr).await;
+        assert!(samples.iter().all(|sample| sample.i > 0 && sample.i < 255));
+
+        sdr.set_tuner_gain(Gain::ManualValue(496)).await.unwrap();
+        assert_eq!(sdr.get_tuner_gain(), 496);
+        let samples = next_chunk(&sdr).await;
+        assert!(samples.iter().any(|sample| sample.i == 0));
+        assert!(samples.iter().any(|sample| sample.i == 255));
+    }
+
+    #[tokio::test]
+    async fn sample_rate_is_validated_and_reported() {
+        let sdr = SimulatedSdr::with_pacing(Scene::new(), Pacing::Unpaced);
+        assert!(sdr.set_sample_rate(500_000).await.is_err());
+        sdr.set_sample_rate(2_400_000).await.unwrap();
+
+        let mut samples = sdr.samples().await.unwrap();
+        samples.try_next().await.unwrap().unwrap();
+        let chunk = samples.try_next().await.unwrap().unwrap();
+        assert_eq!(chunk.sample_rate(), 2_400_000);
+    }
+
+    #[tokio::test]
+    async fn sample_index_counts_across_retunes() {
+        let sdr = SimulatedSdr::with_pacing(Scene::new(), Pacing::Unpaced);
+        let mut samples = sdr.samples().await.unwrap();
+
+        let first = samples.try_next().await.unwrap().unwrap();
+        sdr.set_center_frequency(101_000_000).await.unwrap();
+
+        let mut last = first;
+        while last.center_frequency() != Some(101_000_000) {
+            let chunk = samples.try_next().await.unwrap().unwrap();
+            if chunk.dropped() == 0 {
+                assert_eq!(
+                    chunk.sample_index(),
+                    last.sample_index() + last.len() as u64
+                );
+            }
+            assert!(chunk.timestamp() >= last.timestamp());
+            last = chunk;
+        }
+
+        // slicing moves the index to the new first sample
+        let sample_index = last.sample_index();
+        last.slice(10..);
+        assert_eq!(last.sample_index(), sample_index + 10);
+    }
+
+    #[tokio::test]
+    async fn it_keeps_pacing_after_idling() {
+        let sdr = SimulatedSdr::new(Scene::new());
+        let mut samples = sdr.samples().await.unwrap();
+        samples.try_next().await.unwrap().unwrap();
+        drop(samples);
+
+        // without receivers the generator waits, and falls behind the wall clock
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let mut samples = sdr.samples().await.unwrap();
+        // the first chunk might have been generated before we subscribed
+        samples.try_next().await.unwrap().unwrap();
+        let first = samples.try_next().await.unwrap().unwrap();
+        let mut last = first.timestamp();
+        let mut num_samples = 0;
+        for _ in 0..50 {
+            let chunk = samples.try_next().await.unwrap().unwrap();
+            num_samples += chunk.len();
+            last = chunk.timestamp();
+        }
+
+        // the backlog is not produced in a burst
+        let expected =
+            Duration::from_secs_f64(num_samples as f64 / f64::from(first.sample_rate()));
+        assert!(last - first.timestamp() > expected.mul_f64(0.9));
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    DeviceFilter,
+    Error,
+    buffer_queue,
+    enumerate::devices,
+    handle::Handle,
+};
+
+/// How often we look for a disconnected device.
+pub(crate) const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often in a row we try to reopen a device that is present, before we
+/// give up.
+const MAX_FAILED_REOPENS: u32 = 10;
+
+/// A device that can be closed and reopened.
+///
+/// This is implemented by [`Handle`], and is only a trait, so that the
+/// supervisor can be tested without hardware.
+pub(crate) trait Reopen {
+    fn close(&self);
+
+    fn reopen(&self, index: u32) -> Result<(), Error>;
+}
+
+impl Reopen for Handle {
+    fn close(&self) {
+        Handle::close(self);
+    }
+
+    fn reopen(&self, index: u32) -> Result<(), Error> {
+        Handle::reopen(self, index)
+    }
+}
+
+/// Watches for a disconnected device to come back, and reopens it.
+///
+/// The device is identified by its USB serial, since the index will most
+/// likely be different when it's plugged in again.
+#[derive(Clone, Debug)]
+pub(crate) struct Supervisor {
+    filter: DeviceFilter,
+    poll_interval: Duration,
+}
+
+impl Supervisor {
+    /// Creates a supervisor for the device that is currently at `index`.
+    ///
+    /// This fails if the device has no serial, or if the serial isn't unique,
+    /// since we wouldn't know which device to reopen.
+    pub fn new(index: u32, poll_interval: Duration) -> Result<Self, Error> {
+        let serial = devices()
+            .find(|device| device.index() == index)
+            .and_then(|device| device.serial().map(ToOwned::to_owned))
+            .ok_or(Error::MissingSerial { index })?;
+
+        let filter = DeviceFilter::new().with_exact_serial(serial);
+
+        // make sure the serial identifies this device
+        filter.find()?;
+
+        Ok(Self {
+            filter,
+            poll_interval,
+        })
+    }
+
+    /// Waits until the device is present again and reopens `device`.
+    ///
+    /// Returns `false` if all receivers and subscribers dropped in the
+    /// meantime, or if the device is present, but can't be reopened.
+    pub fn reconnect(&self, device: &impl Reopen, sender: &buffer_queue::Sender) -> bool {
+        self.reconnect_with(device, sender, || {
+            self.filter.find().map(|device| device.index())
+        })
+    }
+
+    /// [`reconnect`][Self::reconnect], but `find` returns the index of the
+    /// device, if it's present.
+    fn reconnect_with(
+        &self,
+        device: &impl Reopen,
+        sender: &buffer_queue::Sender,
+        mut find: impl FnMut() -> Result<u32, Error>,
+    ) -> bool {
+        tracing::info!(filter = %self.filter, "device disconnected. waiting for it to come back");
+
+        // the device might not be gone at all, e.g. after a transient USB error. we
+        // couldn't open it again while we still hold it.
+        device.close();
+
+        let mut failed_reopens = 0;
+
+        loop {
+            if sender.is_closed() {
+                tracing::debug!("all readers dropped while reconnecting");
+                return false;
+            }
+
+            match find() {
+                Ok(index) => {
+                    match device.reopen(index) {
+                        Ok(()) => {
+                            tracing::info!(index, "device reopened");
+                            return true;
+                        }
+                        Err(error) => {
+                            tracing::warn!(?error, "failed to reopen device");
+                            failed_reopens += 1;
+                            if failed_reopens >= MAX_FAILED_REOPENS {
+                                tracing::error!(
+                                    failed_reopens,
+                                    "device is present, but can't be reopened. giving up"
+                                );
+                                return false;
+                            }
+                        }
+                    }
+                }
+                Err(Error::NoMatchingDevice { .. }) => {
+                    failed_reopens = 0;
+                }
+                Err(error) => {
+                    tracing::warn!(?error, "can't identify device");
+                }
+            }
+
+            thread::sleep(self.poll_interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    /// records what the supervisor does with it
+    #[derive(Debug, Default)]
+    struct MockDevice {
+        calls: RefCell<Vec<Call>>,
+        fail_reopen: bool,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Call {
+        Close,
+        Reopen(u32),
+    }
+
+    impl Reopen for MockDevice {
+        fn close(&self) {
+            self.calls.borrow_mut().push(Call::Close);
+        }
+
+        fn reopen(&self, index: u32) -> Result<(), Error> {
+            self.calls.borrow_mut().push(Call::Reopen(index));
+            if self.fail_reopen {
+                Err(Error::from_lib("rtlsdr_open", -1))
+            }
+            else {
+                Ok(())
+            }
+        }
+    }
+
+    fn supervisor() -> Supervisor {
+        Supervisor {
+            filter: DeviceFilter::new().with_exact_serial("00000001"),
+            poll_interval: Duration::ZERO,
+        }
+    }
+
+    fn no_matching_device() -> Error {
+        Error::NoMatchingDevice {
+            filter: "serial=00000001".to_owned(),
+        }
+    }
+
+    #[test]
+    fn it_closes_the_device_before_reopening_it() {
+        let device = MockDevice::default();
+        let (sender, _subscriber) = buffer_queue::channel(1);
+
+        assert!(supervisor().reconnect_with(&device, &sender, || Ok(3)));
+        assert_eq!(*device.calls.borrow(), [Call::Close, Call::Reopen(3)]);
+    }
+
+    #[test]
+    fn it_waits_for_the_device_to_come_back() {
+        let device = MockDevice::default();
+        let (sender, _subscriber) = buffer_queue::channel(1);
+        let mut polls = 0;
+
+        assert!(supervisor().reconnect_with(&device, &sender, || {
+            polls += 1;
+            if polls < 5 {
+                Err(no_matching_device())
+            }
+            else {
+                Ok(7)
+            }
+        }));
+        assert_eq!(polls, 5);
+        assert_eq!(*device.calls.borrow(), [Call::Close, Call::Reopen(7)]);
+    }
+
+    #[test]
+    fn it_gives_up_if_the_device_cant_be_reopened() {
+        let device = MockDevice {
+            fail_reopen: true,
+            ..Default::default()
+        };
+        let (sender, _subscriber) = buffer_queue::channel(1);
+
+        assert!(!supervisor().reconnect_with(&device, &sender, || Ok(3)));
+        assert_eq!(device.calls.borrow().len(), 1 + MAX_FAILED_REOPENS as usize);
+    }
+
+    #[test]
+    fn it_stops_when_all_readers_dropped() {
+        let device = MockDevice::default();
+        let (sender, subscriber) = buffer_queue::channel(1);
+        drop(subscriber);
+
+        assert!(!supervisor().reconnect_with(&device, &sender, || Err(no_matching_device())));
+        assert_eq!(*device.calls.borrow(), [Call::Close]);
+    }
+}
@@ -1,4 +1,12 @@
-use std::str::FromStr;
+#[cfg(feature = "tls")]
+use std::{
+    path::PathBuf,
+    sync::Arc,
+};
+use std::{
+    ops::RangeInclusive,
+    str::FromStr,
+};
 
 use clap::Parser;
 use color_eyre::eyre::{
@@ -6,8 +14,34 @@ use color_eyre::eyre::{
     eyre,
 };
 use rtlsdr_async::{
+    DeviceFilter,
+    ReaderMode,
     RtlSdr,
-    rtl_tcp::server::RtlTcpServer,
+    rtl_tcp::{
+        CommandKind,
+        access_control::{
+            AccessControlHandler,
+            AccessPolicy,
+            Cidr,
+        },
+        server::{
+            BackendHandler,
+            Handler,
+            RtlTcpServer,
+            UnknownCommandPolicy,
+        },
+        transport::Listener,
+        virtual_receiver::{
+            Band,
+            OutOfBandPolicy,
+            VirtualReceiverHandler,
+        },
+    },
+};
+#[cfg(feature = "tls")]
+use rtlsdr_async::rtl_tcp::tls::{
+    self,
+    TlsListener,
 };
 use tokio::net::TcpListener;
 
@@ -17,17 +51,101 @@ struct Args {
     #[clap(short, long, default_value = "localhost:1234")]
     address: String,
 
-    #[clap(short, long, default_value = "0")]
-    device: u32,
+    /// Index of the device to open
+    #[clap(short, long, conflicts_with = "serial")]
+    device: Option<u32>,
+
+    /// Serial number of the device to open. Can contain `*` and `?` wildcards.
+    #[clap(long)]
+    serial: Option<String>,
+
+    /// Reopen the device if it's unplugged and plugged in again
+    #[clap(long)]
+    reconnect: bool,
 
     /// Frequency to tune to
-    #[clap(short, long)]
+    #[clap(short, long, required_if_eq("virtual_receivers", "true"))]
     frequency: Option<u32>,
 
     /// Gain - either 'auto' or in dB
     #[clap(short, long)]
     gain: Option<Gain>,
 
+    /// Read with librtlsdr's async API, using this many USB buffers (0 for the
+    /// default)
+    #[clap(long)]
+    async_buffers: Option<u32>,
+
+    /// Enable the bias-tee. This requires the `bias-tee` feature, which needs
+    /// librtlsdr 0.6.0 or later
+    #[clap(long)]
+    bias_tee: bool,
+
+    /// Send a snapshot of the current config to new clients. This is an
+    /// extension to the protocol, which other clients don't understand.
+    #[clap(long)]
+    config_snapshot: bool,
+
+    /// What to do when a client sends an unknown command - either 'ignore',
+    /// 'close', or N to close the connection on the N-th unknown command
+    #[clap(long, default_value = "ignore")]
+    unknown_commands: UnknownCommandPolicy,
+
+    /// Give every client its own virtual receiver inside the band given by
+    /// the frequency and sample rate. Clients can then only select their
+    /// channel, but not change any other settings.
+    #[clap(long)]
+    virtual_receivers: bool,
+
+    /// Move channels of virtual receivers that don't fit into the band
+    /// instead of rejecting them
+    #[clap(long, requires = "virtual_receivers")]
+    clamp_out_of_band: bool,
+
+    /// Only accept clients from this address block, e.g. '192.168.0.0/16'.
+    /// Can be given multiple times.
+    #[clap(long)]
+    allow: Vec<Cidr>,
+
+    /// Reject clients from this address block. Can be given multiple times.
+    #[clap(long)]
+    deny: Vec<Cidr>,
+
+    /// Maximum number of clients that can be connected at the same time
+    #[clap(long)]
+    max_clients: Option<usize>,
+
+    /// Only the first client can send commands, and all others are read-only
+    #[clap(long)]
+    first_client_controls: bool,
+
+    /// Only allow these commands, e.g. 'set-center-frequency,set-sample-rate'.
+    /// Vendor commands that aren't known are then treated like unknown
+    /// commands.
+    #[clap(long, value_delimiter = ',')]
+    allow_commands: Option<Vec<CommandKind>>,
+
+    /// Only allow tuning to frequencies in this range, e.g.
+    /// '88000000-108000000'
+    #[clap(long)]
+    frequency_range: Option<FrequencyRange>,
+
+    /// Serve over TLS with the certificate chain in this PEM file
+    #[cfg(feature = "tls")]
+    #[clap(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM file with the private key of the TLS certificate
+    #[cfg(feature = "tls")]
+    #[clap(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Only accept clients with a certificate signed by a CA in this PEM
+    /// file
+    #[cfg(feature = "tls")]
+    #[clap(long, requires = "tls_cert")]
+    client_ca: Option<PathBuf>,
+
     /// Sample rate in Hz
     #[clap(short, long, default_value = "2048000")]
     samplerate: u32,
@@ -41,18 +159,110 @@ async fn main() -> Result<(), Error> {
 
     let args = Args::parse();
 
-    let rtl_sdr = RtlSdr::open(args.device)?;
+    let mut builder = RtlSdr::builder()
+        .with_sample_rate(args.samplerate)
+        .with_reconnect(args.reconnect);
     if let Some(frequency) = args.frequency {
-        rtl_sdr.set_center_frequency(frequency).await?;
+        builder = builder.with_center_frequency(frequency);
     }
     if let Some(gain) = args.gain {
-        rtl_sdr.set_tuner_gain(gain.into()).await?;
+        builder = builder.with_gain(gain.into());
     }
-    rtl_sdr.set_sample_rate(args.samplerate).await?;
+    if let Some(buffer_count) = args.async_buffers {
+        builder = builder.with_reader_mode(ReaderMode::Async { buffer_count });
+    }
+    if args.bias_tee {
+        if !cfg!(feature = "bias-tee") {
+            return Err(eyre!(
+                "--bias-tee is unavailable, because rtl_tcp_rs was built without the bias-tee feature"
+            ));
+        }
+        builder = builder.with_bias_tee(true);
+    }
+    let index = if let Some(serial) = &args.serial {
+        DeviceFilter::new()
+            .with_serial(serial.as_str())
+            .find()?
+            .index()
+    }
+    else {
+        args.device.unwrap_or_default()
+    };
+    let rtl_sdr = builder.open(index)?;
 
     let tcp_listener = TcpListener::bind(&args.address).await?;
 
-    RtlTcpServer::from_rtl_sdr(rtl_sdr, tcp_listener)
+    if args.virtual_receivers {
+        let band = Band {
+            center_frequency: args.frequency.expect("frequency is required"),
+            sample_rate: args.samplerate,
+        };
+        let out_of_band = if args.clamp_out_of_band {
+            OutOfBandPolicy::Clamp
+        }
+        else {
+            OutOfBandPolicy::Reject
+        };
+        let handler = VirtualReceiverHandler::new(rtl_sdr, band)
+            .await?
+            .with_out_of_band_policy(out_of_band);
+        serve(handler, tcp_listener, &args).await
+    }
+    else {
+        serve(BackendHandler::new(rtl_sdr), tcp_listener, &args).await
+    }
+}
+
+async fn serve<H>(handler: H, tcp_listener: TcpListener, args: &Args) -> Result<(), Error>
+where
+    H: Handler + Send,
+    H::Error: Sync + 'static,
+    H::ConnectionHandler: Send + 'static,
+{
+    let mut policy = AccessPolicy::default().with_first_client_controls(args.first_client_controls);
+    for &block in &args.allow {
+        policy = policy.with_allow(block);
+    }
+    for &block in &args.deny {
+        policy = policy.with_deny(block);
+    }
+    if let Some(max_clients) = args.max_clients {
+        policy = policy.with_max_clients(max_clients);
+    }
+    if let Some(commands) = &args.allow_commands {
+        policy = policy.with_allowed_commands(commands.iter().copied());
+    }
+    if let Some(range) = args.frequency_range {
+        policy = policy.with_frequency_range(range.into());
+    }
+
+    let handler = AccessControlHandler::new(handler, policy);
+
+    #[cfg(feature = "tls")]
+    if let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key) {
+        let client_ca = args.client_ca.as_ref().map(tls::load_certs).transpose()?;
+        let config = tls::server_config(
+            tls::load_certs(cert)?,
+            tls::load_private_key(key)?,
+            client_ca,
+        )?;
+        let listener = TlsListener::new(tcp_listener, Arc::new(config));
+        return serve_on(handler, listener, args).await;
+    }
+
+    serve_on(handler, tcp_listener, args).await
+}
+
+async fn serve_on<H, L>(handler: H, listener: L, args: &Args) -> Result<(), Error>
+where
+    H: Handler + Send,
+    H::Error: Sync + 'static,
+    H::ConnectionHandler: Send + 'static,
+    L: Listener,
+{
+    RtlTcpServer::new(handler, listener)
+        .with_config_snapshot(args.config_snapshot)
+        .with_unknown_command_policy(args.unknown_commands)
         .serve()
         .await?;
 
@@ -88,3 +298,30 @@ impl From<Gain> for rtlsdr_async::Gain {
         }
     }
 }
+
+#[derive(Clone, Copy, Debug)]
+struct FrequencyRange {
+    min: u32,
+    max: u32,
+}
+
+impl FromStr for FrequencyRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || eyre!("Invalid frequency range: {s}");
+        let (min, max) = s.split_once('-').ok_or_else(invalid)?;
+        let min = min.parse().map_err(|_| invalid())?;
+        let max = max.parse().map_err(|_| invalid())?;
+        if min > max {
+            return Err(invalid());
+        }
+        Ok(Self { min, max })
+    }
+}
+
+impl From<FrequencyRange> for RangeInclusive<u32> {
+    fn from(value: FrequencyRange) -> Self {
+        value.min..=value.max
+    }
+}